@@ -0,0 +1,175 @@
+-- The EC ledger, materialized per user.
+--
+-- The balance of a user is defined as:
+--
+--   + the uploader's share of every purchase of one of their uploads by another user
+--   + the sum of all system transactions affecting the user
+--   - the ECs the user has spent on purchases
+--   + the refund the user gets for every purchase they have rated
+--
+-- All amounts are whole ECs; shares are rounded down per purchase.
+-- `src/db/sql/get_available_ecs.sql` calculates the same value from scratch.
+-- The cached balances below are kept up to date by triggers, so reading a balance
+-- never has to scan the purchases.
+
+CREATE TABLE IF NOT EXISTS ec_balances (
+    user_id uuid PRIMARY KEY REFERENCES users (id),
+    balance bigint NOT NULL DEFAULT 0
+);
+
+-- The part of the price that gets credited to the uploader (80%)
+CREATE OR REPLACE FUNCTION ec_uploader_share(ecs_spent smallint)
+RETURNS bigint AS $$
+    SELECT (ecs_spent::bigint * 4) / 5;
+$$ LANGUAGE sql IMMUTABLE;
+
+-- The part of the price that gets refunded to the buyer once they rate the upload (20%)
+CREATE OR REPLACE FUNCTION ec_rating_refund(ecs_spent smallint)
+RETURNS bigint AS $$
+    SELECT ecs_spent::bigint / 5;
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION ec_balance_apply(affected_user uuid, delta bigint)
+RETURNS void AS $$
+BEGIN
+    IF affected_user IS NULL OR delta = 0 THEN
+        RETURN;
+    END IF;
+
+    INSERT INTO ec_balances (user_id, balance)
+    VALUES (affected_user, delta)
+    ON CONFLICT (user_id) DO UPDATE SET balance = ec_balances.balance + EXCLUDED.balance;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Applies the effect a purchase row has on the ledger, `sign` being 1 to add it or -1 to remove it
+CREATE OR REPLACE FUNCTION ec_balance_apply_purchase(pu purchases, sign bigint)
+RETURNS void AS $$
+DECLARE
+    upload_owner uuid;
+BEGIN
+    -- The buyer pays the price, and gets a part of it back once they've rated the upload
+    PERFORM ec_balance_apply(pu.user_id, sign * -pu.ecs_spent::bigint);
+
+    IF pu.rating IS NOT NULL THEN
+        PERFORM ec_balance_apply(pu.user_id, sign * ec_rating_refund(pu.ecs_spent));
+    END IF;
+
+    -- The uploader earns their share, unless they bought their own upload
+    SELECT uploader INTO upload_owner FROM uploads WHERE id = pu.upload_id;
+
+    IF upload_owner IS DISTINCT FROM pu.user_id THEN
+        PERFORM ec_balance_apply(upload_owner, sign * ec_uploader_share(pu.ecs_spent));
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION ec_balance_on_purchase()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        PERFORM ec_balance_apply_purchase(OLD, -1);
+    END IF;
+
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        PERFORM ec_balance_apply_purchase(NEW, 1);
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER purchases_ec_balance
+AFTER INSERT OR UPDATE OR DELETE ON purchases
+FOR EACH ROW EXECUTE FUNCTION ec_balance_on_purchase();
+
+CREATE OR REPLACE FUNCTION ec_balance_on_system_transaction()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        PERFORM ec_balance_apply(OLD.affected_user, -OLD.delta_ec);
+    END IF;
+
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        PERFORM ec_balance_apply(NEW.affected_user, NEW.delta_ec);
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER system_ec_transactions_ec_balance
+AFTER INSERT OR UPDATE OR DELETE ON system_ec_transactions
+FOR EACH ROW EXECUTE FUNCTION ec_balance_on_system_transaction();
+
+-- Earnings follow the upload when its uploader changes (e.g. when it's reassigned to the nil user)
+CREATE OR REPLACE FUNCTION ec_balance_on_uploader_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM ec_balance_apply(
+        OLD.uploader,
+        -(
+            SELECT COALESCE(SUM(ec_uploader_share(pu.ecs_spent)), 0)
+            FROM purchases AS pu
+            WHERE pu.upload_id = OLD.id AND pu.user_id IS DISTINCT FROM OLD.uploader
+        )::bigint
+    );
+
+    PERFORM ec_balance_apply(
+        NEW.uploader,
+        (
+            SELECT COALESCE(SUM(ec_uploader_share(pu.ecs_spent)), 0)
+            FROM purchases AS pu
+            WHERE pu.upload_id = NEW.id AND pu.user_id IS DISTINCT FROM NEW.uploader
+        )::bigint
+    );
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER uploads_ec_balance
+AFTER UPDATE OF uploader ON uploads
+FOR EACH ROW
+WHEN (OLD.uploader IS DISTINCT FROM NEW.uploader)
+EXECUTE FUNCTION ec_balance_on_uploader_change();
+
+-- Fill the cache with the balances of the existing data
+INSERT INTO
+    ec_balances (user_id, balance)
+SELECT
+    u.id,
+    (
+        SELECT
+            COALESCE(SUM(ec_uploader_share(pu.ecs_spent)), 0)
+        FROM
+            uploads AS up
+            JOIN purchases AS pu ON pu.upload_id = up.id
+        WHERE
+            up.uploader = u.id
+            AND pu.user_id IS DISTINCT FROM up.uploader
+    ) + (
+        SELECT
+            COALESCE(SUM(systrans.delta_ec), 0)
+        FROM
+            system_ec_transactions AS systrans
+        WHERE
+            systrans.affected_user = u.id
+    ) - (
+        SELECT
+            COALESCE(SUM(pu.ecs_spent), 0)
+        FROM
+            purchases AS pu
+        WHERE
+            pu.user_id = u.id
+    ) + (
+        SELECT
+            COALESCE(SUM(ec_rating_refund(pu.ecs_spent)), 0)
+        FROM
+            purchases AS pu
+        WHERE
+            pu.user_id = u.id
+            AND pu.rating IS NOT NULL
+    )
+FROM
+    users AS u;
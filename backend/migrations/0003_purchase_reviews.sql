@@ -0,0 +1,15 @@
+-- Buyers can rate an upload (1 to 5 stars) and optionally review it in text form
+ALTER TABLE
+    purchases
+ADD
+    COLUMN review text;
+
+ALTER TABLE
+    purchases
+ADD
+    CONSTRAINT purchase_rating_range CHECK (rating BETWEEN 1 AND 5);
+
+ALTER TABLE
+    purchases
+ADD
+    CONSTRAINT purchase_review_needs_rating CHECK (review IS NULL OR rating IS NOT NULL);
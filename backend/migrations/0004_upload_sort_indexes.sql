@@ -0,0 +1,13 @@
+-- Indexes for the keyset pagination of upload listings (see `db::upload::SortBy`)
+-- Sorting by rating can't use an index, as the rating is aggregated from the purchases
+CREATE INDEX idx_upload_belongs_to_name ON uploads(belongs_to, upload_name, id);
+
+CREATE INDEX idx_upload_belongs_to_upload_date ON uploads(belongs_to, upload_date, id);
+
+CREATE INDEX idx_upload_belongs_to_date ON uploads(
+    belongs_to,
+    COALESCE(associated_date, upload_date),
+    id
+);
+
+CREATE INDEX idx_upload_upload_date ON uploads(upload_date, id);
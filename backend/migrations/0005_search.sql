@@ -0,0 +1,94 @@
+-- Full-text search over uploads, courses and profs, using German stemming
+ALTER TABLE
+    courses
+ADD
+    COLUMN search_vector tsvector GENERATED ALWAYS AS (to_tsvector('german', course_name)) STORED;
+
+ALTER TABLE
+    profs
+ADD
+    COLUMN search_vector tsvector GENERATED ALWAYS AS (to_tsvector('german', prof_name)) STORED;
+
+-- An upload can be found by its own name & description, but also by its course and prof;
+-- that's why this can't be a generated column, and triggers keep it up to date instead
+ALTER TABLE
+    uploads
+ADD
+    COLUMN search_vector tsvector NOT NULL DEFAULT '';
+
+CREATE OR REPLACE FUNCTION upload_search_vector(
+    upload_name text,
+    description text,
+    belongs_to uuid,
+    held_by uuid
+)
+RETURNS tsvector AS $$
+    SELECT
+        setweight(to_tsvector('german', upload_name), 'A')
+        || setweight(to_tsvector('german', description), 'B')
+        || setweight(COALESCE((SELECT search_vector FROM courses WHERE id = belongs_to), ''), 'C')
+        || setweight(COALESCE((SELECT search_vector FROM profs WHERE id = held_by), ''), 'C');
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION upload_search_vector_update()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.search_vector := upload_search_vector(
+        NEW.upload_name,
+        NEW.description,
+        NEW.belongs_to,
+        NEW.held_by
+    );
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Runs after `before_insert_upload` and `before_insert_upload_belongs_to`, which fill in the nil
+-- defaults, as triggers on the same event fire in alphabetical order of their names
+CREATE TRIGGER uploads_search_vector
+BEFORE INSERT OR UPDATE OF upload_name, description, belongs_to, held_by ON uploads
+FOR EACH ROW EXECUTE FUNCTION upload_search_vector_update();
+
+CREATE OR REPLACE FUNCTION course_search_vector_propagate()
+RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE uploads
+    SET search_vector = upload_search_vector(upload_name, description, belongs_to, held_by)
+    WHERE belongs_to = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER courses_search_vector
+AFTER UPDATE OF course_name ON courses
+FOR EACH ROW
+WHEN (OLD.course_name IS DISTINCT FROM NEW.course_name)
+EXECUTE FUNCTION course_search_vector_propagate();
+
+CREATE OR REPLACE FUNCTION prof_search_vector_propagate()
+RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE uploads
+    SET search_vector = upload_search_vector(upload_name, description, belongs_to, held_by)
+    WHERE held_by = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER profs_search_vector
+AFTER UPDATE OF prof_name ON profs
+FOR EACH ROW
+WHEN (OLD.prof_name IS DISTINCT FROM NEW.prof_name)
+EXECUTE FUNCTION prof_search_vector_propagate();
+
+-- Index the existing uploads
+UPDATE
+    uploads
+SET
+    search_vector = upload_search_vector(upload_name, description, belongs_to, held_by);
+
+CREATE INDEX idx_upload_search_vector ON uploads USING GIN (search_vector);
+
+CREATE INDEX idx_course_search_vector ON courses USING GIN (search_vector);
+
+CREATE INDEX idx_prof_search_vector ON profs USING GIN (search_vector);
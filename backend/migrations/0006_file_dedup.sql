@@ -0,0 +1,3 @@
+-- Files are stored by their hash, so several `files` rows can share the same blob;
+-- the blob stays around as long as any row still references it
+CREATE INDEX idx_file_sha3_256 ON files(sha3_256);
@@ -0,0 +1,24 @@
+-- Users who registered before activation existed could log in without it, so their primary
+-- addresses count as verified
+UPDATE emails
+SET "status" = 'verified'
+WHERE
+    "status" = 'unverified'
+    AND id IN (
+        SELECT
+            primary_email
+        FROM
+            users
+    );
+
+-- Outstanding tokens were stored in plain text, and can't be looked up by their hash
+DELETE FROM email_verification;
+
+-- Activation tokens are stored as SHA-256 hashes, so they are looked up by that hash;
+-- the creation date is needed to throttle how often activation mails are resent
+ALTER TABLE email_verification
+ADD COLUMN created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC');
+
+CREATE UNIQUE INDEX idx_email_verification_token ON email_verification(token);
+
+CREATE INDEX idx_email_verification_email ON email_verification(belongs_to_email);
@@ -0,0 +1,13 @@
+-- Single-use tokens for resetting a forgotten password, stored as SHA-256 hashes like activation tokens
+CREATE TABLE IF NOT EXISTS password_reset (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    token character(43) NOT NULL UNIQUE,
+    of_user uuid NOT NULL REFERENCES users (id),
+    expires_at timestamp without time zone NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX idx_password_reset_user ON password_reset(of_user);
+
+-- Enable audit for password_reset
+CREATE TRIGGER password_reset_audit AFTER INSERT OR UPDATE OR DELETE ON password_reset FOR EACH ROW EXECUTE PROCEDURE audit.if_modified_func();
@@ -0,0 +1,17 @@
+-- A TOTP secret only becomes `totp_secret` once the user has proven their authenticator works;
+-- the last used time step prevents the same code from being used twice
+ALTER TABLE users
+ADD COLUMN totp_pending_secret character varying(50),
+ADD COLUMN totp_last_step bigint;
+
+-- One-time recovery codes for when the authenticator is lost, stored as SHA-256 hashes
+CREATE TABLE IF NOT EXISTS totp_recovery_codes (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    of_user uuid NOT NULL REFERENCES users (id),
+    code_hash character(43) NOT NULL
+);
+
+CREATE INDEX idx_totp_recovery_codes_user ON totp_recovery_codes(of_user);
+
+-- Enable audit for totp_recovery_codes
+CREATE TRIGGER totp_recovery_codes_audit AFTER INSERT OR UPDATE OR DELETE ON totp_recovery_codes FOR EACH ROW EXECUTE PROCEDURE audit.if_modified_func();
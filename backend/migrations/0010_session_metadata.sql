@@ -0,0 +1,8 @@
+-- Sessions expire after a while of not being used, and after a fixed lifetime in any case
+ALTER TABLE sessions
+ADD COLUMN created_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC'),
+ADD COLUMN last_seen_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC');
+
+CREATE UNIQUE INDEX idx_sessions_token ON sessions(token);
+
+CREATE INDEX idx_sessions_user ON sessions(of_user);
@@ -0,0 +1,14 @@
+-- Failed login attempts, to temporarily lock out accounts & IP addresses which guess too many passwords;
+-- the email is stored as entered, whether or not there's an account for it
+CREATE TABLE IF NOT EXISTS failed_logins (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    email character varying(500) NOT NULL,
+    ip inet,
+    attempted_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX idx_failed_logins_email ON failed_logins(email, attempted_at);
+
+CREATE INDEX idx_failed_logins_ip ON failed_logins(ip, attempted_at);
+
+CREATE INDEX idx_failed_logins_attempted_at ON failed_logins(attempted_at);
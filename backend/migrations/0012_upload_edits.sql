@@ -0,0 +1,11 @@
+-- Moderators can edit any upload; each edit records who changed what
+CREATE TABLE IF NOT EXISTS upload_edits (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    upload_id uuid NOT NULL REFERENCES uploads (id) ON DELETE CASCADE,
+    edited_by uuid NOT NULL REFERENCES users (id),
+    edited_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC'),
+    -- The changed fields, as `{ "field": { "old": ..., "new": ... } }`
+    changes jsonb NOT NULL
+);
+
+CREATE INDEX idx_upload_edits_upload ON upload_edits(upload_id, edited_at);
@@ -0,0 +1,25 @@
+-- Moderators approve or reject new files; rejected files leave the moderation queue.
+-- Every decision is kept, together with the moderator's reason
+CREATE TYPE moderation_decision AS ENUM ('approved', 'rejected');
+
+CREATE TABLE IF NOT EXISTS file_moderations (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    file_id uuid NOT NULL REFERENCES files (id) ON DELETE CASCADE,
+    decision moderation_decision NOT NULL,
+    reason text,
+    decided_by uuid NOT NULL REFERENCES users (id),
+    decided_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX idx_file_moderations_file ON file_moderations(file_id, decided_at);
+
+ALTER TABLE
+    files
+ADD
+    COLUMN rejected boolean NOT NULL DEFAULT false;
+
+-- Files are pending until a moderator approves or rejects them
+CREATE INDEX idx_files_pending ON files(revision_at)
+WHERE
+    NOT approval_mod
+    AND NOT rejected;
@@ -0,0 +1,56 @@
+-- Admins suspend users for a while (or indefinitely) and can lift a suspension early;
+-- every suspension is kept, together with the admin's reason
+CREATE TABLE IF NOT EXISTS user_suspensions (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    of_user uuid NOT NULL REFERENCES users (id),
+    reason text NOT NULL,
+    suspended_by uuid NOT NULL REFERENCES users (id),
+    suspended_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC'),
+    -- NULL means indefinitely
+    suspended_until timestamp without time zone,
+    lifted_by uuid REFERENCES users (id),
+    lifted_at timestamp without time zone
+);
+
+CREATE INDEX idx_user_suspensions_user ON user_suspensions(of_user, suspended_at);
+
+-- Deleted accounts are kept (purchases & transactions refer to them), but can't log in anymore
+ALTER TABLE
+    users
+ADD
+    COLUMN deleted_at timestamp without time zone,
+ADD
+    COLUMN deleted_by uuid REFERENCES users (id);
+
+-- The nil user takes over the uploads of deleted accounts (see `set_nil_uploader`);
+-- its email has no address, so nobody can log in as it
+WITH nil_email AS (
+    INSERT INTO
+        emails (id, address, belongs_to_user, of_university, "status")
+    VALUES
+        (
+            '00000000-0000-0000-0000-000000000000',
+            NULL,
+            '00000000-0000-0000-0000-000000000000',
+            NULL,
+            'disabled'
+        ) ON CONFLICT DO NOTHING
+)
+INSERT INTO
+    users (
+        id,
+        first_names,
+        last_name,
+        primary_email,
+        password_hash,
+        user_role
+    )
+VALUES
+    (
+        '00000000-0000-0000-0000-000000000000',
+        'Deleted',
+        'User',
+        '00000000-0000-0000-0000-000000000000',
+        '!',
+        0
+    ) ON CONFLICT DO NOTHING;
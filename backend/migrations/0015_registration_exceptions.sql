@@ -0,0 +1,11 @@
+-- Addresses which may register even though their domain doesn't belong to a university,
+-- e.g. for guest lecturers; stored in lowercase
+CREATE TABLE IF NOT EXISTS registration_exceptions (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    address character varying(500) NOT NULL UNIQUE,
+    -- The university to associate the address with, if any
+    university uuid REFERENCES universities (id),
+    note text,
+    added_by uuid NOT NULL REFERENCES users (id),
+    added_at timestamp without time zone NOT NULL DEFAULT (now() AT TIME ZONE 'UTC')
+);
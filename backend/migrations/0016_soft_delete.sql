@@ -0,0 +1,31 @@
+-- Deleted uploads & files are only hidden, so moderators can restore them;
+-- deleting an upload also deletes its files, with the same timestamp (see `delete_upload`)
+ALTER TABLE
+    uploads
+ADD
+    COLUMN deleted_at timestamp without time zone,
+ADD
+    COLUMN deleted_by uuid REFERENCES users (id);
+
+ALTER TABLE
+    files
+ADD
+    COLUMN deleted_at timestamp without time zone,
+ADD
+    COLUMN deleted_by uuid REFERENCES users (id),
+    -- Set once the purge job has given up the blob; such files can't be restored anymore
+ADD
+    COLUMN blob_purged_at timestamp without time zone;
+
+-- The purge job looks for deleted files whose blob is still around
+CREATE INDEX idx_files_awaiting_purge ON files(sha3_256)
+WHERE
+    deleted_at IS NOT NULL
+    AND blob_purged_at IS NULL;
+
+-- Blobs are only deleted from storage once their files have been marked as purged;
+-- until that has worked, they're listed here, so the purge job can try again
+CREATE TABLE IF NOT EXISTS purged_blobs (
+    sha3_256 character varying(64) PRIMARY KEY,
+    purged_at timestamp without time zone NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Buying an upload with a negative price would mint ECs for the buyer
+UPDATE
+    uploads
+SET
+    price = 0
+WHERE
+    price < 0;
+
+ALTER TABLE
+    uploads
+ADD
+    CONSTRAINT uploads_price_nonnegative CHECK (price >= 0);
@@ -0,0 +1,47 @@
+-- Addresses are stored in lowercase, so registration, login & the taken check all compare them the same way
+UPDATE
+    emails
+SET
+    address = LOWER(address)
+WHERE
+    address <> LOWER(address);
+
+-- Of verified addresses which only differed in case, one stays verified: a primary one if any
+UPDATE
+    emails
+SET
+    STATUS = 'disabled'
+WHERE
+    id IN (
+        SELECT
+            id
+        FROM
+            (
+                SELECT
+                    id,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY address
+                        ORDER BY
+                            EXISTS (
+                                SELECT
+                                    1
+                                FROM
+                                    users
+                                WHERE
+                                    users.primary_email = emails.id
+                            ) DESC,
+                            id
+                    ) AS rank
+                FROM
+                    emails
+                WHERE
+                    STATUS = 'verified'
+            ) AS ranked
+        WHERE
+            rank > 1
+    );
+
+-- Two accounts verifying the same address at the same time can't both succeed
+CREATE UNIQUE INDEX idx_emails_verified_address ON emails(LOWER(address))
+WHERE
+    STATUS = 'verified';
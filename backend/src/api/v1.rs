@@ -1,13 +1,17 @@
 pub mod action;
 mod auth;
+pub mod client;
 mod content;
 mod course;
+mod download;
 mod ecs;
+mod emails;
 mod get;
+mod openapi;
 mod profs;
+mod totp;
 mod university;
 mod users;
-// mod university;
 
 use axum::{
     extract::{Request, State},
@@ -15,30 +19,50 @@ use axum::{
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, put},
-    Json, Router,
+    Router,
 };
 use axum_extra::extract::CookieJar;
-use serde_json::json;
+use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::db::{self, session::ValidationResult, DB_POOL};
+use crate::{
+    conf::CONF,
+    db::{self, session::ValidationResult, DB_POOL},
+    error::{ApiError, ApiResult},
+};
+
+use self::client::ClientInfo;
 
 use super::api_greeting;
 
 const SESSION_COOKIE_NAME: &str = "egiraffe_session_token";
 
+/// The response of requests which don't return anything but their success
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SuccessRes {
+    pub success: bool,
+}
+
 pub fn routes() -> Router {
     use AuthLevel::*;
 
     Router::new()
         .route("/", get(api_greeting).post(api_greeting).put(api_greeting))
+        .route("/openapi.json", get(openapi::handle_get_openapi))
         .nest(
             "/auth",
             Router::new()
                 .route("/login", put(auth::handle_login))
                 .route("/register", put(auth::handle_register))
                 .route("/logout", put(auth::handle_logout))
-                .route("/activate", put(auth::handle_activate)),
+                .route("/activate", put(auth::handle_activate))
+                .route("/resend-activation", put(auth::handle_resend_activation))
+                .route(
+                    "/request-password-reset",
+                    put(auth::handle_request_password_reset),
+                )
+                .route("/reset-password", put(auth::handle_reset_password)),
         )
         .nest(
             "/get",
@@ -46,7 +70,10 @@ pub fn routes() -> Router {
         )
         .nest(
             "/do",
-            action::routes().layer(middleware::from_fn(auth::<RegularUser>)),
+            action::routes()
+                .nest("/totp", totp::routes())
+                .nest("/emails", emails::routes())
+                .layer(middleware::from_fn(auth::<RegularUser>)),
         )
         .nest(
             "/mod",
@@ -63,7 +90,7 @@ pub fn routes() -> Router {
                 .route("/demo-admin-route", get(handle_demo_protected_route))
                 .nest("/ecs", ecs::routes())
                 .nest("/users", users::routes())
-                // .nest("/university", university::routes())
+                .nest("/university", university::routes())
                 .layer(middleware::from_fn(auth::<Admin>)),
         )
         .route(
@@ -91,8 +118,8 @@ async fn auth<const REQUIRED_AUTH_LEVEL: i16>(
     cookie_jar: CookieJar,
     mut request: Request,
     next: Next,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Response> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     assert!(
         REQUIRED_AUTH_LEVEL >= AuthLevel::Anyone && REQUIRED_AUTH_LEVEL <= AuthLevel::Admin,
@@ -101,11 +128,6 @@ async fn auth<const REQUIRED_AUTH_LEVEL: i16>(
 
     log::info!("Auth level required: {}", REQUIRED_AUTH_LEVEL);
 
-    let unauthorized = (
-        StatusCode::UNAUTHORIZED,
-        Json(json!({ "success": false, "message": "Unauthorized" })),
-    );
-
     let Some(session_cookie) = cookie_jar.get(SESSION_COOKIE_NAME) else {
         log::info!("No session cookie");
 
@@ -113,15 +135,32 @@ async fn auth<const REQUIRED_AUTH_LEVEL: i16>(
             request.extensions_mut().insert(Uuid::nil());
             return Ok(next.run(request).await);
         } else {
-            return Err(unauthorized);
+            return Err(ApiError::Unauthorized);
         }
     };
 
-    match db::session::validate_session(&mut tx, &session_cookie.value().to_string()).await {
+    let client = ClientInfo::new(request.headers(), request.extensions());
+    let validation_result =
+        db::session::validate_session(&mut tx, session_cookie.value(), &client).await?;
+
+    // Validating a session records when it was last used, and removes it if it has expired
+    tx.commit().await?;
+
+    match validation_result {
         ValidationResult::Valid {
             auth_level,
             user_id,
+            totp_enabled,
         } if auth_level >= REQUIRED_AUTH_LEVEL => {
+            // Moderators & admins may log in without TOTP, but only to set it up
+            if CONF.requiretotpformods
+                && REQUIRED_AUTH_LEVEL >= AuthLevel::Moderator
+                && !totp_enabled
+            {
+                log::info!("User {user_id} needs to enable TOTP first");
+                return Err(ApiError::TotpMandatory);
+            }
+
             request.extensions_mut().insert(user_id);
             let response = next.run(request).await;
             Ok(response)
@@ -132,9 +171,7 @@ async fn auth<const REQUIRED_AUTH_LEVEL: i16>(
         }
         ValidationResult::Invalid | ValidationResult::Valid { .. } => {
             log::info!("Invalid session");
-            Err(unauthorized)
+            Err(ApiError::Unauthorized)
         }
     }
-
-    // We don't commit the transaction because we don't need to write anything
 }
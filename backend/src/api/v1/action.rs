@@ -1,26 +1,24 @@
-use std::{io::Read, path::PathBuf};
-
+use anyhow::Context;
 use axum::{
-    extract::{Multipart, State},
+    extract::{multipart::Field, DefaultBodyLimit, Multipart, State},
     http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
     Extension, Json, Router,
 };
-use futures::{io, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sha3::{Digest, Sha3_256};
-use tempfile::{tempfile, NamedTempFile};
+use tempfile::NamedTempFile;
 use tokio::io::AsyncWriteExt;
-use tokio_util::io::StreamReader;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::api_greeting,
+    api::{api_greeting, v1::SuccessRes},
     conf::CONF,
     data::{File, Purchase, RedactedUser, Upload, UploadType},
     db::{self, user::make_pwd_hash, DB_POOL},
+    error::{ApiError, ApiResult, ErrorRes},
+    storage,
     util::bad_request,
 };
 
@@ -32,11 +30,54 @@ pub fn routes() -> Router {
         .route("/upload", put(handle_do_upload))
         // .route("/universities", put(handle_get_universities))
         .route("/me", put(handle_do_me))
-        .route("/file", put(handle_do_file))
+        .route(
+            "/file",
+            // Leave some room for the other form fields; the file itself is limited while streaming
+            put(handle_do_file).layer(DefaultBodyLimit::max(
+                usize::try_from(CONF.maxfilesize).unwrap_or(usize::MAX) + 64 * 1024,
+            )),
+        )
         .route("/purchase", put(handle_do_purchase))
+        .route("/rate", put(handle_do_rate))
+        .route("/delete-upload", put(handle_do_delete_upload))
+        .route("/delete-file", put(handle_do_delete_file))
+        .route("/revoke-session", put(handle_do_revoke_session))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RevokeSessionReq {
+    pub session_id: Uuid,
+}
+
+/// Log the current user out on another device (or this one)
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/revoke-session",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = RevokeSessionReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such session of the current user", body = ErrorRes),
+    ),
+)]
+async fn handle_do_revoke_session(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<RevokeSessionReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    // Only the user's own sessions can be revoked
+    if !db::session::revoke_session(&mut tx, current_user_id, req.session_id).await? {
+        return Err(ApiError::NotFound("No such session".into()));
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct DoUploadReq {
     /// The ID of an existing upload, or `None` if this is a new upload
     pub id: Option<Uuid>,
@@ -55,46 +96,49 @@ pub struct DoUploadReq {
     pub upload_type: UploadType,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DoUploadRes {
+    pub success: bool,
+    pub message: String,
+
+    /// The created upload, `None` if an existing upload was modified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Upload>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/upload",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DoUploadReq,
+    responses(
+        (status = 200, body = DoUploadRes),
+        (status = 400, description = "Missing required fields of a new upload", body = ErrorRes),
+        (status = 403, description = "Not the uploader", body = ErrorRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+    ),
+)]
 async fn handle_do_upload(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     Json(req): Json<DoUploadReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<DoUploadRes>> {
+    if req.price.is_some_and(|price| price < 0) {
+        return Err(bad_request("The price must not be negative"));
+    }
 
-    // log::info!("Create/alter upload for course {}", req.belongs_to.unwrap_or("default"));
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    dbg!(&req);
+    // log::info!("Create/alter upload for course {}", req.belongs_to.unwrap_or("default"));
 
     // TODO handle updating the description
     // 0. Check if a new upload is being created or an existing one is being modified
     if let Some(id) = req.id {
         // 1. Get the upload from the database
-        let maybe_upload = db::upload::get_upload_by_id(&mut tx, id).await;
-        let Ok(upload) = maybe_upload else {
-            log::error!(
-                "Failed to get upload from database: {}",
-                maybe_upload.unwrap_err()
-            );
-
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "success": false,
-                    "message": "Failed to get upload from database",
-                })),
-            );
-        };
-
-        let Some(upload) = upload else {
+        let Some(upload) = db::upload::get_upload_by_id(&mut tx, id).await? else {
             log::error!("Cannot modify: no such upload: {id}");
 
-            return (
-                StatusCode::NOT_FOUND,
-                Json(json!({
-                    "success": false,
-                    "message": "No such upload",
-                })),
-            );
+            return Err(ApiError::NotFound("No such upload".into()));
         };
 
         // 2. Check if the user is allowed to modify this upload
@@ -104,13 +148,9 @@ async fn handle_do_upload(
                 upload.uploader
             );
 
-            return (
-                StatusCode::FORBIDDEN,
-                Json(json!({
-                    "success": false,
-                    "message": "User is not allowed to modify this upload",
-                })),
-            );
+            return Err(ApiError::Forbidden(
+                "User is not allowed to modify this upload".into(),
+            ));
         }
 
         // 3. Modify the upload
@@ -139,29 +179,17 @@ async fn handle_do_upload(
         upload.last_modified_date = chrono::Utc::now().naive_utc();
 
         // 4. Update the upload in the database
-        let update_result = db::upload::update_upload(&mut tx, &upload).await;
-
-        if update_result.is_ok() {
-            log::info!("Upload updated successfully, id: {}", upload.id);
-
-            (
-                StatusCode::OK,
-                Json(json!({
-                    "success": true,
-                    "message": "Upload updated successfully",
-                })),
-            )
-        } else {
-            log::error!("Failed to update upload: {}", update_result.unwrap_err());
-
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "success": false,
-                    "message": "Failed to update upload",
-                })),
-            )
-        }
+        db::upload::update_upload(&mut tx, &upload).await?;
+
+        tx.commit().await?;
+
+        log::info!("Upload updated successfully, id: {}", upload.id);
+
+        Ok(Json(DoUploadRes {
+            success: true,
+            message: "Upload updated successfully".into(),
+            upload: None,
+        }))
     } else {
         // Case 2: new upload is being created
 
@@ -179,13 +207,7 @@ async fn handle_do_upload(
             } = req;
 
             let (Some(name), Some(price), Some(belongs_to)) = (name, price, belongs_to) else {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(json!({
-                        "success": false,
-                        "message": "Missing required fields",
-                    })),
-                );
+                return Err(bad_request("Missing required fields"));
             };
 
             let now = chrono::Utc::now().naive_utc();
@@ -206,83 +228,60 @@ async fn handle_do_upload(
         };
 
         // 2. Insert the upload into the database
-        let create_result = db::upload::create_upload(&mut tx, &upload).await;
-
-        if create_result.is_ok() {
-            log::info!("Upload created successfully, id: {}", upload.id);
-
-            tx.commit().await.unwrap();
-
-            (
-                StatusCode::OK,
-                Json(json!({
-                    "success": true,
-                    "message": "Upload created successfully",
-                    "upload": upload,
-                })),
-            )
-        } else {
-            log::error!("Failed to create upload: {}", create_result.unwrap_err());
-
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "success": false,
-                    "message": "Failed to create upload",
-                })),
-            )
-        }
+        db::upload::create_upload(&mut tx, &upload).await?;
+
+        log::info!("Upload created successfully, id: {}", upload.id);
+
+        tx.commit().await?;
+
+        Ok(Json(DoUploadRes {
+            success: true,
+            message: "Upload created successfully".into(),
+            upload: Some(upload),
+        }))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct DoMeReq {
     pub first_names: Option<String>,
     pub last_name: Option<String>,
     pub password: Option<String>,
-    // TODO handle updating the email address
+    // Email addresses are changed through `/do/emails`
 
     // TODO handle updating the TOTP secret
     // totp_secret: Option<String>,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DoMeRes {
+    pub success: bool,
+    pub message: String,
+    pub user: RedactedUser,
+}
+
 /// Handle updates to the current user's profile
 ///
 /// Also: UwU
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/me",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DoMeReq,
+    responses((status = 200, body = DoMeRes)),
+)]
 async fn handle_do_me(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     Json(req): Json<DoMeReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<DoMeRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     // 1. Get the user from the database
-    let maybe_user = db::user::get_user_by_id(&mut tx, current_user_id).await;
-    let Ok(user) = maybe_user else {
-        log::error!(
-            "Failed to get user from database: {}",
-            maybe_user.unwrap_err()
-        );
-
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get user from database",
-            })),
-        );
-    };
-
-    // TODO consider merging these `let else`s
-    let Some(mut user) = user else {
+    let Some(mut user) = db::user::get_user_by_id(&mut tx, current_user_id).await? else {
         log::error!("Cannot modify: no such user: {current_user_id}");
 
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "No such user",
-            })),
-        );
+        return Err(ApiError::NotFound("No such user".into()));
     };
 
     // 2. Modify the user
@@ -299,33 +298,18 @@ async fn handle_do_me(
     }
 
     // 3. Update the user in the database
-    let update_result = db::user::update_user(&mut tx, user.clone()).await;
+    db::user::update_user(&mut tx, user.clone()).await?;
 
-    if update_result.is_ok() {
-        log::info!("User updated successfully, id: {}", user.id);
-    } else {
-        log::error!("Failed to update user: {}", update_result.unwrap_err());
-
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to update user",
-            })),
-        );
-    }
+    log::info!("User updated successfully, id: {}", user.id);
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
     // 4. Return the updated user
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "message": "User retrieved successfully",
-            "user": RedactedUser::from(user),
-        })),
-    )
+    Ok(Json(DoMeRes {
+        success: true,
+        message: "User retrieved successfully".into(),
+        user: RedactedUser::from(user),
+    }))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -340,97 +324,99 @@ struct DoFileReq {
     mime_type: String,
 }
 
+/// The multipart form of a file upload; the fields have to be sent in this order
+#[derive(Debug, ToSchema)]
+#[allow(dead_code)] // Only describes the form for the API docs
+pub struct DoFileForm {
+    /// The ID of the upload this file belongs to
+    upload_id: Uuid,
+
+    /// The file, with its name and MIME type
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+/// Another upload which contains a file with the same content
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct ExistingUpload {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DoFileRes {
+    pub success: bool,
+    pub message: String,
+    pub file: File,
+    pub existing_in_uploads: Vec<ExistingUpload>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/file",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body(content = DoFileForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = DoFileRes),
+        (status = 400, description = "Malformed form", body = ErrorRes),
+        (status = 403, description = "Not the uploader", body = ErrorRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+        (status = 413, description = "The file exceeds the maximum size", body = ErrorRes),
+    ),
+)]
 async fn handle_do_file(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     mut multipart: Multipart,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
-
+) -> ApiResult<Json<DoFileRes>> {
     // 0. Get the form fields
     // 0.a. Get the upload ID
-    let Some(field) = multipart.next_field().await.unwrap() else {
-        return bad_request("Missing some form field");
+    let Ok(Some(field)) = multipart.next_field().await else {
+        return Err(bad_request("Missing some form field"));
     };
-    if field.name().unwrap().to_owned() != "upload_id" {
-        return bad_request("Invalid form field name, expected \"upload_id\"");
+    if field.name() != Some("upload_id") {
+        return Err(bad_request(
+            "Invalid form field name, expected \"upload_id\"",
+        ));
     }
 
-    let upload_id = Uuid::parse_str(&field.text().await.unwrap()).unwrap();
+    let Some(upload_id) = field
+        .text()
+        .await
+        .ok()
+        .and_then(|text| Uuid::parse_str(&text).ok())
+    else {
+        return Err(bad_request("Invalid upload ID"));
+    };
 
     // 0.b. Get the file name & MIME type
-    let Some(field) = multipart.next_field().await.unwrap() else {
-        return bad_request("Missing some form field");
+    let Ok(Some(field)) = multipart.next_field().await else {
+        return Err(bad_request("Missing some form field"));
     };
-    if field.name().unwrap().to_owned() != "file" {
-        return bad_request("Invalid form field name, expected \"file\"");
+    if field.name() != Some("file") {
+        return Err(bad_request("Invalid form field name, expected \"file\""));
     }
 
-    let name = field.file_name().unwrap().to_owned();
-    let mime_type = field.content_type().unwrap().to_owned();
+    let (Some(name), Some(mime_type)) = (field.file_name(), field.content_type()) else {
+        return Err(bad_request("The file needs a name and a MIME type"));
+    };
 
     let upload_req = DoFileReq {
         upload_id,
-        name,
-        mime_type,
+        name: name.to_owned(),
+        mime_type: mime_type.to_owned(),
     };
 
-    // 2. Begin reading & writing the file's contents; and generate file metadata
-    let file_id = Uuid::new_v4();
-
-    // 3. Write the file to disk & check for collisions
-    std::fs::create_dir_all(&CONF.upload_dir).unwrap();
-    // let path = PathBuf::from(&CONF.upload_dir).join(file_id.to_string());
-
-    let named_temp_file = NamedTempFile::new().unwrap();
-    let tmp_path = named_temp_file.path();
-    let fs_file = named_temp_file.reopen().unwrap();
-
-    let mut fs_file = tokio::fs::File::from_std(fs_file);
-    let body_with_io_error = field.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-    let body_reader = StreamReader::new(body_with_io_error);
-    futures::pin_mut!(body_reader);
-
-    // TODO consider moving this till after the database stuff
-    let file_writing_future = tokio::io::copy(&mut body_reader, &mut fs_file);
-
     // 1. Get the upload from the database
-    let maybe_upload = db::upload::get_upload_by_id(&mut tx, upload_req.upload_id).await;
-    let Ok(upload) = maybe_upload else {
-        log::error!(
-            "Failed to get upload from database: {}",
-            maybe_upload.unwrap_err()
-        );
-
-        // Stop writing the file to disk & delete it
-        let _ = file_writing_future.await;
-        let _ = tokio::fs::remove_file(tmp_path).await;
-
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get upload from database",
-            })),
-        );
-    };
-
-    let Some(upload) = upload else {
+    // This transaction is only short-lived, so that none is held open while the file streams in
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+    let Some(upload) = db::upload::get_upload_by_id(&mut tx, upload_req.upload_id).await? else {
         log::error!(
             "Cannot modify: no such upload: {id}",
             id = upload_req.upload_id
         );
 
-        // Stop writing the file to disk & delete it
-        let _ = file_writing_future.await;
-        let _ = tokio::fs::remove_file(tmp_path).await;
-
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "No such upload",
-            })),
-        );
+        return Err(ApiError::NotFound("No such upload".into()));
     };
 
     // 2. Check if the user is allowed to modify this upload
@@ -440,37 +426,21 @@ async fn handle_do_file(
             upload.uploader
         );
 
-        // Stop writing the file to disk & delete it
-        let _ = file_writing_future.await;
-        let _ = tokio::fs::remove_file(tmp_path).await;
-
-        return (
-            StatusCode::FORBIDDEN,
-            Json(json!({
-                "success": false,
-                "message": "User is not allowed to modify this upload",
-            })),
-        );
+        return Err(ApiError::Forbidden(
+            "User is not allowed to modify this upload".into(),
+        ));
     }
 
-    // 3. Finish writing the file to disk
-    let bytes_written = file_writing_future.await.unwrap();
+    tx.commit().await?;
 
-    // 4. Calculate the SHA3-256 hash of the file
-    // HACK we're reading the darn file back in again
-    let hsh_input = std::fs::File::open(tmp_path).unwrap();
-    // HACK this really should be mmap'd
-    let mut hasher = Sha3_256::new();
-    let data = &hsh_input.bytes().collect::<Result<Vec<u8>, _>>().unwrap();
-    hasher.update(data);
-    let sha3_256 = hex::encode(hasher.finalize());
-    dbg!(&sha3_256);
+    // 3. Stream the file to a temporary file, hashing it on the way
+    let (named_temp_file, sha3_256, bytes_written) = receive_file(field, upload.id).await?;
 
     let file = File {
-        id: file_id,
+        id: Uuid::new_v4(),
         name: upload_req.name,
         mime_type: upload_req.mime_type,
-        size: bytes_written as i64,
+        size: i64::try_from(bytes_written).context("File size out of range")?,
         upload_id: upload_req.upload_id,
         revision_at: chrono::Utc::now().naive_utc(), // FIXME update the upload's last modified date to this timestamp
         approval_uploader: true, // TODO consider making this user-configurable to allow for "draft uploads"
@@ -478,183 +448,408 @@ async fn handle_do_file(
         sha3_256,
     };
 
-    // 5. Persist the file in the database
-    let maybe_file = db::file::create_file(&mut tx, &file).await;
-    if maybe_file.is_err() {
-        log::error!("Failed to create file: {}", maybe_file.unwrap_err());
+    // 4. Store the file, and let the uploader know if the same file is already part of other uploads
+    let existing_in_uploads = persist_file(&file, named_temp_file).await?;
 
-        // Delete the file
-        let _ = tokio::fs::remove_file(tmp_path).await;
+    // 5. Respond with the file metadata
+    Ok(Json(DoFileRes {
+        success: true,
+        message: "File uploaded successfully".into(),
+        file,
+        existing_in_uploads,
+    }))
+}
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to create file",
-            })),
+/// Moves a received file to its final location and persists it in the database; returns the other
+/// uploads which contain a file with the same content
+async fn persist_file(
+    file: &File,
+    named_temp_file: NamedTempFile,
+) -> ApiResult<Vec<ExistingUpload>> {
+    // The lock keeps the purge job and uploads of the same content off the blob until we're done
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+    db::file::lock_blob(&mut tx, &file.sha3_256).await?;
+
+    // The upload may have been deleted while the file was streaming in
+    if db::upload::get_upload_by_id(&mut tx, file.upload_id)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound("No such upload".into()));
+    }
+
+    let is_new_blob = storage::blob_store()
+        .put(&file.sha3_256, named_temp_file)
+        .await
+        .with_context(|| format!("Failed to store file {}", file.id))?;
+
+    if !is_new_blob {
+        // Files are stored by their hash, so the existing blob has exactly the same content;
+        // the new database entry simply references it
+        log::info!(
+            "File {} reuses the existing blob {}",
+            file.id,
+            file.sha3_256
         );
+    }
+
+    let persisted = async {
+        db::file::create_file(&mut tx, file).await?;
+        let duplicates = db::file::get_files_with_same_content(&mut tx, file.id).await?;
+        tx.commit().await?;
+
+        anyhow::Ok(duplicates)
+    }
+    .await;
+
+    let duplicates = match persisted {
+        Ok(duplicates) => duplicates,
+        Err(err) => {
+            if is_new_blob {
+                discard_unreferenced_blob(&file.sha3_256).await;
+            }
+
+            return Err(err.into());
+        }
     };
 
-    // 6. Move the file to the correct location (from the temporary location)
-    // let path = PathBuf::from(&CONF.upload_dir).join(file_id.to_string());
+    let mut existing_in_uploads: Vec<_> = duplicates
+        .into_iter()
+        .map(|(_, upload)| ExistingUpload {
+            id: upload.id,
+            name: upload.name,
+        })
+        .collect();
+    existing_in_uploads.dedup(); // Duplicates are ordered by upload, so one entry per upload remains
 
-    // To reduce the number of inodes in one directory, we can create a subdirectory for each byte of the hash
-    let target_folder = PathBuf::from(&CONF.upload_dir).join(&file.sha3_256[..2]);
-    std::fs::create_dir_all(&target_folder).unwrap();
+    Ok(existing_in_uploads)
+}
 
-    let path = target_folder.join(&file.sha3_256);
+/// Streams a file to a temporary file, hashing it on the way
+///
+/// Files are stored by their hash, which is only known once the whole file has been received.
+/// Returns the temporary file, the hash and the size of the file.
+async fn receive_file(
+    mut field: Field<'_>,
+    upload_id: Uuid,
+) -> ApiResult<(NamedTempFile, String, u64)> {
+    let named_temp_file = storage::blob_store()
+        .staging_file()
+        .context("Failed to create temporary file")?;
+
+    let mut fs_file = tokio::fs::File::from_std(
+        named_temp_file
+            .reopen()
+            .context("Failed to open temporary file")?,
+    );
+    let mut hasher = Sha3_256::new();
+    let mut bytes_written: u64 = 0;
+
+    // The temporary file gets deleted when `named_temp_file` is dropped on an early return
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            // The request body exceeded the limit set on the route
+            Err(err) if err.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return Err(file_too_large(upload_id));
+            }
+            Err(err) => {
+                log::info!("Failed to receive file: {err}");
+                return Err(bad_request("Failed to receive file"));
+            }
+        };
+
+        bytes_written += chunk.len() as u64;
+        if bytes_written > CONF.maxfilesize {
+            return Err(file_too_large(upload_id));
+        }
 
-    if path.exists() {
-        // TODO we need to clean up the database entry; it's preferable to use a tx and commit at the end of this function
-        return bad_request("File already exists; please try again");
+        hasher.update(&chunk);
+
+        fs_file
+            .write_all(&chunk)
+            .await
+            .context("Failed to write file")?;
     }
 
-    // HACK just write it again ffs (`data` into `path`)
-    let mut file_yet_again = tokio::fs::File::create(&path).await.unwrap();
-    file_yet_again.write_all(data).await.unwrap();
-
-    // dbg!("Moving file", &path, &tmp_path);
-    // std::fs::rename(tmp_path, &path).unwrap();
-    // dbg!("File moved");
-    // TODO consider doing a custom tmp location on the same block device/volume
-    // named_temp_file.persist(&path).unwrap(); // We can't do that as it's cross-device
-    // dbg!("File persisted");
-
-    tx.commit().await.unwrap();
-
-    // 7. Respond with the file metadata
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "message": "File uploaded successfully",
-            "file": file,
-        })),
-    )
+    fs_file.sync_all().await.context("Failed to write file")?;
+
+    Ok((
+        named_temp_file,
+        hex::encode(hasher.finalize()),
+        bytes_written,
+    ))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DoPurchaseReq {
+/// Deletes a blob which was stored for a file that then couldn't be persisted
+///
+/// An upload of the same content may have picked up the blob in the meantime, so it's only deleted
+/// if no file references it. Failing that, the blob is merely left behind, which is harmless.
+async fn discard_unreferenced_blob(sha3_256: &str) {
+    let discarded = async {
+        let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+        db::file::lock_blob(&mut tx, sha3_256).await?;
+
+        if db::file::is_blob_referenced(&mut tx, sha3_256).await? {
+            return anyhow::Ok(());
+        }
+
+        storage::blob_store().delete(sha3_256).await?;
+        tx.commit().await?;
+
+        anyhow::Ok(())
+    }
+    .await;
+
+    if let Err(err) = discarded {
+        log::error!("Failed to discard unreferenced blob {sha3_256}: {err:#}");
+    }
+}
+
+fn file_too_large(upload_id: Uuid) -> ApiError {
+    log::info!(
+        "Rejecting file for upload {upload_id}: larger than {} bytes",
+        CONF.maxfilesize
+    );
+
+    ApiError::FileTooLarge {
+        max_size: CONF.maxfilesize,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DoPurchaseReq {
     /// The ID of the upload this file belongs to
     upload_id: Uuid,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DoPurchaseRes {
+    pub success: bool,
+    pub message: String,
+    pub purchase: Purchase,
+    pub upload: Upload,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/purchase",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DoPurchaseReq,
+    responses(
+        (status = 200, body = DoPurchaseRes),
+        (status = 400, description = "Already purchased", body = ErrorRes),
+        (status = 402, description = "Not enough ECs", body = ErrorRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+    ),
+)]
 async fn handle_do_purchase(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     Json(req): Json<DoPurchaseReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<DoPurchaseRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     // 1. Get the upload from the database
-    let maybe_upload = db::upload::get_upload_by_id(&mut tx, req.upload_id).await;
-    let Ok(upload) = maybe_upload else {
-        log::error!(
-            "Failed to get upload from database: {}",
-            maybe_upload.unwrap_err()
-        );
+    let Some(upload) = db::upload::get_upload_by_id(&mut tx, req.upload_id).await? else {
+        log::error!("Cannot purchase: no such upload: {id}", id = req.upload_id);
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get upload from database",
-            })),
-        );
+        return Err(ApiError::NotFound("No such upload".into()));
     };
 
-    let Some(upload) = upload else {
-        log::error!("Cannot purchase: no such upload: {id}", id = req.upload_id);
+    // 2. Purchase the upload, checking & updating the balances atomically
+    let purchase = db::purchase::purchase_upload(&mut tx, current_user_id, &upload)
+        .await
+        .inspect_err(|err| {
+            log::info!(
+                "Cannot purchase upload ({}) for user ({current_user_id}): {err:?}",
+                upload.id
+            );
+        })?;
+
+    // 3. Persist the purchase and both balance changes at once
+    tx.commit().await?;
+
+    Ok(Json(DoPurchaseRes {
+        success: true,
+        message: "Purchase successful".into(),
+        purchase,
+        upload,
+    }))
+}
+
+/// The longest review we accept, in characters
+const MAX_REVIEW_LENGTH: usize = 5000;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DoRateReq {
+    /// The ID of the purchased upload to rate
+    upload_id: Uuid,
+
+    /// The rating, from 1 to 5 stars
+    rating: i16,
 
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "No such upload",
-            })),
+    /// An optional review in text form
+    review: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DoRateRes {
+    pub success: bool,
+    pub message: String,
+    pub purchase: Purchase,
+}
+
+/// Handles buyers rating (and optionally reviewing) an upload they purchased
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/rate",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DoRateReq,
+    responses(
+        (status = 200, body = DoRateRes),
+        (status = 400, description = "Invalid rating, or the review is too long", body = ErrorRes),
+        (status = 403, description = "Upload not purchased", body = ErrorRes),
+    ),
+)]
+async fn handle_do_rate(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<DoRateReq>,
+) -> ApiResult<Json<DoRateRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    if !(1..=5).contains(&req.rating) {
+        return Err(bad_request("The rating must be between 1 and 5"));
+    }
+
+    // Treat an empty review like no review at all
+    let review = req
+        .review
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+
+    if review.is_some_and(|r| r.chars().count() > MAX_REVIEW_LENGTH) {
+        return Err(bad_request("The review is too long"));
+    }
+
+    let purchase =
+        db::purchase::rate_purchase(&mut tx, current_user_id, req.upload_id, req.rating, review)
+            .await?;
+
+    let Some(purchase) = purchase else {
+        log::info!(
+            "Cannot rate: user ({current_user_id}) has not purchased upload ({})",
+            req.upload_id
         );
+
+        return Err(ApiError::Forbidden("Only buyers can rate an upload".into()));
     };
 
-    // // 2. Get the current user from the database
-    // let maybe_user = db::user::get_user_by_id(&mut tx, current_user_id).await;
-    // let Ok(Some(user)) = maybe_user else {
-    //     log::error!(
-    //         "Failed to get user from database: {}",
-    //         maybe_user.unwrap_err()
-    //     );
-    //
-    //     return (
-    //         StatusCode::INTERNAL_SERVER_ERROR,
-    //         Json(json!({
-    //             "success": false,
-    //             "message": "Failed to get user from database",
-    //         })),
-    //     );
-    // };
-
-    // 3. Check if the user has already purchased this upload
-    let maybe_purchase = db::purchase::get_purchase(&mut tx, current_user_id, req.upload_id).await;
-    let Ok(purchase) = maybe_purchase else {
-        log::error!(
-            "Failed to get purchase from database: {}",
-            maybe_purchase.unwrap_err()
-        );
+    tx.commit().await?;
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get purchase from database",
-            })),
-        );
+    Ok(Json(DoRateRes {
+        success: true,
+        message: "Rating saved".into(),
+        purchase,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteUploadReq {
+    pub upload_id: Uuid,
+}
+
+/// Handles uploaders retracting one of their uploads, together with its files
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/delete-upload",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DeleteUploadReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 403, description = "Not the uploader", body = ErrorRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+    ),
+)]
+async fn handle_do_delete_upload(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<DeleteUploadReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let Some(upload) = db::upload::get_upload_by_id(&mut tx, req.upload_id).await? else {
+        return Err(ApiError::NotFound("No such upload".into()));
     };
 
-    if purchase.is_some() {
-        log::error!(
-            "Cannot purchase: user ({current_user_id}) has already purchased this upload ({})",
+    if upload.uploader != current_user_id {
+        log::info!(
+            "Cannot delete: user ({current_user_id}) is not the uploader ({})",
             upload.uploader
         );
 
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({
-                "success": false,
-                "message": "User has already purchased this upload",
-            })),
-        );
+        return Err(ApiError::Forbidden(
+            "Only the uploader can delete this upload".into(),
+        ));
     }
 
-    // TODO 4. Check if the user has enough ECS to purchase this upload
+    if !db::deletion::delete_upload(&mut tx, upload.id, current_user_id).await? {
+        return Err(ApiError::NotFound("No such upload".into()));
+    }
 
-    // 5. Create the purchase
-    let purchase = Purchase {
-        user_id: current_user_id,
-        upload_id: req.upload_id,
-        ecs_spent: upload.price,
-        purchase_date: chrono::Utc::now().naive_utc().clone(),
-        rating: None,
-    };
+    tx.commit().await?;
 
-    // 6. Persist the purchase in the database
-    let create_result = db::purchase::create_purchase(&mut tx, &purchase).await;
-    if create_result.is_err() {
-        log::error!("Failed to create purchase: {}", create_result.unwrap_err());
-
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to create purchase",
-            })),
-        );
+    log::info!("User {current_user_id} deleted upload {}", upload.id);
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteFileReq {
+    pub file_id: Uuid,
+}
+
+/// Handles uploaders retracting a single file of one of their uploads
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/delete-file",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = DeleteFileReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 403, description = "Not the uploader", body = ErrorRes),
+        (status = 404, description = "No such file", body = ErrorRes),
+    ),
+)]
+async fn handle_do_delete_file(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<DeleteFileReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let Some(uploader) = db::deletion::get_uploader_of_file(&mut tx, req.file_id).await? else {
+        return Err(ApiError::NotFound("No such file".into()));
     };
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "message": "Purchase successful",
-            "purchase": purchase,
-            "upload": upload,
-        })),
-    )
+    if uploader != current_user_id {
+        log::info!("Cannot delete: user ({current_user_id}) is not the uploader ({uploader})");
+
+        return Err(ApiError::Forbidden(
+            "Only the uploader can delete this file".into(),
+        ));
+    }
+
+    if !db::deletion::delete_file(&mut tx, req.file_id, current_user_id).await? {
+        return Err(ApiError::NotFound("No such file".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!("User {current_user_id} deleted file {}", req.file_id);
+
+    Ok(Json(SuccessRes { success: true }))
 }
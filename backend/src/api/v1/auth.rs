@@ -1,36 +1,41 @@
 use std::sync::Arc;
 
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, Json};
 use axum_extra::extract::{cookie::Cookie, CookieJar};
-use tokio::task;
+use once_cell::sync::Lazy;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sha_crypt::sha512_check; //For legacy logins
+use sqlx::PgTransaction;
 use time::Duration;
+use tokio::task;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::v1::{AuthLevel, SESSION_COOKIE_NAME},
-    data::UserWithEmails,
-    db::{self, DB_POOL},
-    mail::send_activation_mail,
+    api::v1::{client::ClientInfo, totp, AuthLevel, SESSION_COOKIE_NAME},
+    conf::CONF,
+    data::{User, UserWithEmails},
+    db::{self, activation::ActivationError, password_reset::PasswordResetError, DB_POOL},
+    error::{ApiError, ApiResult, ErrorRes},
+    mail::{send_activation_mail, send_password_reset_mail},
 };
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct LoginReq {
     pub email: String,
     pub password: String,
     pub totp: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct LoginRes {
     pub success: bool,
     pub email: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct RegisterReq {
     pub first_names: String,
     pub last_name: String,
@@ -39,92 +44,225 @@ pub struct RegisterReq {
     pub nick: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct ActivationReq {
     pub token: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ResendActivationReq {
+    pub email: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ActivationRes {
+    pub success: bool,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct RequestPasswordResetReq {
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ResetPasswordReq {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PasswordResetRes {
+    pub success: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
 pub struct RegisterRes {
     pub success: bool,
     // pub email: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct LogoutRes {
     pub success: bool,
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    request_body = LoginReq,
+    responses(
+        (status = 200, description = "Logged in; sets the session cookie", body = LoginRes),
+        (status = 400, description = "Wrong email or password, or wrong two-factor code", body = ErrorRes),
+        (status = 401, description = "A two-factor code is required", body = ErrorRes),
+        (status = 403, description = "Email not verified", body = ErrorRes),
+        (status = 429, description = "Too many failed attempts", body = ErrorRes),
+    ),
+)]
 pub async fn handle_login(
     cookie_jar: CookieJar,
+    client: ClientInfo,
     Json(login_data): Json<LoginReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
-
-    let Some(user) = db::user::get_active_user_by_email(&mut tx, &login_data.email).await else {
-        log::info!("Login failed: no user with email {}", login_data.email);
-        return (
-            StatusCode::BAD_REQUEST,
-            cookie_jar,
-            Json(LoginRes {
-                success: false,
-                email: login_data.email,
-            }),
-        );
-    };
+) -> ApiResult<(CookieJar, Json<LoginRes>)> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    // FIXME timing side channel
+    // Refuse logins for accounts & IP addresses with too many recent failed attempts
+    if db::login_throttle::is_locked_out(&mut tx, &login_data.email, client.ip).await? {
+        log::info!("Login locked out for email {}", login_data.email);
+        return Err(ApiError::LoginLockedOut);
+    }
 
-    let password_valid = if user.password_hash.starts_with("$6$") {
-        //Hash from old Egiraffe
-        sha512_check(&(login_data.password), &user.password_hash).is_ok()
-        //TODO: Update Hash in DB
-    } else {
-        let argon2 = Argon2::default();
-        let password_hash = PasswordHash::new(&user.password_hash).unwrap();
-        argon2
-            .verify_password(login_data.password.as_bytes(), &password_hash)
-            .is_ok()
-    };
+    let user = db::user::get_active_user_by_email(&mut tx, &login_data.email).await;
+
+    // Check unknown emails against a dummy hash, so they take as long as wrong passwords
+    let password_hash = user
+        .as_ref()
+        .map_or(DUMMY_PASSWORD_HASH.as_str(), |user| &user.password_hash);
+    let password_valid = verify_password(&login_data.password, password_hash);
 
-    if !password_valid {
+    let (Some(user), true) = (user, password_valid) else {
         log::info!(
-            "Login failed: wrong password for email {}",
+            "Login failed: wrong email or password for email {}",
             login_data.email
         );
-        return (
-            StatusCode::BAD_REQUEST,
-            cookie_jar,
-            Json(LoginRes {
-                success: false,
-                email: login_data.email,
-            }),
+        return Err(
+            reject_login(tx, &login_data.email, &client, ApiError::InvalidCredentials).await,
         );
+    };
+
+    upgrade_legacy_hash(&mut tx, &user, &login_data.password).await?;
+
+    // Only tell users their account isn't activated yet if they know the password
+    if !db::activation::is_primary_email_verified(&mut tx, user.id).await? {
+        log::info!("Login failed: email {} is not verified", login_data.email);
+        return Err(ApiError::EmailNotVerified);
     }
 
-    let token = db::session::create_session(&mut tx, user.id).await;
+    if let Some(suspension) = db::suspension::get_active_suspension(&mut tx, user.id).await? {
+        log::info!("Login failed: user {} is suspended", user.id);
+        return Err(ApiError::AccountSuspended {
+            reason: suspension.reason,
+            until: suspension.suspended_until,
+        });
+    }
+
+    // With TOTP enabled, logging in takes a second step with a code from the authenticator
+    if let Some(totp_secret) = &user.totp_secret {
+        let Some(code) = &login_data.totp else {
+            log::info!("Login needs a TOTP code for email {}", login_data.email);
+            return Err(ApiError::TotpRequired);
+        };
+
+        if !totp::verify_second_factor(&mut tx, user.id, totp_secret, code).await? {
+            log::info!(
+                "Login failed: wrong TOTP code for email {}",
+                login_data.email
+            );
+            return Err(
+                reject_login(tx, &login_data.email, &client, ApiError::InvalidTotpCode).await,
+            );
+        }
+    }
+
+    if let Err(e) = db::login_throttle::clear_failed_logins(&mut tx, &login_data.email).await {
+        log::error!("Failed to clear failed logins: {e:#}");
+    }
+
+    let token = db::session::create_session(&mut tx, user.id, &client).await?;
 
     log::info!("Login successful for email {}", login_data.email);
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
     let mut session_cookie = Cookie::new(SESSION_COOKIE_NAME, token);
-    session_cookie.make_permanent();
+    // The session expires on the server by then anyway
+    session_cookie.set_max_age(Some(Duration::days(CONF.sessionmaxage.into())));
     session_cookie.set_http_only(true);
     session_cookie.set_path("/");
 
-    (
-        StatusCode::OK,
+    Ok((
         cookie_jar.add(session_cookie), // Good that this forces a new session in case a session already exists :) Otherwise we would have a session fixation vulnerability.
         Json(LoginRes {
             success: true,
             email: login_data.email,
         }),
-    )
+    ))
+}
+
+/// Now that we know the password, replace the old Egiraffe's hash with an Argon2 one;
+/// this only sticks if the login succeeds, otherwise it's retried on the next attempt
+async fn upgrade_legacy_hash(
+    tx: &mut PgTransaction<'_>,
+    user: &User,
+    password: &str,
+) -> anyhow::Result<()> {
+    if !user.password_hash.starts_with("$6$") {
+        return Ok(());
+    }
+
+    db::user::set_password(tx, user.id, password).await?;
+    log::info!("Upgraded legacy password hash of user {}", user.id);
+
+    Ok(())
 }
 
-pub async fn handle_register(Json(register_data): Json<RegisterReq>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+/// A hash of a random password, made like the real ones, to check against when there's no such user
+static DUMMY_PASSWORD_HASH: Lazy<String> =
+    Lazy::new(|| db::user::make_pwd_hash(&Uuid::new_v4().to_string()));
+
+/// Checks a password against an Argon2 hash, or a SHA-512 crypt hash from the old Egiraffe
+pub(super) fn verify_password(password: &str, password_hash: &str) -> bool {
+    if password_hash.starts_with("$6$") {
+        //Hash from old Egiraffe
+        sha512_check(password, password_hash).is_ok()
+    } else {
+        let password_hash = match PasswordHash::new(password_hash) {
+            Ok(password_hash) => password_hash,
+            Err(e) => {
+                // Nobody can log in with a broken hash, but that's no reason to crash
+                log::error!("Stored password hash can't be parsed: {e}");
+                return false;
+            }
+        };
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok()
+    }
+}
+
+/// Counts a wrong password (or two-factor code) towards the lockout, then fails with the given error.
+///
+/// The attempt is recorded even though the login fails, so the transaction is committed.
+pub(super) async fn reject_login(
+    mut tx: PgTransaction<'_>,
+    email: &str,
+    client: &ClientInfo,
+    error: ApiError,
+) -> ApiError {
+    let recorded = db::login_throttle::record_failed_login(&mut tx, email, client.ip).await;
+
+    match recorded.and(tx.commit().await.map_err(Into::into)) {
+        Ok(()) => error,
+        Err(e) => e.context("Failed to record failed login").into(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/register",
+    tag = "auth",
+    request_body = RegisterReq,
+    responses(
+        (status = 200, description = "Registered; an activation mail has been sent", body = RegisterRes),
+        (status = 400, description = "Invalid email address, or one which doesn't belong to a university", body = ErrorRes),
+        (status = 409, description = "Email address already taken", body = ErrorRes),
+    ),
+)]
+pub async fn handle_register(
+    Json(register_data): Json<RegisterReq>,
+) -> ApiResult<Json<RegisterRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Register attempt for email {}", register_data.email);
 
@@ -140,12 +278,11 @@ pub async fn handle_register(Json(register_data): Json<RegisterReq>) -> impl Int
     // * reasonable size (no DoS)
     // * that they don't contain possibly malicious characters
 
-    // HACK the call to `unwrap` must be replaced with an error handling mechanism (return a 500 error)
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = &Argon2::default();
     let password_hash: Arc<str> = argon2
         .hash_password(password.as_bytes(), &salt) // Allocates twice (once for the `String`)
-        .unwrap()
+        .map_err(|e| ApiError::Internal(anyhow::anyhow!("Failed to hash password: {e}")))?
         .serialize()
         .as_str()
         .into(); // Allocates once (for the `Arc`) // TODO attempt to avoid this allocation
@@ -164,42 +301,238 @@ pub async fn handle_register(Json(register_data): Json<RegisterReq>) -> impl Int
     };
 
     // TODO: I didn't manage yet to get the register()-function to work only with a reference
-    let registration_result = db::user::register(&mut tx, user.clone()).await;
-    tx.commit().await.unwrap();
+    let email_id = db::user::register(&mut tx, user.clone())
+        .await
+        .inspect_err(|e| log::info!("Registration failed: {e:?}"))?;
+
+    log::info!("Registration successful");
+
+    let token = db::activation::create_activation_token(&mut tx, email_id).await?;
+
+    tx.commit().await?;
 
-    match registration_result {
-        Ok(_) => {
-            log::info!("Registration successful");
+    if let Err(e) =
+        send_activation_mail(&user.first_names, &user.last_name, &user.emails[0], &token).await
+    {
+        // The user can ask for the mail to be sent again
+        log::error!("Failed to send activation mail: {e:?}");
+    }
+
+    Ok(Json(RegisterRes { success: true }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/activate",
+    tag = "auth",
+    request_body = ActivationReq,
+    responses(
+        (status = 200, body = ActivationRes),
+        (status = 400, description = "Invalid or expired token", body = ErrorRes),
+        (status = 409, description = "Another account has verified the address already", body = ErrorRes),
+    ),
+)]
+pub async fn handle_activate(
+    Json(activation_data): Json<ActivationReq>,
+) -> ApiResult<Json<ActivationRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    match db::activation::activate_email(&mut tx, &activation_data.token).await {
+        Ok(email_id) => log::info!("Activated email {email_id}"),
+        Err(ActivationError::TokenExpired) => {
+            // The token is used up either way, expired ones are of no use anymore
+            tx.commit().await?;
+            return Err(ApiError::TokenExpired);
         }
-        Err(e) => {
-            log::error!("Registration failed: {:?}", e);
+        Err(e) => return Err(e.into()),
+    }
 
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(RegisterRes { success: false }),
-            );
+    tx.commit().await?;
+
+    Ok(Json(ActivationRes { success: true }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/resend-activation",
+    tag = "auth",
+    request_body = ResendActivationReq,
+    responses((
+        status = 200,
+        description = "Also returned if there is no unverified account with this email",
+        body = ActivationRes,
+    )),
+)]
+pub async fn handle_resend_activation(
+    Json(resend_data): Json<ResendActivationReq>,
+) -> ApiResult<Json<ActivationRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let unverified_email =
+        db::activation::get_unverified_email(&mut tx, &resend_data.email).await?;
+
+    // Unknown & already verified addresses get the same answer, so this can't be used to find accounts
+    let Some(unverified_email) = unverified_email else {
+        log::info!(
+            "Not resending activation mail to {}: no unverified account",
+            resend_data.email
+        );
+        return Ok(Json(ActivationRes { success: true }));
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    if unverified_email
+        .last_token_created_at
+        .is_some_and(|created_at| now - created_at < db::activation::RESEND_COOLDOWN)
+    {
+        log::info!(
+            "Not resending activation mail to {}: too soon",
+            resend_data.email
+        );
+        return Err(ApiError::TooManyRequests(
+            "Please wait a few minutes before requesting another mail".into(),
+        ));
+    }
+
+    let token = db::activation::create_activation_token(&mut tx, unverified_email.id).await?;
+
+    tx.commit().await?;
+
+    if let Err(e) = send_activation_mail(
+        &unverified_email.first_names,
+        &unverified_email.last_name,
+        &unverified_email.address,
+        &token,
+    )
+    .await
+    {
+        log::error!("Failed to send activation mail: {e:?}");
+    }
+
+    Ok(Json(ActivationRes { success: true }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/request-password-reset",
+    tag = "auth",
+    request_body = RequestPasswordResetReq,
+    responses((
+        status = 200,
+        description = "Also returned if there is no account with this email",
+        body = PasswordResetRes,
+    )),
+)]
+pub async fn handle_request_password_reset(
+    Json(reset_data): Json<RequestPasswordResetReq>,
+) -> ApiResult<Json<PasswordResetRes>> {
+    // Whatever happens, the answer is the same, and it's sent before any work is done; so neither
+    // the answer nor how long it takes can be used to find out who has an account
+    task::spawn(async move {
+        if let Err(e) = request_password_reset(&reset_data.email).await {
+            log::error!("Failed to handle password reset request: {e:#}");
         }
+    });
+
+    Ok(Json(PasswordResetRes { success: true }))
+}
+
+/// Sends a password reset mail to the user with this email, if there is one
+async fn request_password_reset(email: &str) -> anyhow::Result<()> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let Some(user) = db::user::get_active_user_by_email(&mut tx, email).await else {
+        log::info!("Password reset requested for unknown email {email}");
+        return Ok(());
+    };
+
+    let last_request = db::password_reset::get_last_reset_request(&mut tx, user.id).await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    if last_request.is_some_and(|created_at| now - created_at < db::password_reset::RESEND_COOLDOWN)
+    {
+        log::info!("Not sending another password reset mail to {email}: too soon");
+        return Ok(());
     }
 
-    let mail_result = send_activation_mail(&user.first_names, &user.last_name, &user.emails[0], "TODO: Real token").await;
+    let token = db::password_reset::create_reset_token(&mut tx, user.id).await?;
+
+    tx.commit().await?;
+
+    if let Err(e) = send_password_reset_mail(
+        user.first_names.as_deref().unwrap_or_default(),
+        user.last_name.as_deref().unwrap_or_default(),
+        email,
+        &token,
+        db::password_reset::RESET_TOKEN_VALIDITY.num_minutes(),
+    )
+    .await
+    {
+        log::error!("Failed to send password reset mail: {e:?}");
+    }
 
-    (StatusCode::OK, Json(RegisterRes { success: true }))
+    Ok(())
 }
-pub async fn handle_activate(Json(activation_data): Json<ActivationReq>) -> impl IntoResponse {
-    //TODO
-    return (StatusCode::OK, Json(RegisterRes { success: true }));
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/reset-password",
+    tag = "auth",
+    request_body = ResetPasswordReq,
+    responses(
+        (status = 200, description = "Password changed; all sessions are logged out", body = PasswordResetRes),
+        (status = 400, description = "Empty password, or invalid or expired token", body = ErrorRes),
+    ),
+)]
+pub async fn handle_reset_password(
+    cookie_jar: CookieJar,
+    Json(reset_data): Json<ResetPasswordReq>,
+) -> ApiResult<(CookieJar, Json<PasswordResetRes>)> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    // Checked first, so the token isn't used up by a request that can't succeed
+    if reset_data.password.is_empty() {
+        return Err(ApiError::BadRequest("Password must not be empty".into()));
+    }
+
+    let user_id = match db::password_reset::redeem_reset_token(&mut tx, &reset_data.token).await {
+        Ok(user_id) => user_id,
+        Err(PasswordResetError::TokenExpired) => {
+            // The token is used up either way, expired ones are of no use anymore
+            tx.commit().await?;
+            return Err(ApiError::TokenExpired);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    db::user::set_password(&mut tx, user_id, &reset_data.password).await?;
+    // Whoever knew the old password must not stay logged in
+    db::session::delete_sessions_of_user(&mut tx, user_id).await?;
+    db::password_reset::delete_reset_tokens_of_user(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Password of user {user_id} has been reset");
+
+    Ok((
+        cookie_jar.add(make_dead_cookie()),
+        Json(PasswordResetRes { success: true }),
+    ))
 }
 
-pub async fn handle_logout(cookie_jar: CookieJar) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/logout",
+    tag = "auth",
+    responses((status = 200, description = "Logged out; removes the session cookie", body = LogoutRes)),
+)]
+pub async fn handle_logout(cookie_jar: CookieJar) -> ApiResult<(CookieJar, Json<LogoutRes>)> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     let Some(cookie) = cookie_jar.get(SESSION_COOKIE_NAME) else {
         log::info!("Logout failed: no session cookie");
-        return (
-            StatusCode::OK, // We consider logouts to be idempotent
-            cookie_jar,
-            Json(LogoutRes { success: true }), // Thus we return success
-        );
+        // We consider logouts to be idempotent, thus we return success
+        return Ok((cookie_jar, Json(LogoutRes { success: true })));
     };
 
     let db_session_deletion_status = db::session::delete_session(&mut tx, cookie.value()).await;
@@ -208,17 +541,16 @@ pub async fn handle_logout(cookie_jar: CookieJar) -> impl IntoResponse {
         log::error!("Failed to delete session from database");
     }
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
     log::info!("Logout");
 
     let dead_cookie = make_dead_cookie();
 
-    (
-        StatusCode::OK,
+    Ok((
         cookie_jar.add(dead_cookie),
         Json(LogoutRes { success: true }),
-    )
+    ))
 }
 
 // TODO consider turning this into a static constant to clone from
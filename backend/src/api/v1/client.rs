@@ -0,0 +1,62 @@
+//! Information about the client a request comes from
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, Extensions, HeaderMap},
+};
+
+use crate::conf::CONF;
+
+/// The user agent & IP address of a client, as far as they are known
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl ClientInfo {
+    pub fn new(headers: &HeaderMap, extensions: &Extensions) -> Self {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        // Behind a reverse proxy, every connection comes from the proxy;
+        // it appends the actual client's address to `X-Forwarded-For`, so the last entry is the one to trust
+        let forwarded_ip = || {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()
+                .and_then(|ip| ip.trim().parse().ok())
+        };
+        let peer_ip = || {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        };
+
+        let ip = if CONF.webserver.behindproxy {
+            forwarded_ip().or_else(peer_ip)
+        } else {
+            peer_ip()
+        };
+
+        Self { user_agent, ip }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::new(&parts.headers, &parts.extensions))
+    }
+}
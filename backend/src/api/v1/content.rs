@@ -1,40 +1,68 @@
 //! Content moderation API endpoints
 
-use std::path::PathBuf;
-
 use anyhow::Context;
 use axum::{
-    body::Body,
     extract::State,
-    http::{header, StatusCode},
-    response::IntoResponse,
+    http::HeaderMap,
+    response::Response,
     routing::{get, put},
     Extension, Json, Router,
 };
 use chrono::NaiveDateTime;
-use serde::Deserialize;
-use serde_json::json;
-use tokio_util::io::ReaderStream;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::api_greeting,
-    conf::CONF,
-    data::File,
-    db::{self, DB_POOL},
+    api::{
+        api_greeting,
+        v1::{
+            action::{DeleteFileReq, DeleteUploadReq},
+            get::UploadsRes,
+            SuccessRes,
+        },
+    },
+    data::{File, Upload, UploadType},
+    db::{
+        self,
+        deletion::{DeletedFile, DeletedUpload},
+        moderation::{ModerationDecision, PendingFile},
+        upload::UploadEdit,
+        DB_POOL,
+    },
+    error::{ApiError, ApiResult, ErrorRes},
+    mail,
+    util::{bad_request, double_option},
 };
 
+use super::download;
+
 pub fn routes() -> Router {
     Router::new()
         .route("/", get(api_greeting).post(api_greeting).put(api_greeting))
         .route("/modify-upload", put(handle_modify_upload))
+        .route("/get-upload-edits", put(handle_get_upload_edits))
         .route("/modify-file", put(handle_modify_file))
+        .route("/moderation-queue", put(handle_get_moderation_queue))
+        .route("/moderate-files", put(handle_moderate_files))
         .route("/get-all-uploads", put(handle_get_all_uploads))
         .route("/get-all-files", put(handle_get_all_files))
+        .route("/get-file-duplicates", put(handle_get_file_duplicates))
         .route("/download-file-as-mod", put(download_file_as_mod))
+        .route("/get-deleted", put(handle_get_deleted))
+        .route("/delete-upload", put(handle_delete_upload))
+        .route("/delete-file", put(handle_delete_file))
+        .route("/restore-upload", put(handle_restore_upload))
+        .route("/restore-file", put(handle_restore_file))
 }
 
-#[derive(Debug, Deserialize)]
+/// The longest upload name the database can hold, in characters
+const MAX_UPLOAD_NAME_LENGTH: usize = 200;
+
+/// A moderator's changes to an upload; fields which are left out stay as they are
+#[derive(Debug, Deserialize, ToSchema)]
+#[allow(clippy::option_option)] // Nullable fields can be left out, removed, or set
 pub struct ModifyUploadRequest {
     pub id: Uuid,
     pub name: Option<String>,
@@ -42,25 +70,219 @@ pub struct ModifyUploadRequest {
     pub price: Option<i16>,
     pub uploader: Option<Uuid>,
     pub upload_date: Option<NaiveDateTime>,
+
+    /// Defaults to now if anything changes
     pub last_modified_date: Option<NaiveDateTime>,
 
+    /// The date associated with the upload, e.g. the date of the exam; `null` removes it
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<NaiveDateTime>)]
+    pub associated_date: Option<Option<NaiveDateTime>>,
+
+    pub upload_type: Option<UploadType>,
+
     /// The ID of the course this upload belongs to
     pub belongs_to: Option<Uuid>,
 
-    /// The ID of the prof that held the course this upload belongs to
+    /// The ID of the prof that held the course this upload belongs to; `null` removes it
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<Uuid>)]
     pub held_by: Option<Option<Uuid>>,
 }
 
-pub async fn handle_modify_upload(Json(request): Json<ModifyUploadRequest>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ModifyUploadRes {
+    pub success: bool,
+    pub upload: Upload,
+}
 
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(json!({ "error": "not implemented" })), // TODO
-    )
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/modify-upload",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = ModifyUploadRequest,
+    responses(
+        (status = 200, description = "The upload after the changes", body = ModifyUploadRes),
+        (status = 400, description = "Invalid value, or no such course, prof, or uploader", body = ErrorRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+    ),
+)]
+pub async fn handle_modify_upload(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(request): Json<ModifyUploadRequest>,
+) -> ApiResult<Json<ModifyUploadRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let Some(old_upload) = db::upload::get_upload_by_id(&mut tx, request.id).await? else {
+        return Err(ApiError::NotFound("No such upload".into()));
+    };
+
+    // 1. Check the new values
+    if let Some(name) = &request.name {
+        if name.trim().is_empty() {
+            return Err(bad_request("The name must not be empty"));
+        }
+
+        if name.chars().count() > MAX_UPLOAD_NAME_LENGTH {
+            return Err(bad_request("The name is too long"));
+        }
+    }
+
+    if request.price.is_some_and(|price| price < 0) {
+        return Err(bad_request("The price must not be negative"));
+    }
+
+    if let Some(uploader) = request.uploader {
+        if db::user::get_user_by_id(&mut tx, uploader).await?.is_none() {
+            return Err(bad_request("No such uploader"));
+        }
+    }
+
+    if let Some(course_id) = request.belongs_to {
+        if db::course::get_course(&mut tx, course_id).await?.is_none() {
+            return Err(bad_request("No such course"));
+        }
+    }
+
+    if let Some(Some(prof_id)) = request.held_by {
+        if db::prof::get_prof(&mut tx, prof_id).await?.is_none() {
+            return Err(bad_request("No such prof"));
+        }
+    }
+
+    // 2. Apply the changes
+    let mut upload = old_upload.clone();
+
+    if let Some(name) = request.name {
+        upload.name = name;
+    }
+
+    if let Some(description) = request.description {
+        upload.description = description;
+    }
+
+    if let Some(price) = request.price {
+        upload.price = price;
+    }
+
+    if let Some(uploader) = request.uploader {
+        upload.uploader = uploader;
+    }
+
+    if let Some(upload_date) = request.upload_date {
+        upload.upload_date = upload_date;
+    }
+
+    if let Some(associated_date) = request.associated_date {
+        upload.associated_date = associated_date;
+    }
+
+    if let Some(upload_type) = request.upload_type {
+        upload.upload_type = upload_type;
+    }
+
+    if let Some(belongs_to) = request.belongs_to {
+        upload.belongs_to = belongs_to;
+    }
+
+    if let Some(held_by) = request.held_by {
+        upload.held_by = held_by;
+    }
+
+    let changes = changed_fields(&old_upload, &upload)?;
+
+    if changes.is_empty() && request.last_modified_date.is_none() {
+        log::info!(
+            "Moderator {current_user_id} left upload {} unchanged",
+            upload.id
+        );
+
+        return Ok(Json(ModifyUploadRes {
+            success: true,
+            upload,
+        }));
+    }
+
+    upload.last_modified_date = request
+        .last_modified_date
+        .unwrap_or_else(|| chrono::Utc::now().naive_utc());
+
+    // 3. Store the upload, and who changed what
+    db::upload::update_upload(&mut tx, &upload).await?;
+
+    if !changes.is_empty() {
+        db::upload::record_upload_edit(&mut tx, upload.id, current_user_id, &changes.into())
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    log::info!("Moderator {current_user_id} modified upload {}", upload.id);
+
+    Ok(Json(ModifyUploadRes {
+        success: true,
+        upload,
+    }))
+}
+
+/// The fields which differ between two versions of an upload, as `{ "field": { "old": ..., "new": ... } }`
+fn changed_fields(old: &Upload, new: &Upload) -> anyhow::Result<Map<String, Value>> {
+    let Value::Object(old) = serde_json::to_value(old)? else {
+        unreachable!("An upload is serialized as an object");
+    };
+    let Value::Object(mut new) = serde_json::to_value(new)? else {
+        unreachable!("An upload is serialized as an object");
+    };
+
+    Ok(old
+        .into_iter()
+        // The modification date changes with every edit anyway
+        .filter(|(field, _)| field != "last_modified_date")
+        .filter_map(|(field, old_value)| {
+            let new_value = new.remove(&field)?;
+
+            (old_value != new_value).then(|| (field, json!({ "old": old_value, "new": new_value })))
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GetUploadEditsReq {
+    pub upload_id: Uuid,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadEditsRes {
+    pub success: bool,
+    pub edits: Vec<UploadEdit>,
+}
+
+/// Lists the moderators' edits of an upload, oldest first
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/get-upload-edits",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = GetUploadEditsReq,
+    responses((status = 200, body = UploadEditsRes)),
+)]
+pub async fn handle_get_upload_edits(
+    Json(req): Json<GetUploadEditsReq>,
+) -> ApiResult<Json<UploadEditsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let edits = db::upload::get_upload_edits(&mut tx, req.upload_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(UploadEditsRes {
+        success: true,
+        edits,
+    }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ModifyFileRequest {
     pub id: Uuid,
     pub name: Option<String>,
@@ -72,8 +294,22 @@ pub struct ModifyFileRequest {
     pub approval_mod: Option<bool>,
 }
 
-pub async fn handle_modify_file(Json(request): Json<ModifyFileRequest>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/modify-file",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = ModifyFileRequest,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such file", body = ErrorRes),
+        (status = 501, description = "Only `approval_mod` can be changed so far", body = ErrorRes),
+    ),
+)]
+pub async fn handle_modify_file(
+    Json(request): Json<ModifyFileRequest>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     let file = sqlx::query_as!(
         File,
@@ -95,155 +331,533 @@ pub async fn handle_modify_file(Json(request): Json<ModifyFileRequest>) -> impl
         ",
         request.id
     )
-    .fetch_one(&mut *tx)
+    .fetch_optional(&mut *tx)
     .await
-    .context("Failed to get file")
-    .unwrap();
+    .context("Failed to get file")?
+    .ok_or_else(|| ApiError::NotFound("No such file".into()))?;
 
     if request.name.is_some()
         || request.mime_type.is_some()
         || request.revision_at.is_some()
         || request.upload_id.is_some()
     {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": "not implemented" })), // TODO
-        );
+        return Err(ApiError::NotImplemented); // TODO
     }
 
     if let Some(approval_mod) = request.approval_mod {
-        sqlx::query!(
-            "
-            UPDATE
-                files
-            SET
-                approval_mod = $1
-            WHERE
-                id = $2
-            ",
-            approval_mod,
-            request.id
+        db::moderation::set_mod_approval(&mut tx, request.id, approval_mod).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ModerationQueueReq {
+    /// The maximum number of files, see [`db::DEFAULT_PAGE_SIZE`] and [`db::MAX_PAGE_SIZE`]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ModerationQueueRes {
+    pub success: bool,
+
+    /// The oldest files awaiting review
+    pub files: Vec<PendingFile>,
+
+    /// The number of all files awaiting review, including those not in `files`
+    pub pending_count: i64,
+}
+
+/// Lists the files awaiting review, oldest first
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/moderation-queue",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = ModerationQueueReq,
+    responses((status = 200, body = ModerationQueueRes)),
+)]
+pub async fn handle_get_moderation_queue(
+    Json(req): Json<ModerationQueueReq>,
+) -> ApiResult<Json<ModerationQueueRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let limit = req
+        .limit
+        .unwrap_or(db::DEFAULT_PAGE_SIZE)
+        .clamp(1, db::MAX_PAGE_SIZE);
+
+    let files = db::moderation::get_pending_files(&mut tx, limit).await?;
+    let pending_count = db::moderation::count_pending_files(&mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(ModerationQueueRes {
+        success: true,
+        files,
+        pending_count,
+    }))
+}
+
+/// The most files which can be decided on at once
+const MAX_MODERATED_FILES: usize = 100;
+
+/// The longest reason we accept, in characters
+const MAX_REASON_LENGTH: usize = 2000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ModerateFilesReq {
+    pub file_ids: Vec<Uuid>,
+    pub decision: ModerationDecision,
+
+    /// Shown to the uploaders; required for rejections
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ModerateFilesRes {
+    pub success: bool,
+
+    /// The IDs of the files decided on
+    pub file_ids: Vec<Uuid>,
+}
+
+/// Approves or rejects one or more files, and lets their uploaders know by email
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/moderate-files",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = ModerateFilesReq,
+    responses(
+        (status = 200, body = ModerateFilesRes),
+        (status = 400, description = "No or too many files, or a missing reason for a rejection", body = ErrorRes),
+        (status = 404, description = "Some file doesn't exist; nothing has been decided", body = ErrorRes),
+    ),
+)]
+pub async fn handle_moderate_files(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<ModerateFilesReq>,
+) -> ApiResult<Json<ModerateFilesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let mut file_ids = req.file_ids;
+    file_ids.sort_unstable();
+    file_ids.dedup();
+
+    if file_ids.is_empty() {
+        return Err(bad_request("No files given"));
+    }
+
+    if file_ids.len() > MAX_MODERATED_FILES {
+        return Err(bad_request(format!(
+            "At most {MAX_MODERATED_FILES} files can be decided on at once"
+        )));
+    }
+
+    // Treat an empty reason like no reason at all
+    let reason = req
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+
+    if reason.is_none() && req.decision == ModerationDecision::Rejected {
+        return Err(bad_request("Rejections need a reason"));
+    }
+
+    if reason.is_some_and(|r| r.chars().count() > MAX_REASON_LENGTH) {
+        return Err(bad_request("The reason is too long"));
+    }
+
+    let decided =
+        db::moderation::decide_files(&mut tx, &file_ids, req.decision, reason, current_user_id)
+            .await?;
+
+    // All or nothing, so a typo in one ID doesn't leave the others half-decided
+    if decided.len() != file_ids.len() {
+        return Err(ApiError::NotFound("No such file".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!(
+        "Moderator {current_user_id} decided {:?} on {} files",
+        req.decision,
+        decided.len()
+    );
+
+    // The decision stands even if a mail can't be sent, so failures are only logged
+    let approved = req.decision == ModerationDecision::Approved;
+    for files_of_uploader in decided.chunk_by(|a, b| a.uploader_id == b.uploader_id) {
+        let uploader = &files_of_uploader[0];
+
+        // Uploads of deleted accounts belong to the nil user, who has no mailbox
+        let Some(email) = uploader.uploader_email.as_deref() else {
+            continue;
+        };
+        if uploader.uploader_id.is_nil() {
+            continue;
+        }
+
+        let files: Vec<_> = files_of_uploader
+            .iter()
+            .map(|file| (file.upload_name.as_str(), file.file_name.as_str()))
+            .collect();
+
+        if let Err(err) = mail::send_moderation_mail(
+            uploader.uploader_first_names.as_deref().unwrap_or_default(),
+            uploader.uploader_last_name.as_deref().unwrap_or_default(),
+            email,
+            approved,
+            &files,
+            reason,
         )
-        .execute(&mut *tx)
         .await
-        .context("Failed to update file")
-        .unwrap();
+        {
+            log::error!(
+                "Failed to send moderation mail to {}: {err}",
+                uploader.uploader_id
+            );
+        }
     }
 
-    tx.commit().await.unwrap();
+    Ok(Json(ModerateFilesRes {
+        success: true,
+        file_ids,
+    }))
+}
 
-    (StatusCode::OK, Json(json!({ "success": true })))
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GetAllUploadsReq {
+    pub sorting: Option<db::upload::Sorting>,
+    #[serde(flatten)]
+    pub pagination: db::Pagination,
 }
 
-pub async fn handle_get_all_uploads() -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/get-all-uploads",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = GetAllUploadsReq,
+    responses(
+        (status = 200, body = UploadsRes),
+        (status = 400, description = "Invalid cursor", body = ErrorRes),
+    ),
+)]
+pub async fn handle_get_all_uploads(
+    Json(req): Json<GetAllUploadsReq>,
+) -> ApiResult<Json<UploadsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let sort_by = req.sorting.as_ref().map(|s| s.by).unwrap_or_default();
+    let cursor = match req.pagination.cursor.as_deref() {
+        Some(cursor) => match db::upload::UploadCursor::decode(cursor, sort_by) {
+            Ok(cursor) => Some(cursor),
+            Err(err) => {
+                log::info!("Invalid cursor: {err:#}");
+                return Err(bad_request("Invalid cursor"));
+            }
+        },
+        None => None,
+    };
 
-    let uploads = db::upload::get_all_uploads(&mut tx, None).await.unwrap();
+    let uploads =
+        db::upload::get_all_uploads(&mut tx, req.sorting, req.pagination.limit(), cursor).await?;
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "uploads": uploads,
-        })),
-    )
+    Ok(Json(UploadsRes {
+        success: true,
+        uploads: uploads.items,
+        next_cursor: uploads.next_cursor,
+    }))
 }
 
-pub async fn handle_get_all_files() -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+/// Files together with the uploads they belong to
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FilesRes {
+    pub success: bool,
 
-    let files = db::file::get_all_files_and_join_upload(&mut tx)
-        .await
-        .unwrap();
+    /// Pairs of a file and its upload
+    pub files: Vec<(File, Upload)>,
+}
 
-    tx.commit().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/get-all-files",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = FilesRes)),
+)]
+pub async fn handle_get_all_files() -> ApiResult<Json<FilesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "files": files,
-        })),
-    )
+    let files = db::file::get_all_files_and_join_upload(&mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(FilesRes {
+        success: true,
+        files,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GetFileDuplicatesReq {
+    pub file_id: Uuid,
+}
+
+/// Lists all other files with the same content, to spot re-uploads (e.g. of paid content)
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/get-file-duplicates",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = GetFileDuplicatesReq,
+    responses((status = 200, body = FilesRes)),
+)]
+pub async fn handle_get_file_duplicates(
+    Json(req): Json<GetFileDuplicatesReq>,
+) -> ApiResult<Json<FilesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let duplicates = db::file::get_files_with_same_content(&mut tx, req.file_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(FilesRes {
+        success: true,
+        files: duplicates,
+    }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GetFileAsModReq {
     pub file_id: Uuid,
 }
 
 /// Handles the actual download of a file to a client
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/download-file-as-mod",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = GetFileAsModReq,
+    responses(
+        (status = 200, description = "The file's contents", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range of the file's contents", content_type = "application/octet-stream"),
+        (status = 403, description = "The uploader doesn't consent to the download", body = ErrorRes),
+    ),
+)]
 async fn download_file_as_mod(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    headers: HeaderMap,
     Json(req): Json<GetFileAsModReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Response> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    let maybe_file = db::file::get_file(&mut tx, req.file_id).await;
+    let file = db::file::get_file(&mut tx, req.file_id).await?;
 
-    let Ok(file) = maybe_file else {
-        log::error!("Failed to get file: {}", maybe_file.unwrap_err());
-
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get file",
-            })),
-        ));
+    let Some(access) = db::entitlement::can_access_file(&mut tx, current_user_id, &file).await?
+    else {
+        return Err(ApiError::NotFound("No such upload".into()));
     };
 
+    tx.commit().await?;
+
     // Deny access to mods if the uploader does not consent to the file being downloaded
-    if !file.approval_uploader {
+    if !access.is_granted() {
         log::info!(
-            "Moderator {current_user_id} is unauthorized (uploader approval) to access file {}",
+            "Moderator {current_user_id} is unauthorized ({access:?}) to access file {}",
             file.id
         );
 
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "success": false,
-                "message": "Unauthorized",
-            })),
+        return Err(ApiError::Forbidden(
+            "Not allowed to access this file".into(),
         ));
     }
 
-    // Prepare the download logic
-    let do_download_to_user = async {
-        let fs_file = tokio::fs::File::open(
-            PathBuf::from(&CONF.upload_dir)
-                .join(&file.sha3_256[..2])
-                .join(&file.sha3_256),
-        )
-        .await;
-
-        let fs_file = fs_file.unwrap(); // TODO handle error
-
-        let stream = ReaderStream::new(fs_file);
-        let body = Body::from_stream(stream);
-
-        return Ok((
-            StatusCode::OK,
-            [
-                (header::CONTENT_TYPE, file.mime_type),
-                (
-                    header::CONTENT_DISPOSITION,
-                    format!("attachment; filename={}", file.name),
-                ),
-            ],
-            body,
-        ));
-    };
-
     log::info!(
-        "Moderator {current_user_id} is authorized (mod) to access file {}",
+        "Moderator {current_user_id} is authorized ({access:?}) to access file {}",
         file.id
     );
 
-    tx.commit().await.unwrap();
+    download::file_response(file, &headers).await
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeletedContentRes {
+    pub success: bool,
+    pub uploads: Vec<DeletedUpload>,
+
+    /// The deleted files, including those of deleted uploads
+    pub files: Vec<DeletedFile>,
+}
+
+/// Lists everything which has been deleted, e.g. to pick something to restore
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/get-deleted",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = DeletedContentRes)),
+)]
+async fn handle_get_deleted() -> ApiResult<Json<DeletedContentRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let uploads = db::deletion::get_deleted_uploads(&mut tx).await?;
+    let files = db::deletion::get_deleted_files(&mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(DeletedContentRes {
+        success: true,
+        uploads,
+        files,
+    }))
+}
+
+/// Deletes any upload, together with its files
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/delete-upload",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = DeleteUploadReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such upload, or it's deleted already", body = ErrorRes),
+    ),
+)]
+async fn handle_delete_upload(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<DeleteUploadReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    if !db::deletion::delete_upload(&mut tx, req.upload_id, current_user_id).await? {
+        return Err(ApiError::NotFound("No such upload".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!(
+        "Moderator {current_user_id} deleted upload {}",
+        req.upload_id
+    );
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+/// Deletes any single file
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/delete-file",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = DeleteFileReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such file, or it's deleted already", body = ErrorRes),
+    ),
+)]
+async fn handle_delete_file(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<DeleteFileReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    if !db::deletion::delete_file(&mut tx, req.file_id, current_user_id).await? {
+        return Err(ApiError::NotFound("No such file".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!("Moderator {current_user_id} deleted file {}", req.file_id);
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RestoreUploadReq {
+    pub upload_id: Uuid,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RestoreUploadRes {
+    pub success: bool,
+
+    /// The files deleted along with the upload which were restored too; purged ones stay deleted
+    pub restored_files_count: u64,
+}
+
+/// Restores a deleted upload, together with the files deleted along with it
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/restore-upload",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = RestoreUploadReq,
+    responses(
+        (status = 200, body = RestoreUploadRes),
+        (status = 404, description = "No such upload", body = ErrorRes),
+        (status = 409, description = "The upload isn't deleted", body = ErrorRes),
+    ),
+)]
+async fn handle_restore_upload(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<RestoreUploadReq>,
+) -> ApiResult<Json<RestoreUploadRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let restored_files_count = db::deletion::restore_upload(&mut tx, req.upload_id).await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "Moderator {current_user_id} restored upload {} with {restored_files_count} files",
+        req.upload_id
+    );
+
+    Ok(Json(RestoreUploadRes {
+        success: true,
+        restored_files_count,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RestoreFileReq {
+    pub file_id: Uuid,
+}
+
+/// Restores a single deleted file, as long as its content hasn't been purged yet
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/content/restore-file",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = RestoreFileReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such file", body = ErrorRes),
+        (status = 409, description = "The file isn't deleted, its upload is deleted, or its content is purged", body = ErrorRes),
+    ),
+)]
+async fn handle_restore_file(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<RestoreFileReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    db::deletion::restore_file(&mut tx, req.file_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Moderator {current_user_id} restored file {}", req.file_id);
 
-    do_download_to_user.await
+    Ok(Json(SuccessRes { success: true }))
 }
@@ -1,18 +1,17 @@
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
     Json, Router,
 };
-use serde::Deserialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::api_greeting,
+    api::{api_greeting, v1::SuccessRes},
     data::Course,
     db::{self, DB_POOL},
+    error::ApiResult,
 };
 
 pub fn routes() -> Router {
@@ -22,7 +21,7 @@ pub fn routes() -> Router {
         .route("/replace", put(handle_replace_course))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateCourseReq {
     pub name: String,
 
@@ -30,8 +29,24 @@ pub struct CreateCourseReq {
     pub held_at: Uuid,
 }
 
-async fn handle_create_course(Json(course): Json<CreateCourseReq>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreateCourseRes {
+    pub success: bool,
+    pub course: Course,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/courses/create",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = CreateCourseReq,
+    responses((status = 200, body = CreateCourseRes)),
+)]
+async fn handle_create_course(
+    Json(course): Json<CreateCourseReq>,
+) -> ApiResult<Json<CreateCourseRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     let course = Course {
         id: Uuid::new_v4(),
@@ -39,41 +54,29 @@ async fn handle_create_course(Json(course): Json<CreateCourseReq>) -> impl IntoR
         held_at: course.held_at,
     };
 
-    let db_action_result = db::course::create_course(&mut tx, &course).await;
-    tx.commit().await.unwrap();
+    db::course::create_course(&mut tx, &course).await?;
+    tx.commit().await?;
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
-    }
-
-    (
-        StatusCode::OK,
-        Json(json!({ "success": true, "course": course })),
-    )
+    Ok(Json(CreateCourseRes {
+        success: true,
+        course,
+    }))
 }
 
-async fn handle_replace_course(Json(course): Json<Course>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
-
-    let db_action_result = db::course::replace_course(&mut tx, course).await;
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/courses/replace",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = Course,
+    responses((status = 200, body = SuccessRes)),
+)]
+async fn handle_replace_course(Json(course): Json<Course>) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
-    }
+    db::course::replace_course(&mut tx, course).await?;
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
-    (StatusCode::OK, Json(json!({ "success": true })))
+    Ok(Json(SuccessRes { success: true }))
 }
@@ -0,0 +1,200 @@
+//! Serving the content of files to clients, with support for range and conditional requests
+
+use std::{fmt::Write, ops::RangeInclusive};
+
+use anyhow::{anyhow, Context};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+
+use crate::{data::File, error::ApiResult, storage};
+
+/// The format of HTTP dates, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Responds with the content of a file, honoring the request's `Range`, `If-Range`,
+/// `If-None-Match` and `If-Modified-Since` headers
+///
+/// The `ETag` is the file's hash, so it's the same for all files with the same content,
+/// and `Last-Modified` is the file's revision date.
+pub async fn file_response(file: File, request_headers: &HeaderMap) -> ApiResult<Response> {
+    let file_size = u64::try_from(file.size).unwrap_or_default();
+    let etag = format!("\"{}\"", file.sha3_256);
+    let last_modified = file.revision_at.format(HTTP_DATE_FORMAT).to_string();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, header_value(&etag));
+    headers.insert(header::LAST_MODIFIED, header_value(&last_modified));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    // Files may be paid content, so they must not end up in shared caches
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+
+    if is_not_modified(request_headers, &etag, file.revision_at) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    let Ok(range) = requested_range(request_headers, &etag, &last_modified, file_size) else {
+        headers.insert(
+            header::CONTENT_RANGE,
+            header_value(&format!("bytes */{file_size}")),
+        );
+
+        return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+    };
+
+    let blob = storage::blob_store()
+        .get(&file.sha3_256, range.clone())
+        .await
+        .with_context(|| format!("Failed to get content of file {}", file.id))?
+        .ok_or_else(|| anyhow!("Content of file {} is missing from storage", file.id))?;
+
+    headers.insert(header::CONTENT_TYPE, header_value(&file.mime_type));
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        header_value(&content_disposition(&file.name)),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(blob.size));
+
+    let status = if let Some(range) = range {
+        headers.insert(
+            header::CONTENT_RANGE,
+            header_value(&format!(
+                "bytes {}-{}/{file_size}",
+                range.start(),
+                range.end()
+            )),
+        );
+
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status, headers, Body::from_stream(blob.stream)).into_response())
+}
+
+/// Checks whether the client's cached copy is still up to date
+fn is_not_modified(request_headers: &HeaderMap, etag: &str, revision_at: NaiveDateTime) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since (RFC 9110, section 13.1.3)
+    if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+
+        // Uses the weak comparison, so `W/"..."` matches as well
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag);
+    }
+
+    request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| NaiveDateTime::parse_from_str(value, HTTP_DATE_FORMAT).ok())
+        // HTTP dates only have a precision of seconds
+        .is_some_and(|since| revision_at.and_utc().timestamp() <= since.and_utc().timestamp())
+}
+
+/// Gets the byte range the client asked for, or `None` to send the whole file
+///
+/// Returns an error if the range can't be satisfied. Invalid `Range` headers, and ranges
+/// which are conditional on an outdated `If-Range`, are ignored as per RFC 9110.
+/// Requests for multiple ranges are answered with the whole file, which is allowed as well.
+fn requested_range(
+    request_headers: &HeaderMap,
+    etag: &str,
+    last_modified: &str,
+    file_size: u64,
+) -> Result<Option<RangeInclusive<u64>>, ()> {
+    let Some(range) = request_headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Ok(None);
+    };
+
+    // Only send a part of the file if the client still has the rest of the same version
+    if let Some(if_range) = request_headers.get(header::IF_RANGE) {
+        let if_range = if_range.to_str().unwrap_or_default();
+
+        // An entity tag has to match exactly (strong comparison), a date has to be the exact date
+        if if_range != etag && if_range != last_modified {
+            return Ok(None);
+        }
+    }
+
+    let Ok(parsed) = http_range_header::parse_range_header(range) else {
+        return Ok(None);
+    };
+
+    // An empty file has no bytes to satisfy any range with
+    if file_size == 0 {
+        return Err(());
+    }
+
+    let ranges = parsed.validate(file_size).map_err(|_| ())?;
+
+    if let [range] = ranges.as_slice() {
+        Ok(Some(range.clone()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Builds the `Content-Disposition` of a download (RFC 6266)
+///
+/// `filename*` carries the actual name; `filename` is an ASCII fallback for old clients.
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '_'
+            }
+        })
+        .flat_map(|c| {
+            let escape = matches!(c, '"' | '\\').then_some('\\');
+            escape.into_iter().chain([c])
+        })
+        .collect();
+
+    // Everything but the `attr-char`s of RFC 8187 gets percent-encoded
+    let encoded = file_name.bytes().fold(String::new(), |mut encoded, byte| {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+        encoded
+    });
+
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    // File names & MIME types come from users; leave out anything that can't be in a header
+    HeaderValue::from_str(value).unwrap_or_else(|_| {
+        HeaderValue::from_str(&value.replace(|c: char| !c.is_ascii() || c.is_ascii_control(), ""))
+            .unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_disposition_quotes_and_encodes_the_name() {
+        assert_eq!(
+            content_disposition("Prüfung 2023; \"Lösung\".pdf"),
+            "attachment; filename=\"Pr_fung 2023; \\\"L_sung\\\".pdf\"; \
+             filename*=UTF-8''Pr%C3%BCfung%202023%3B%20%22L%C3%B6sung%22.pdf"
+        );
+    }
+}
@@ -1,21 +1,20 @@
 use anyhow::Context;
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
     Json, Router,
 };
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::{PgPool, PgTransaction};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::api_greeting,
+    api::{api_greeting, v1::SuccessRes},
     data::Prof,
     db::{self, DB_POOL},
+    error::ApiResult,
 };
 
 pub fn routes() -> Router {
@@ -28,36 +27,60 @@ pub fn routes() -> Router {
         )
 }
 
-pub async fn handle_get_user_balance(Json(user_id): Json<Uuid>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserBalanceRes {
+    pub success: bool,
 
-    let balance = db::ecs::calculate_available_funds(&mut tx, user_id).await;
+    /// The cached balance, which purchases are checked against
+    pub balance: i64,
 
-    tx.commit().await.unwrap(); // TODO check if we really need a transaction here
+    /// The balance calculated from all transactions; differs from `balance` if the cache is stale
+    pub ledger_balance: i64,
+}
 
-    match balance {
-        Ok(balance) => (
-            StatusCode::OK,
-            Json(json!({ "success": true, "balance": balance })),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": e.to_string() })),
-        ),
-    }
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/ecs/get-user-balance",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(content = Uuid, description = "The ID of the user"),
+    responses((status = 200, body = UserBalanceRes)),
+)]
+pub async fn handle_get_user_balance(Json(user_id): Json<Uuid>) -> ApiResult<Json<UserBalanceRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    // Admins also get the balance calculated from the full ledger, to spot a stale cache
+    let balance = db::ecs::calculate_available_funds(&mut tx, user_id).await?;
+    let ledger_balance = db::ecs::recalculate_available_funds(&mut tx, user_id).await?;
+
+    tx.commit().await?; // TODO check if we really need a transaction here
+
+    Ok(Json(UserBalanceRes {
+        success: true,
+        balance,
+        ledger_balance,
+    }))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct CreateSystemTransactionRequest {
     user_id: Uuid,
     delta_ec: i64,
     reason: Option<String>,
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/ecs/create-system-transaction",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = CreateSystemTransactionRequest,
+    responses((status = 200, body = SuccessRes)),
+)]
 pub async fn handle_create_system_transaction(
     Json(req): Json<CreateSystemTransactionRequest>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Creating system transaction: {:?}", req);
 
@@ -68,17 +91,11 @@ pub async fn handle_create_system_transaction(
         reason: req.reason,
     };
 
-    let result = create_system_transaction(&mut tx, transaction).await;
+    create_system_transaction(&mut tx, transaction).await?;
 
-    tx.commit().await.unwrap(); // TODO check if we really need a transaction here
+    tx.commit().await?; // TODO check if we really need a transaction here
 
-    match result {
-        Ok(_) => (StatusCode::OK, Json(json!({ "success": true }))),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": e.to_string() })),
-        ),
-    }
+    Ok(Json(SuccessRes { success: true }))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,274 @@
+//! A user's email addresses: they log in with the primary one, and can switch to another verified one,
+//! e.g. when they graduate and lose their student address
+
+use axum::{routing::put, Extension, Json, Router};
+use email_address::EmailAddress;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::v1::{
+        auth::{reject_login, verify_password},
+        client::ClientInfo,
+        totp, SuccessRes,
+    },
+    db::{
+        self,
+        email::{EmailStatus, UserEmail},
+        DB_POOL,
+    },
+    error::{ApiError, ApiResult, ErrorRes},
+    mail::send_email_verification_mail,
+    util::bad_request,
+};
+
+/// How many addresses (including the primary one) a user may have
+const MAX_EMAILS_PER_USER: usize = 5;
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/list", put(handle_get_emails))
+        .route("/add", put(handle_add_email))
+        .route("/remove", put(handle_remove_email))
+        .route("/set-primary", put(handle_set_primary_email))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EmailsRes {
+    pub success: bool,
+    pub emails: Vec<UserEmail>,
+}
+
+/// List the current user's email addresses, the primary one first
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/emails/list",
+    tag = "do",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = EmailsRes)),
+)]
+pub async fn handle_get_emails(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+) -> ApiResult<Json<EmailsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let emails = db::email::get_emails_of_user(&mut tx, current_user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(EmailsRes {
+        success: true,
+        emails,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddEmailReq {
+    pub address: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AddEmailRes {
+    pub success: bool,
+    pub email_id: Uuid,
+}
+
+/// Add another address to the current user, which has to be verified through the mail sent to it.
+///
+/// Unlike at registration, the address doesn't need to belong to a university.
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/emails/add",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = AddEmailReq,
+    responses(
+        (status = 200, description = "Added; a verification mail has been sent", body = AddEmailRes),
+        (status = 400, description = "Invalid email address, or too many addresses", body = ErrorRes),
+        (status = 409, description = "Email address already taken", body = ErrorRes),
+    ),
+)]
+pub async fn handle_add_email(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<AddEmailReq>,
+) -> ApiResult<Json<AddEmailRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let address = req.address.trim();
+    let Ok(email_address) = address.parse::<EmailAddress>() else {
+        return Err(ApiError::EmailInvalid(address.into()));
+    };
+
+    if db::email::get_emails_of_user(&mut tx, current_user_id)
+        .await?
+        .len()
+        >= MAX_EMAILS_PER_USER
+    {
+        return Err(bad_request(format!(
+            "A user can have at most {MAX_EMAILS_PER_USER} email addresses"
+        )));
+    }
+
+    if db::email::is_address_taken(&mut tx, address).await? {
+        return Err(ApiError::EmailTaken(address.into()));
+    }
+
+    let Some(user) = db::user::get_user_by_id(&mut tx, current_user_id).await? else {
+        return Err(ApiError::Unauthorized);
+    };
+
+    let of_university = db::user::find_university_of_email(&mut tx, &email_address)
+        .await?
+        .flatten();
+
+    let email_id = db::email::add_email(&mut tx, current_user_id, address, of_university).await?;
+    let token = db::activation::create_activation_token(&mut tx, email_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("User {current_user_id} added email {email_id}");
+
+    if let Err(e) =
+        send_email_verification_mail(&user.first_names, &user.last_name, address, &token).await
+    {
+        // The user can remove the address and add it again
+        log::error!("Failed to send email verification mail: {e:?}");
+    }
+
+    Ok(Json(AddEmailRes {
+        success: true,
+        email_id,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EmailIdReq {
+    pub email_id: Uuid,
+}
+
+/// Remove one of the current user's addresses, other than the primary one
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/emails/remove",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = EmailIdReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such address of the current user", body = ErrorRes),
+        (status = 409, description = "The primary address can't be removed", body = ErrorRes),
+    ),
+)]
+pub async fn handle_remove_email(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<EmailIdReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let emails = db::email::get_emails_of_user(&mut tx, current_user_id).await?;
+    let Some(email) = emails.iter().find(|email| email.id == req.email_id) else {
+        return Err(ApiError::NotFound("No such email address".into()));
+    };
+
+    if email.is_primary {
+        return Err(ApiError::Conflict(
+            "The primary address can't be removed; make another one primary first".into(),
+        ));
+    }
+
+    // The address may have been removed or made primary in the meantime
+    if !db::email::remove_email(&mut tx, current_user_id, email.id).await? {
+        return Err(ApiError::NotFound("No such email address".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!("User {current_user_id} removed email {}", req.email_id);
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetPrimaryEmailReq {
+    pub email_id: Uuid,
+    /// The current password; the primary address is the one to log in and reset the password with
+    pub password: String,
+    /// A code from the authenticator or a recovery code, if two-factor authentication is enabled
+    pub totp: Option<String>,
+}
+
+/// Make a verified address the current user's primary one, which they log in with from now on.
+///
+/// This takes the same credentials as logging in, so a stolen session can't take over the account.
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/emails/set-primary",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = SetPrimaryEmailReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 400, description = "Wrong password, or wrong two-factor code", body = ErrorRes),
+        (status = 401, description = "A two-factor code is required", body = ErrorRes),
+        (status = 404, description = "No such address of the current user", body = ErrorRes),
+        (status = 409, description = "The address hasn't been verified", body = ErrorRes),
+        (status = 429, description = "Too many failed attempts", body = ErrorRes),
+    ),
+)]
+pub async fn handle_set_primary_email(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    client: ClientInfo,
+    Json(req): Json<SetPrimaryEmailReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let Some(user) = db::user::get_user_by_id(&mut tx, current_user_id).await? else {
+        return Err(ApiError::Unauthorized);
+    };
+
+    // Wrong passwords & codes count towards the login lockout of the current primary address
+    let login_email = &user.emails[0];
+    if db::login_throttle::is_locked_out(&mut tx, login_email, client.ip).await? {
+        return Err(ApiError::LoginLockedOut);
+    }
+
+    if !verify_password(&req.password, &user.password_hash) {
+        return Err(reject_login(tx, login_email, &client, ApiError::InvalidCredentials).await);
+    }
+
+    if let Some(totp_secret) = &user.totp_secret {
+        let Some(code) = &req.totp else {
+            return Err(ApiError::TotpRequired);
+        };
+
+        if !totp::verify_second_factor(&mut tx, user.id, totp_secret, code).await? {
+            return Err(reject_login(tx, login_email, &client, ApiError::InvalidTotpCode).await);
+        }
+    }
+
+    let emails = db::email::get_emails_of_user(&mut tx, current_user_id).await?;
+    let Some(email) = emails.iter().find(|email| email.id == req.email_id) else {
+        return Err(ApiError::NotFound("No such email address".into()));
+    };
+
+    if email.status != EmailStatus::Verified {
+        return Err(ApiError::Conflict(
+            "Only verified addresses can become the primary one".into(),
+        ));
+    }
+
+    // The address may have been removed in the meantime
+    if !db::email::set_primary_email(&mut tx, current_user_id, email.id).await? {
+        return Err(ApiError::NotFound("No such email address".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!(
+        "User {current_user_id} made email {} their primary one",
+        req.email_id
+    );
+
+    Ok(Json(SuccessRes { success: true }))
+}
@@ -1,30 +1,38 @@
-use std::{path::PathBuf, vec};
-
 use anyhow::Context;
 use axum::{
-    body::Body,
     extract::State,
-    http::{header, StatusCode},
-    response::{AppendHeaders, IntoResponse},
+    http::HeaderMap,
+    response::{AppendHeaders, Response},
     routing::{get, put},
     Extension, Json, Router,
 };
 use axum_extra::extract::{cookie::Cookie, CookieJar};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::{prelude::FromRow, PgTransaction};
-use tokio_util::io::ReaderStream;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
     api::{api_greeting, v1::auth::make_dead_cookie},
     conf::CONF,
-    data::{File, Purchase, RedactedUser, Upload, UploadType},
-    db::{self, DB_POOL},
+    data::{
+        Course, File, OwnedUniversity, Prof, Purchase, RatedUpload, RedactedUser, Upload,
+        UploadType,
+    },
+    db::{
+        self,
+        entitlement::FileAccess,
+        purchase::UploadReview,
+        search::{CourseSearchResult, ProfSearchResult, UploadSearchResult},
+        session::SessionInfo,
+        DB_POOL,
+    },
+    error::{ApiError, ApiResult, ErrorRes},
+    util::bad_request,
 };
 
-use super::SESSION_COOKIE_NAME;
+use super::{download, SESSION_COOKIE_NAME};
 
 pub fn routes() -> Router {
     Router::new()
@@ -35,180 +43,241 @@ pub fn routes() -> Router {
         .route("/me", put(handle_get_me))
         .route("/file", put(handle_get_file))
         .route("/files-of-upload", put(handle_get_files_of_upload))
+        .route("/upload-reviews", put(handle_get_upload_reviews))
         .route("/prof", put(handle_get_prof))
         .route("/my-ecs-balance", put(handle_get_my_ecs))
         .route("/purchased-uploads", put(handle_get_purchased_uploads))
+        .route("/search", put(handle_get_search))
+        .route("/sessions", put(handle_get_sessions))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionsRes {
+    pub success: bool,
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// Handles requests to list the current user's logins, e.g. to find ones on lost devices
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/sessions",
+    tag = "get",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, body = SessionsRes),
+        (status = 401, description = "Not logged in", body = ErrorRes),
+    ),
+)]
+async fn handle_get_sessions(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    cookie_jar: CookieJar,
+) -> ApiResult<Json<SessionsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    if current_user_id.is_nil() {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let current_token = cookie_jar.get(SESSION_COOKIE_NAME).map(Cookie::value);
+
+    let sessions =
+        db::session::get_sessions_of_user(&mut tx, current_user_id, current_token).await?;
+
+    Ok(Json(SessionsRes {
+        success: true,
+        sessions,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MyEcsRes {
+    pub success: bool,
+    pub ecs_balance: i64,
 }
 
 /// Handles requests to get the user's own current ECs balance
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/my-ecs-balance",
+    tag = "get",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, body = MyEcsRes),
+        (status = 401, description = "Not logged in", body = ErrorRes),
+    ),
+)]
 async fn handle_get_my_ecs(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<MyEcsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     // Most `/get` endpoints do not require authentication; this one does
     if current_user_id.is_nil() {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({ "success": false, "message": "Unauthorized" })),
-        );
+        return Err(ApiError::Unauthorized);
     }
 
-    // let maybe_ecs = sqlx::query_file_as!(i32, "src/db/sql/get_available_ecs.sql", current_user_id)
-    //     .fetch_one(&*&mut **tx)
-    //     .await;
-    let maybe_ecs: anyhow::Result<(f64,)> =
-        sqlx::query_as(include_str!("../../db/sql/get_available_ecs.sql"))
-            .bind(&current_user_id)
-            .fetch_one(&mut *tx)
-            .await
-            .context("Failed to get ECs");
-
-    let Ok((ecs,)) = maybe_ecs else {
-        log::error!("Failed to get ECs: {:#?}", maybe_ecs.unwrap_err());
-
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get ECs",
-            })),
-        );
-    };
+    let ecs = db::ecs::calculate_available_funds(&mut tx, current_user_id).await?;
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "ecs_balance": ecs,
-        })),
-    )
+    Ok(Json(MyEcsRes {
+        success: true,
+        ecs_balance: ecs,
+    }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GetUploadsReq {
     pub course_id: Uuid,
     pub sorting: Option<db::upload::Sorting>,
+    #[serde(flatten)]
+    pub pagination: db::Pagination,
 }
 
-async fn handle_get_courses() -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+/// One page of uploads, see [`db::Page`]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadsRes {
+    pub success: bool,
+    pub uploads: Vec<RatedUpload>,
+    pub next_cursor: Option<String>,
+}
 
-    let maybe_courses = db::course::get_courses(&mut tx).await;
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CoursesRes {
+    pub success: bool,
+    pub courses: Vec<Course>,
+}
 
-    let Ok(courses) = maybe_courses else {
-        log::error!("Failed to get courses: {}", maybe_courses.unwrap_err());
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/courses",
+    tag = "get",
+    responses((status = 200, body = CoursesRes)),
+)]
+async fn handle_get_courses() -> ApiResult<Json<CoursesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get courses",
-            })),
-        );
-    };
+    let courses = db::course::get_courses(&mut tx).await?;
 
     // TODO add some kind of upload approval status
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "courses": courses,
-        })),
-    )
+    Ok(Json(CoursesRes {
+        success: true,
+        courses,
+    }))
 }
 
-async fn handle_get_uploads(Json(course): Json<GetUploadsReq>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/uploads",
+    tag = "get",
+    request_body = GetUploadsReq,
+    responses(
+        (status = 200, body = UploadsRes),
+        (status = 400, description = "Invalid cursor", body = ErrorRes),
+    ),
+)]
+async fn handle_get_uploads(Json(course): Json<GetUploadsReq>) -> ApiResult<Json<UploadsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Get uploads for course {}", course.course_id);
 
-    let maybe_uploads =
-        db::upload::get_uploads_of_course(&mut tx, course.course_id, course.sorting).await;
+    let sort_by = course.sorting.as_ref().map(|s| s.by).unwrap_or_default();
+    let cursor = match course.pagination.cursor.as_deref() {
+        Some(cursor) => match db::upload::UploadCursor::decode(cursor, sort_by) {
+            Ok(cursor) => Some(cursor),
+            Err(err) => {
+                log::info!("Invalid cursor: {err:#}");
+                return Err(bad_request("Invalid cursor"));
+            }
+        },
+        None => None,
+    };
 
-    let Ok(uploads) = maybe_uploads else {
-        log::error!("Failed to get courses: {}", maybe_uploads.unwrap_err());
+    // TODO return a more specific error message (e.g. 404 if course doesn't exist)
+    let uploads = db::upload::get_uploads_of_course(
+        &mut tx,
+        course.course_id,
+        course.sorting,
+        course.pagination.limit(),
+        cursor,
+    )
+    .await?;
 
-        // TODO return a more specific error message (e.g. 404 if course doesn't exist)
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get courses",
-            })),
-        );
-    };
+    tx.commit().await?;
 
-    tx.commit().await.unwrap();
+    Ok(Json(UploadsRes {
+        success: true,
+        uploads: uploads.items,
+        next_cursor: uploads.next_cursor,
+    }))
+}
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "uploads": uploads,
-        })),
-    )
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UniversitiesRes {
+    pub success: bool,
+    pub universities: Vec<OwnedUniversity>,
 }
 
-async fn handle_get_universities() -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/universities",
+    tag = "get",
+    responses((status = 200, body = UniversitiesRes)),
+)]
+async fn handle_get_universities() -> ApiResult<Json<UniversitiesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    let maybe_universities = db::university::get_universities(&mut tx).await;
+    let universities = db::university::get_universities(&mut tx).await?;
 
-    let Ok(universities) = maybe_universities else {
-        log::error!(
-            "Failed to get universities: {}",
-            maybe_universities.unwrap_err()
-        );
+    tx.commit().await?;
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get universities",
-            })),
-        );
-    };
+    Ok(Json(UniversitiesRes {
+        success: true,
+        universities,
+    }))
+}
 
-    tx.commit().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MeRes {
+    pub success: bool,
+    pub user: RedactedUser,
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "universities": universities,
-        })),
-    )
+    /// The university of the user's primary email address
+    pub university: Option<OwnedUniversity>,
 }
 
-async fn handle_get_me(cookie_jar: CookieJar) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
-
-    // We return a generic error response if the user is not logged in
-    //  to avoid leaking private information
-    let generic_error_response = json!({
-        "success": false,
-        "message": "You are not logged in",
-    });
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/me",
+    tag = "get",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, body = MeRes),
+        (status = 401, description = "Not logged in; an invalid session cookie gets removed", body = ErrorRes),
+    ),
+)]
+async fn handle_get_me(
+    cookie_jar: CookieJar,
+) -> Result<(CookieJar, Json<MeRes>), (CookieJar, ApiError)> {
+    let mut tx = match (*DB_POOL.get().unwrap()).begin().await {
+        Ok(tx) => tx,
+        Err(err) => return Err((cookie_jar, err.into())),
+    };
 
     // TODO we should probably have a middleware for this
 
     // Get the session cookie from the cookie jar
     let Some(session_cookie) = cookie_jar.get(SESSION_COOKIE_NAME) else {
         log::info!("No session cookie");
-        return (
-            StatusCode::UNAUTHORIZED,
-            cookie_jar,
-            Json(generic_error_response),
-        );
+        return Err((cookie_jar, ApiError::Unauthorized));
     };
 
     // Get the user from the database
+    // We return a generic error response if the user is not logged in
+    //  to avoid leaking private information
     let maybe_user = db::user::get_user_by_session(&mut tx, session_cookie.value()).await;
     let Ok(user) = maybe_user else {
         log::error!(
@@ -216,304 +285,293 @@ async fn handle_get_me(cookie_jar: CookieJar) -> impl IntoResponse {
             maybe_user.unwrap_err()
         );
 
-        return (
-            StatusCode::UNAUTHORIZED,
-            cookie_jar.add(make_dead_cookie()),
-            Json(generic_error_response),
-        );
+        return Err((cookie_jar.add(make_dead_cookie()), ApiError::Unauthorized));
     };
 
-    tx.commit().await.unwrap();
+    let university = match db::university::get_university_of_user(&mut tx, user.id).await {
+        Ok(university) => university,
+        Err(err) => return Err((cookie_jar, err.into())),
+    };
+
+    if let Err(err) = tx.commit().await {
+        return Err((cookie_jar, err.into()));
+    }
 
-    return (
-        StatusCode::OK,
+    Ok((
         cookie_jar,
-        Json(json!({
-            "success": true,
-            "user": RedactedUser::from(user), // hide sensitive information
-        })),
-    );
+        Json(MeRes {
+            success: true,
+            user: RedactedUser::from(user), // hide sensitive information
+            university,
+        }),
+    ))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GetFileReq {
     pub file_id: Uuid,
 }
 
 /// Handles the actual download of a file to a client
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/file",
+    tag = "get",
+    security(("session_cookie" = [])),
+    request_body = GetFileReq,
+    responses(
+        (status = 200, description = "The file's contents", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range of the file's contents", content_type = "application/octet-stream"),
+        (status = 304, description = "The file hasn't changed"),
+        (status = 401, description = "Not logged in", body = ErrorRes),
+        (status = 403, description = "Not purchased, or not approved", body = ErrorRes),
+        (status = 404, description = "The file has been deleted", body = ErrorRes),
+        (status = 416, description = "The requested range is not satisfiable"),
+    ),
+)]
 async fn handle_get_file(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    headers: HeaderMap,
     Json(req): Json<GetFileReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Response> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     // Most `/get` endpoints do not require authentication; this one does
     if current_user_id.is_nil() {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(json!({ "success": false, "message": "Unauthorized" })),
-        ));
+        return Err(ApiError::Unauthorized);
     }
 
-    let maybe_file = db::file::get_file(&mut tx, req.file_id).await;
-
-    let Ok(file) = maybe_file else {
-        log::error!("Failed to get file: {}", maybe_file.unwrap_err());
-
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get file",
-            })),
-        ));
-    };
+    let file = db::file::get_file(&mut tx, req.file_id).await?;
 
-    // Prepare the download logic
-    let do_download_to_user = async {
-        let fs_file = tokio::fs::File::open(
-            PathBuf::from(&CONF.upload_dir)
-                .join(&file.sha3_256[..2])
-                .join(&file.sha3_256),
-        )
-        .await;
-
-        let fs_file = fs_file.unwrap(); // TODO handle error
-
-        let stream = ReaderStream::new(fs_file);
-        let body = Body::from_stream(stream);
-
-        return Ok((
-            StatusCode::OK,
-            [
-                (header::CONTENT_TYPE, file.mime_type),
-                (
-                    header::CONTENT_DISPOSITION,
-                    format!("attachment; filename={}", file.name),
-                ),
-            ],
-            body,
-        ));
+    let Some(access) = db::entitlement::can_access_file(&mut tx, current_user_id, &file).await?
+    else {
+        return Err(ApiError::NotFound("No such upload".into()));
     };
 
-    let Ok(upload) = db::file::get_upload_of_file(&mut tx, file.id).await else {
-        log::error!("Failed to get upload of file: {}", file.id);
+    tx.commit().await?;
 
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get upload",
-            })),
-        ));
-    };
-
-    // A user is always authorized to access their own files
-    if upload.uploader == current_user_id {
+    if access == FileAccess::Deleted {
         log::info!(
-            "User {current_user_id} is authorized (owner) to access file {}",
+            "User {current_user_id} cannot access deleted file {}",
             file.id
         );
-        return do_download_to_user.await;
-    } else {
-        log::info!("User {current_user_id} does not own file {}", file.id);
-        // Option 1: the file is owned by the user
-        // or
-        // Option 2: the file has been approved by a moderator and by the uploader
-        if !(file.approval_mod && file.approval_uploader) {
-            log::info!(
-                "User {current_user_id} is not authorized to access file {}",
-                file.id
-            );
-
-            // The user has not purchased this file
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(json!({
-                    "success": false,
-                    "message": "This file lacks approval from a moderator and/or the uploader",
-                })),
-            ));
-        }
-    }
 
-    // Check if there is a valid purchase for this file
-    let maybe_purchase = db::purchase::get_purchase(&mut tx, current_user_id, file.id).await;
-    let Ok(purchase) = maybe_purchase else {
-        log::error!("Failed to get purchase: {}", maybe_purchase.unwrap_err());
-
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get purchase",
-            })),
-        ));
-    };
+        return Err(ApiError::NotFound("No such file".into()));
+    }
 
-    if purchase.is_none() {
+    if access.is_granted() {
         log::info!(
-            "User {current_user_id} is not authorized to access file {}",
+            "User {current_user_id} is authorized ({access:?}) to access file {}",
             file.id
         );
 
-        // The user has not purchased this file
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "success": false,
-                "message": "No valid purchase for this file and user",
-            })),
-        ));
-    };
+        return download::file_response(file, &headers).await;
+    }
 
     log::info!(
-        "User {current_user_id} is authorized (purchase) to access file {}",
+        "User {current_user_id} is not authorized ({access:?}) to access file {}",
         file.id
     );
 
-    do_download_to_user.await
+    let message = if access == FileAccess::NotApproved {
+        "This file lacks approval from a moderator and/or the uploader"
+    } else {
+        "No valid purchase for this file and user"
+    };
+
+    Err(ApiError::Forbidden(message.into()))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GetUploadReq {
     pub upload_id: Uuid,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileOfUpload {
+    #[serde(flatten)]
+    file: File,
+
+    /// Whether the current user may download this file
+    can_download: bool,
+
+    /// Whether the file has been deleted; only moderators and earlier buyers still see it
+    is_deleted: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FilesOfUploadRes {
+    pub success: bool,
+
+    /// The files the current user may see, i.e. approved ones and their own
+    pub files: Vec<FileOfUpload>,
+
+    /// The number of files, including unapproved ones the current user may not see
+    pub total_files_count: usize,
+    pub upload: Upload,
+    pub uploader_name: String,
+
+    /// Whether the upload has been deleted; only moderators and earlier buyers still see it
+    pub is_deleted: bool,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/files-of-upload",
+    tag = "get",
+    request_body = GetUploadReq,
+    responses(
+        (status = 200, body = FilesOfUploadRes),
+        (status = 404, description = "No such upload, or it has been deleted", body = ErrorRes),
+    ),
+)]
 async fn handle_get_files_of_upload(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     Json(upload): Json<GetUploadReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<FilesOfUploadRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Get details for upload {}", upload.upload_id);
 
-    let maybe_files = db::file::get_files_and_join_upload(&mut tx, upload.upload_id).await;
+    // TODO return a more specific error message (e.g. 404 if course doesn't exist)
+    let Some(entitlements) =
+        db::entitlement::get_upload_entitlements(&mut tx, current_user_id, upload.upload_id)
+            .await?
+    else {
+        return Err(ApiError::NotFound("No such upload".into()));
+    };
 
-    let Ok(files) = maybe_files else {
-        log::error!("Failed to get files: {}", maybe_files.unwrap_err());
+    if entitlements.is_hidden() {
+        log::info!("Upload {} has been deleted", upload.upload_id);
 
-        // TODO return a more specific error message (e.g. 404 if course doesn't exist)
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get files & upload info",
-            })),
-        );
-    };
+        return Err(ApiError::NotFound("No such upload".into()));
+    }
 
-    let original_files_count = files.len();
+    let files = db::file::get_files_and_join_upload(&mut tx, upload.upload_id).await?;
+    let deleted_file_ids = db::deletion::get_deleted_file_ids(&mut tx, upload.upload_id).await?;
 
-    // Option 1: the file is owned by the user
-    // or
-    // Option 2: the file has been approved by a moderator and by the uploader
-    let show_file_predicate = |(file, upload): &(File, Upload)| {
-        (upload.uploader == current_user_id) || (file.approval_mod && file.approval_uploader)
-    };
+    // Deleted files don't count, unless the user may still see them
+    let files = files
+        .into_iter()
+        .map(|(file, _)| {
+            let is_deleted = deleted_file_ids.contains(&file.id);
+            let access = entitlements.access_to(&file, is_deleted);
+
+            (file, access, is_deleted)
+        })
+        .filter(|(_, access, _)| *access != FileAccess::Deleted)
+        .collect::<Vec<_>>();
 
-    // Filter out files that have not been approved
-    // FIXME let people get their own files even if they are not approved
+    let original_files_count = files.len();
+
+    // Filter out files that have not been approved, unless the user may see them anyway
+    // (e.g. their own files); files which still need to be purchased are listed
     let files = files
         .into_iter()
-        .filter(show_file_predicate)
-        .map(|(file, _)| file)
+        .filter_map(|(file, access, is_deleted)| {
+            (access != FileAccess::NotApproved).then(|| FileOfUpload {
+                file,
+                can_download: access.is_granted(),
+                is_deleted,
+            })
+        })
         .collect::<Vec<_>>();
 
     // Get the upload info
-    let maybe_upload_and_uploader_name = get_upload(&mut tx, upload.upload_id).await;
+    let (upload, uploader_name) = get_upload(&mut tx, upload.upload_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(FilesOfUploadRes {
+        success: true,
+        files,
+        total_files_count: original_files_count,
+        upload,
+        uploader_name,
+        is_deleted: entitlements.is_deleted(),
+    }))
+}
 
-    let Ok((upload, uploader_name)) = maybe_upload_and_uploader_name else {
-        log::error!(
-            "Failed to get upload: {}",
-            maybe_upload_and_uploader_name.unwrap_err()
-        );
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadReviewsRes {
+    pub success: bool,
+    pub reviews: Vec<UploadReview>,
+}
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get upload",
-            })),
-        );
-    };
+/// Handles requests for the ratings & reviews of an upload
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/upload-reviews",
+    tag = "get",
+    request_body = GetUploadReq,
+    responses((status = 200, body = UploadReviewsRes)),
+)]
+async fn handle_get_upload_reviews(
+    Json(upload): Json<GetUploadReq>,
+) -> ApiResult<Json<UploadReviewsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    tx.commit().await.unwrap();
-
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "files": files,
-            "total_files_count": original_files_count,
-            "upload": upload,
-            "uploader_name": uploader_name,
-        })),
-    )
+    log::info!("Get reviews for upload {}", upload.upload_id);
+
+    let reviews = db::purchase::get_reviews_of_upload(&mut tx, upload.upload_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(UploadReviewsRes {
+        success: true,
+        reviews,
+    }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GetProfReq {
     pub prof_id: Uuid,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProfRes {
+    pub success: bool,
+    pub prof: Prof,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/prof",
+    tag = "get",
+    security(("session_cookie" = [])),
+    request_body = GetProfReq,
+    responses(
+        (status = 200, body = ProfRes),
+        (status = 401, description = "Not logged in", body = ErrorRes),
+        (status = 404, description = "No such prof", body = ErrorRes),
+    ),
+)]
 async fn handle_get_prof(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
     Json(prof_req): Json<GetProfReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<ProfRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Get details for prof {}", prof_req.prof_id);
 
     if current_user_id.is_nil() {
         log::info!("User is not logged in; resolving profs requires authentication");
 
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "success": false,
-                "message": "Resolving profs requires authentication",
-            })),
-        );
+        return Err(ApiError::Unauthorized);
     }
 
-    let maybe_prof = db::prof::get_prof(&mut tx, prof_req.prof_id).await;
-
-    let Ok(prof) = maybe_prof else {
-        log::error!("Failed to get prof: {}", maybe_prof.unwrap_err());
-
-        // TODO return a more specific error message (e.g. 404 if prof doesn't exist)
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get prof",
-            })),
-        );
-    };
-
-    let Some(prof) = prof else {
+    let Some(prof) = db::prof::get_prof(&mut tx, prof_req.prof_id).await? else {
         log::info!("Prof {} does not exist", prof_req.prof_id);
 
-        // TODO return a more specific error message (e.g. 404 if prof doesn't exist)
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "success": false,
-                "message": "Prof does not exist",
-            })),
-        );
+        return Err(ApiError::NotFound("Prof does not exist".into()));
     };
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "prof": prof,
-        })),
-    )
+    Ok(Json(ProfRes {
+        success: true,
+        prof,
+    }))
 }
 
 async fn get_upload(
@@ -583,8 +641,8 @@ async fn get_upload(
     Ok((upload, uploader_name.unwrap_or_default()))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-struct PurchaseInfoItem {
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct PurchaseInfoItem {
     #[sqlx(flatten)]
     purchase: Purchase,
     #[sqlx(flatten)]
@@ -594,26 +652,36 @@ struct PurchaseInfoItem {
     most_recent_available_file: File,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurchasedUploadsRes {
+    pub success: bool,
+    pub purchase_info_items: Vec<PurchaseInfoItem>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/purchased-uploads",
+    tag = "get",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, body = PurchasedUploadsRes),
+        (status = 401, description = "Not logged in", body = ErrorRes),
+    ),
+)]
 async fn handle_get_purchased_uploads(
     Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<PurchasedUploadsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     log::info!("Get purchased uploads for user {}", current_user_id);
 
     if current_user_id.is_nil() {
         log::info!("User is not logged in; resolving purchased uploads requires authentication");
 
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "success": false,
-                "message": "Resolving purchased uploads requires authentication",
-            })),
-        );
+        return Err(ApiError::Unauthorized);
     }
 
-    let maybe_purchases: anyhow::Result<Vec<PurchaseInfoItem>> = sqlx::query_as(
+    let purchases: Vec<PurchaseInfoItem> = sqlx::query_as(
         "
         SELECT
             p.user_id,
@@ -621,6 +689,7 @@ async fn handle_get_purchased_uploads(
             p.ecs_spent,
             p.purchase_date,
             p.rating,
+            p.review,
             u.id,
             u.upload_name,
             u.description,
@@ -655,6 +724,10 @@ async fn handle_get_purchased_uploads(
                     files f
                 WHERE
                     f.upload_id = u.id
+                    AND (
+                        f.deleted_at IS NULL
+                        OR $2
+                    )
                 ORDER BY
                     f.revision_at DESC
                 LIMIT
@@ -662,6 +735,10 @@ async fn handle_get_purchased_uploads(
             ) f ON TRUE
         WHERE
             p.user_id = $1
+            AND (
+                u.deleted_at IS NULL
+                OR $2
+            )
         ORDER BY
             p.purchase_date DESC,
             u.upload_date DESC,
@@ -670,29 +747,77 @@ async fn handle_get_purchased_uploads(
         ",
     )
     .bind(&current_user_id)
+    .bind(CONF.purchaserskeepdeleted) // Deleted uploads stay listed if their buyers keep access
     .fetch_all(&mut *tx)
     .await
-    .context("Failed to get purchases");
+    .context("Failed to get purchases")?;
 
-    let Ok(purchases) = maybe_purchases else {
-        log::error!("Failed to get purchases: {}", maybe_purchases.unwrap_err());
+    tx.commit().await?;
 
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": "Failed to get purchases",
-            })),
-        );
-    };
+    Ok(Json(PurchasedUploadsRes {
+        success: true,
+        purchase_info_items: purchases,
+    }))
+}
 
-    tx.commit().await.unwrap();
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SearchReq {
+    /// The search terms, in web search syntax (quotes, `or`, and `-` are supported)
+    pub query: String,
 
-    (
-        StatusCode::OK,
-        Json(json!({
-            "success": true,
-            "purchase_info_items": purchases,
-        })),
-    )
+    #[serde(flatten)]
+    pub filter: db::search::UploadFilter,
+
+    /// The maximum number of results per category
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchRes {
+    pub success: bool,
+    pub uploads: Vec<UploadSearchResult>,
+    pub courses: Vec<CourseSearchResult>,
+    pub profs: Vec<ProfSearchResult>,
+}
+
+/// Handles full-text searches across uploads, courses and profs
+#[utoipa::path(
+    put,
+    path = "/api/v1/get/search",
+    tag = "get",
+    request_body = SearchReq,
+    responses(
+        (status = 200, body = SearchRes),
+        (status = 400, description = "Empty search query", body = ErrorRes),
+    ),
+)]
+async fn handle_get_search(Json(req): Json<SearchReq>) -> ApiResult<Json<SearchRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let query = req.query.trim();
+
+    if query.is_empty() {
+        return Err(bad_request("The search query must not be empty"));
+    }
+
+    log::info!("Search for {query:?}");
+
+    let limit = req
+        .limit
+        .unwrap_or(db::DEFAULT_PAGE_SIZE)
+        .clamp(1, db::MAX_PAGE_SIZE);
+
+    let uploads = db::search::search_uploads(&mut tx, query, &req.filter, limit).await?;
+    let courses =
+        db::search::search_courses(&mut tx, query, req.filter.university_id, limit).await?;
+    let profs = db::search::search_profs(&mut tx, query, limit).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(SearchRes {
+        success: true,
+        uploads,
+        courses,
+        profs,
+    }))
 }
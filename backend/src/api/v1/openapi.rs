@@ -0,0 +1,212 @@
+//! The API description (`OpenAPI` document), generated from the handlers and their request & response types
+
+use axum::Json;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::error::ErrorRes;
+
+use super::{
+    action, auth, content, course, ecs, emails, get, profs, totp, university, users, SuccessRes,
+    SESSION_COOKIE_NAME,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Egiraffe API",
+        description = "Most endpoints use `PUT` with a JSON body, even if they only read data. \
+                       Failed requests are answered with an `ErrorRes`, whose `code` doesn't change between versions.",
+    ),
+    paths(
+        auth::handle_login,
+        auth::handle_register,
+        auth::handle_logout,
+        auth::handle_activate,
+        auth::handle_resend_activation,
+        auth::handle_request_password_reset,
+        auth::handle_reset_password,
+        get::handle_get_courses,
+        get::handle_get_uploads,
+        get::handle_get_universities,
+        get::handle_get_me,
+        get::handle_get_file,
+        get::handle_get_files_of_upload,
+        get::handle_get_upload_reviews,
+        get::handle_get_prof,
+        get::handle_get_my_ecs,
+        get::handle_get_purchased_uploads,
+        get::handle_get_search,
+        get::handle_get_sessions,
+        action::handle_do_upload,
+        action::handle_do_me,
+        action::handle_do_file,
+        action::handle_do_purchase,
+        action::handle_do_rate,
+        action::handle_do_revoke_session,
+        action::handle_do_delete_upload,
+        action::handle_do_delete_file,
+        totp::handle_enroll,
+        totp::handle_confirm,
+        totp::handle_disable,
+        totp::handle_regenerate_recovery_codes,
+        emails::handle_get_emails,
+        emails::handle_add_email,
+        emails::handle_remove_email,
+        emails::handle_set_primary_email,
+        course::handle_create_course,
+        course::handle_replace_course,
+        profs::handle_create_prof,
+        profs::handle_replace_prof,
+        content::handle_modify_upload,
+        content::handle_get_upload_edits,
+        content::handle_modify_file,
+        content::handle_get_moderation_queue,
+        content::handle_moderate_files,
+        content::handle_get_all_uploads,
+        content::handle_get_all_files,
+        content::handle_get_file_duplicates,
+        content::download_file_as_mod,
+        content::handle_get_deleted,
+        content::handle_delete_upload,
+        content::handle_delete_file,
+        content::handle_restore_upload,
+        content::handle_restore_file,
+        ecs::handle_get_user_balance,
+        ecs::handle_create_system_transaction,
+        users::handle_get_users,
+        users::handle_get_legacy_password_report,
+        users::handle_search_users,
+        users::handle_set_user_role,
+        users::handle_suspend_user,
+        users::handle_unsuspend_user,
+        users::handle_logout_user,
+        users::handle_delete_user,
+        users::handle_get_registration_exceptions,
+        users::handle_add_registration_exception,
+        users::handle_remove_registration_exception,
+        university::handle_create_university,
+        university::handle_replace_university,
+        university::handle_delete_university,
+    ),
+    components(schemas(ErrorRes, SuccessRes)),
+    modifiers(&SessionCookie),
+    tags(
+        (name = "auth", description = "Logging in & out, registration, and password resets"),
+        (name = "get", description = "Reading data; most endpoints work without logging in"),
+        (name = "do", description = "Changes by logged-in users"),
+        (name = "mod", description = "Managing content, for moderators"),
+        (name = "admin", description = "Managing users, ECs & universities, for admins"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Adds the session cookie, which the endpoints needing a login refer to as `session_cookie`
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(SESSION_COOKIE_NAME))),
+        );
+    }
+}
+
+pub async fn handle_get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, path::Path};
+
+    use super::*;
+
+    /// Routes which are deliberately left out of the API description
+    const UNDOCUMENTED_HANDLERS: &[&str] = &[
+        "api_greeting",
+        "handle_demo_protected_route",
+        "handle_get_openapi",
+    ];
+
+    /// Collects the names of the handlers in the `.route(...)` calls of a source file, outside of tests
+    fn routed_handlers(source: &str) -> Vec<String> {
+        let code: String = source
+            .split("#[cfg(test)]")
+            .next()
+            .unwrap()
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        code.split(".route(")
+            .skip(1)
+            .map(|call| {
+                // Skip the path, then take what the first method router (e.g. `put(...)`) is given
+                let after_path = call.splitn(3, '"').nth(2).expect("route without a path");
+                let handler = after_path
+                    .split_once('(')
+                    .and_then(|(_, rest)| rest.split_once(')'))
+                    .expect("route without a handler")
+                    .0;
+
+                handler.rsplit("::").next().unwrap().trim().to_owned()
+            })
+            .collect()
+    }
+
+    fn collect_routed_handlers(dir: &Path, handlers: &mut Vec<(String, String)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+
+            if path.is_dir() {
+                collect_routed_handlers(&path, handlers);
+            } else if path.extension().is_some_and(|extension| extension == "rs") {
+                let source = fs::read_to_string(&path).unwrap();
+                handlers.extend(
+                    routed_handlers(&source)
+                        .into_iter()
+                        .map(|handler| (path.display().to_string(), handler)),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let operation_ids: HashSet<&str> = openapi["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|path_item| path_item.as_object().unwrap().values())
+            .filter_map(|operation| operation["operationId"].as_str())
+            .collect();
+
+        let mut handlers = Vec::new();
+        collect_routed_handlers(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/api"),
+            &mut handlers,
+        );
+        assert!(!handlers.is_empty(), "no routes found");
+
+        let undocumented: Vec<_> = handlers
+            .iter()
+            .filter(|(_, handler)| {
+                !UNDOCUMENTED_HANDLERS.contains(&handler.as_str())
+                    && !operation_ids.contains(handler.as_str())
+            })
+            .collect();
+
+        assert!(
+            undocumented.is_empty(),
+            "routes missing from `ApiDoc` (add `#[utoipa::path]` and list them in `paths(...)`): {undocumented:?}"
+        );
+    }
+}
@@ -1,18 +1,17 @@
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
     Json, Router,
 };
-use serde::Deserialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::api_greeting,
+    api::{api_greeting, v1::SuccessRes},
     data::Prof,
     db::{self, DB_POOL},
+    error::ApiResult,
 };
 
 pub fn routes() -> Router {
@@ -22,55 +21,57 @@ pub fn routes() -> Router {
         .route("/replace", put(handle_replace_prof))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateProfReq {
     pub name: String,
 }
 
-async fn handle_create_prof(Json(prof): Json<CreateProfReq>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreateProfRes {
+    pub success: bool,
+    pub prof: Prof,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/profs/create",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = CreateProfReq,
+    responses((status = 200, body = CreateProfRes)),
+)]
+async fn handle_create_prof(Json(prof): Json<CreateProfReq>) -> ApiResult<Json<CreateProfRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     let prof = Prof {
         id: Uuid::new_v4(),
         name: prof.name,
     };
 
-    let db_action_result = db::prof::create_prof(&mut tx, &prof).await;
+    db::prof::create_prof(&mut tx, &prof).await?;
 
-    tx.commit().await.unwrap(); // TODO check if we really need a transaction here
+    tx.commit().await?; // TODO check if we really need a transaction here
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
-    }
-
-    (
-        StatusCode::OK,
-        Json(json!({ "success": true, "prof": prof })),
-    )
+    Ok(Json(CreateProfRes {
+        success: true,
+        prof,
+    }))
 }
 
-async fn handle_replace_prof(Json(prof): Json<Prof>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
-
-    let db_action_result = db::prof::update_prof(&mut tx, &prof).await;
+#[utoipa::path(
+    put,
+    path = "/api/v1/mod/profs/replace",
+    tag = "mod",
+    security(("session_cookie" = [])),
+    request_body = Prof,
+    responses((status = 200, body = SuccessRes)),
+)]
+async fn handle_replace_prof(Json(prof): Json<Prof>) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
-    }
+    db::prof::update_prof(&mut tx, &prof).await?;
 
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+    tx.commit().await?;
 
-    (StatusCode::OK, Json(json!({ "success": true })))
+    Ok(Json(SuccessRes { success: true }))
 }
@@ -0,0 +1,338 @@
+//! Two-factor authentication with time-based one-time passwords (TOTP, RFC 6238)
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{routing::put, Extension, Json, Router};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgTransaction;
+use totp_rs::{Algorithm, Secret, TOTP};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::v1::{auth::reject_login, client::ClientInfo, AuthLevel, SuccessRes},
+    conf::CONF,
+    db::{self, DB_POOL},
+    error::{ApiError, ApiResult, ErrorRes},
+    util::bad_request,
+};
+
+/// Shown as the account's provider in authenticator apps
+const ISSUER: &str = "Egiraffe";
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Recovery codes use lowercase letters & digits, without the easily confused ones
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/enroll", put(handle_enroll))
+        .route("/confirm", put(handle_confirm))
+        .route("/disable", put(handle_disable))
+        .route("/recovery-codes", put(handle_regenerate_recovery_codes))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TotpCodeReq {
+    /// A code from the authenticator, or (where allowed) a recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TotpEnrollRes {
+    pub success: bool,
+    /// The shared secret, base32-encoded, for entering it manually
+    pub secret: String,
+    /// The same secret as an `otpauth://` URI, usually shown as a QR code
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryCodesRes {
+    pub success: bool,
+    /// Each code can be used once instead of a code from the authenticator; they're only shown now
+    pub recovery_codes: Vec<String>,
+}
+
+/// Start enrolling an authenticator, which has to be confirmed with a code before it's used
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/totp/enroll",
+    tag = "do",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, body = TotpEnrollRes),
+        (status = 409, description = "Two-factor authentication is already enabled", body = ErrorRes),
+    ),
+)]
+pub async fn handle_enroll(
+    Extension(current_user_id): Extension<Uuid>,
+) -> ApiResult<Json<TotpEnrollRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let user = db::user::get_user_by_id(&mut tx, current_user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    if user.totp_secret.is_some() {
+        return Err(ApiError::Conflict(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    // 160 bits, as recommended by RFC 4226
+    let secret: [u8; 20] = rand::random();
+    let totp = make_totp(secret.to_vec(), user.emails[0].clone())?;
+
+    db::totp::set_pending_secret(&mut tx, current_user_id, &totp.get_secret_base32()).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(TotpEnrollRes {
+        success: true,
+        secret: totp.get_secret_base32(),
+        otpauth_uri: totp.get_url(),
+    }))
+}
+
+/// Enable TOTP once the user has shown their authenticator produces valid codes
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/totp/confirm",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = TotpCodeReq,
+    responses(
+        (status = 200, description = "Enabled; comes with the first recovery codes", body = RecoveryCodesRes),
+        (status = 400, description = "No enrollment in progress, or invalid code", body = ErrorRes),
+    ),
+)]
+pub async fn handle_confirm(
+    Extension(current_user_id): Extension<Uuid>,
+    Json(req): Json<TotpCodeReq>,
+) -> ApiResult<Json<RecoveryCodesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let state = db::totp::get_totp_state(&mut tx, current_user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    let Some(pending_secret) = state.pending_secret else {
+        return Err(bad_request("No enrollment in progress"));
+    };
+
+    if !verify_totp_code(&mut tx, current_user_id, &pending_secret, req.code.trim()).await? {
+        return Err(ApiError::InvalidTotpCode);
+    }
+
+    db::totp::confirm_pending_secret(&mut tx, current_user_id).await?;
+
+    let recovery_codes = new_recovery_codes(&mut tx, current_user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Enabled TOTP for user {current_user_id}");
+
+    Ok(Json(RecoveryCodesRes {
+        success: true,
+        recovery_codes,
+    }))
+}
+
+/// Turn two-factor authentication off, confirmed with a code or a recovery code
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/totp/disable",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = TotpCodeReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 400, description = "Not enabled, or invalid code", body = ErrorRes),
+        (status = 403, description = "Moderators & admins have to keep it enabled", body = ErrorRes),
+        (status = 429, description = "Too many failed attempts", body = ErrorRes),
+    ),
+)]
+pub async fn handle_disable(
+    Extension(current_user_id): Extension<Uuid>,
+    client: ClientInfo,
+    Json(req): Json<TotpCodeReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let user = db::user::get_user_by_id(&mut tx, current_user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    if CONF.requiretotpformods && user.user_role >= AuthLevel::Moderator {
+        return Err(ApiError::TotpMandatory);
+    }
+
+    let Some(secret) = user.totp_secret else {
+        return Err(bad_request("Two-factor authentication is not enabled"));
+    };
+
+    // Wrong codes count towards the login lockout, so a stolen session can't be used to guess them
+    let email = &user.emails[0];
+    if db::login_throttle::is_locked_out(&mut tx, email, client.ip).await? {
+        return Err(too_many_attempts());
+    }
+
+    if !verify_second_factor(&mut tx, current_user_id, &secret, &req.code).await? {
+        return Err(reject_login(tx, email, &client, ApiError::InvalidTotpCode).await);
+    }
+
+    db::totp::disable_totp(&mut tx, current_user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Disabled TOTP for user {current_user_id}");
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+/// Replace the recovery codes of a user, e.g. once most of them have been used
+#[utoipa::path(
+    put,
+    path = "/api/v1/do/totp/recovery-codes",
+    tag = "do",
+    security(("session_cookie" = [])),
+    request_body = TotpCodeReq,
+    responses(
+        (status = 200, description = "The old recovery codes no longer work", body = RecoveryCodesRes),
+        (status = 400, description = "Not enabled, or invalid code", body = ErrorRes),
+        (status = 429, description = "Too many failed attempts", body = ErrorRes),
+    ),
+)]
+pub async fn handle_regenerate_recovery_codes(
+    Extension(current_user_id): Extension<Uuid>,
+    client: ClientInfo,
+    Json(req): Json<TotpCodeReq>,
+) -> ApiResult<Json<RecoveryCodesRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let state = db::totp::get_totp_state(&mut tx, current_user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    let Some(secret) = state.secret else {
+        return Err(bad_request("Two-factor authentication is not enabled"));
+    };
+
+    // Wrong codes count towards the login lockout, see `handle_disable`
+    let user = db::user::get_user_by_id(&mut tx, current_user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let email = &user.emails[0];
+    if db::login_throttle::is_locked_out(&mut tx, email, client.ip).await? {
+        return Err(too_many_attempts());
+    }
+
+    if !verify_second_factor(&mut tx, current_user_id, &secret, &req.code).await? {
+        return Err(reject_login(tx, email, &client, ApiError::InvalidTotpCode).await);
+    }
+
+    let recovery_codes = new_recovery_codes(&mut tx, current_user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(RecoveryCodesRes {
+        success: true,
+        recovery_codes,
+    }))
+}
+
+/// Checks a code from the authenticator or a recovery code of a user with TOTP enabled.
+///
+/// Valid codes are used up, so the transaction has to be committed for that to stick.
+pub async fn verify_second_factor(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    secret: &str,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let code = code.trim();
+
+    if code.len() == 6 && code.bytes().all(|c| c.is_ascii_digit()) {
+        verify_totp_code(tx, user_id, secret, code).await
+    } else {
+        db::totp::use_recovery_code(tx, user_id, &normalize_recovery_code(code)).await
+    }
+}
+
+/// Checks a code from the authenticator, allowing one step of clock drift in either direction
+async fn verify_totp_code(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    secret: &str,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let totp = make_totp(Secret::Encoded(secret.into()).to_bytes()?, String::new())?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let current_step = now / totp.step;
+
+    let Some(step) = (current_step.saturating_sub(1)..=current_step + 1)
+        .find(|step| totp.check(code, step * totp.step))
+    else {
+        return Ok(false);
+    };
+
+    // Each code may only be used once, even within its time window
+    db::totp::use_time_step(tx, user_id, i64::try_from(step)?).await
+}
+
+fn too_many_attempts() -> ApiError {
+    ApiError::TooManyRequests("Too many wrong codes; try again later".into())
+}
+
+fn make_totp(secret: Vec<u8>, account_name: String) -> anyhow::Result<TOTP> {
+    // 6 digits every 30 seconds with SHA-1 is what every authenticator app supports;
+    // clock drift is handled when checking codes
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        30,
+        secret,
+        Some(ISSUER.into()),
+        account_name,
+    )?)
+}
+
+/// Create & store a new set of recovery codes, returning them to be shown to the user once
+async fn new_recovery_codes(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| random_recovery_code())
+        .collect();
+
+    db::totp::replace_recovery_codes(tx, user_id, &codes).await?;
+
+    // Shown as `abcde-fghjk`, which is easier to write down
+    Ok(codes
+        .into_iter()
+        .map(|code| format!("{}-{}", &code[..5], &code[5..]))
+        .collect())
+}
+
+fn random_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+
+    // 10 characters of 31 possible ones are about 50 bits
+    (0..10)
+        .map(|_| char::from(RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())]))
+        .collect()
+}
+
+/// Recovery codes are accepted with any case, spacing & dashes
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
@@ -1,32 +1,38 @@
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
     Json, Router,
 };
-use serde::Deserialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
     api::api_greeting,
-    data::{Course, OwnedUniversity, RgbColor},
+    data::{OwnedUniversity, RgbColor},
     db::{self, DB_POOL},
+    error::{ApiError, ApiResult, ErrorRes},
+    util::bad_request,
 };
 
+use super::SuccessRes;
+
 pub fn routes() -> Router {
     Router::new()
         .route("/", get(api_greeting).post(api_greeting).put(api_greeting))
         .route("/create", put(handle_create_university))
         .route("/replace", put(handle_replace_university))
+        .route("/delete", put(handle_delete_university))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateUniversityReq {
     pub full_name: String,
     pub mid_name: String,
     pub short_name: String,
+
+    /// The domains of the university's email addresses, e.g. `tugraz.at`
     pub email_domain_names: Vec<String>,
     pub homepage_url: String,
     pub cms_url: String,
@@ -34,12 +40,115 @@ pub struct CreateUniversityReq {
     pub text_color: RgbColor,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreateUniversityRes {
+    pub success: bool,
+    pub id: Uuid,
+}
+
+/// Trims & lowercases where it makes sense, and checks every field (but not for clashes)
+fn normalize_university(university: &mut OwnedUniversity) -> ApiResult<()> {
+    for (field, value, max_length) in [
+        ("full name", &mut university.full_name, 100),
+        ("mid name", &mut university.mid_name, 50),
+        ("short name", &mut university.short_name, 50),
+    ] {
+        *value = value.trim().to_owned();
+
+        if value.is_empty() {
+            return Err(bad_request(format!("The {field} is empty")));
+        }
+
+        if value.chars().count() > max_length {
+            return Err(bad_request(format!(
+                "The {field} is longer than {max_length} characters"
+            )));
+        }
+    }
+
+    for domain in &mut university.email_domain_names {
+        *domain = domain.trim().trim_end_matches('.').to_lowercase();
+
+        if !is_valid_domain(domain) {
+            return Err(bad_request(format!("Invalid email domain: {domain}")));
+        }
+    }
+
+    university.email_domain_names.sort_unstable();
+    university.email_domain_names.dedup();
+
+    if university.email_domain_names.is_empty() {
+        return Err(bad_request("A university needs at least one email domain"));
+    }
+
+    for (field, url) in [
+        ("homepage", &mut university.homepage_url),
+        ("CMS", &mut university.cms_url),
+    ] {
+        *url = url.trim().to_owned();
+
+        let is_valid = url.len() <= 200
+            && Url::parse(url).is_ok_and(|url| {
+                matches!(url.scheme(), "http" | "https") && url.host_str().is_some()
+            });
+
+        if !is_valid {
+            return Err(bad_request(format!("Invalid {field} URL: {url}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether this is a lowercase domain name with at least two labels, like `tugraz.at`
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<_> = domain.split('.').collect();
+
+    domain.len() <= 100
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+        // Top-level domains aren't numeric, which rules out IP addresses
+        && !labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Makes sure no other university has the same names or claims one of the email domains
+async fn check_for_clashes(
+    tx: &mut sqlx::PgTransaction<'_>,
+    university: &OwnedUniversity,
+) -> ApiResult<()> {
+    match db::university::find_clashing_university(tx, university).await? {
+        Some(other) => Err(ApiError::Conflict(
+            format!("{other} already has one of these names or email domains").into(),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/university/create",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = CreateUniversityReq,
+    responses(
+        (status = 200, body = CreateUniversityRes),
+        (status = 400, description = "An empty name, or an invalid email domain or URL", body = ErrorRes),
+        (status = 409, description = "Another university has one of the names or email domains", body = ErrorRes),
+    ),
+)]
 async fn handle_create_university(
     Json(university): Json<CreateUniversityReq>,
-) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+) -> ApiResult<Json<CreateUniversityRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    let university = OwnedUniversity {
+    let mut university = OwnedUniversity {
         id: Uuid::nil(), // This will be set by the database
         full_name: university.full_name,
         mid_name: university.mid_name,
@@ -51,40 +160,79 @@ async fn handle_create_university(
         text_color: university.text_color,
     };
 
-    let db_action_result = db::university::create_university(&mut tx, university).await;
+    normalize_university(&mut university)?;
+    check_for_clashes(&mut tx, &university).await?;
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
-    }
+    let id = db::university::create_university(&mut tx, university).await?;
+
+    tx.commit().await?;
 
-    let id = db_action_result.unwrap();
-    tx.commit().await.unwrap();
+    log::info!("Created university {id}");
 
-    (StatusCode::OK, Json(json!({ "success": true, "id": id })))
+    Ok(Json(CreateUniversityRes { success: true, id }))
 }
 
-async fn handle_replace_university(Json(course): Json<Course>) -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/university/replace",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = OwnedUniversity,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 400, description = "An empty name, or an invalid email domain or URL", body = ErrorRes),
+        (status = 404, description = "No such university", body = ErrorRes),
+        (status = 409, description = "Another university has one of the names or email domains", body = ErrorRes),
+    ),
+)]
+async fn handle_replace_university(
+    Json(mut university): Json<OwnedUniversity>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
-    let db_action_result = db::course::replace_course(&mut tx, course).await;
+    normalize_university(&mut university)?;
+    check_for_clashes(&mut tx, &university).await?;
 
-    if let Err(error) = db_action_result {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "message": error.to_string(),
-            })),
-        );
+    let id = university.id;
+    if !db::university::replace_university(&mut tx, university).await? {
+        return Err(ApiError::NotFound("No such university".into()));
     }
 
-    tx.commit().await.unwrap();
+    tx.commit().await?;
+
+    log::info!("Replaced university {id}");
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteUniversityReq {
+    pub id: Uuid,
+}
+
+/// Deletes a university which has neither courses nor email addresses
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/university/delete",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = DeleteUniversityReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such university", body = ErrorRes),
+        (status = 409, description = "Courses or email addresses still belong to the university", body = ErrorRes),
+    ),
+)]
+async fn handle_delete_university(
+    Json(req): Json<DeleteUniversityReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    db::university::delete_university(&mut tx, req.id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Deleted university {}", req.id);
 
-    (StatusCode::OK, Json(json!({ "success": true })))
+    Ok(Json(SuccessRes { success: true }))
 }
@@ -1,43 +1,122 @@
 use anyhow::Context;
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::IntoResponse,
     routing::{get, put},
-    Json, Router,
+    Extension, Json, Router,
 };
-use chrono::NaiveDateTime;
+use chrono::{Duration, Utc};
+use email_address::EmailAddress;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sqlx::PgPool;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
     api::api_greeting,
     data::{Prof, RedactedUser},
-    db::{self, DB_POOL},
+    db::{
+        self,
+        registration_exception::RegistrationException,
+        suspension::Suspension,
+        user::{AdminUser, LegacyPasswordReport},
+        DB_POOL,
+    },
+    error::{ApiError, ApiResult, ErrorRes},
+    util::bad_request,
 };
 
+use super::{AuthLevel, SuccessRes};
+
+/// How many users a search returns at most
+const MAX_SEARCHED_USERS: i64 = 100;
+
+/// How long a reason for a suspension may be, in characters
+const MAX_REASON_LENGTH: usize = 2000;
+
+/// Suspensions longer than this (ten years, in hours) have to be indefinite
+const MAX_SUSPENSION_HOURS: u32 = 10 * 365 * 24;
+
 pub fn routes() -> Router {
     Router::new()
         .route("/", get(api_greeting).post(api_greeting).put(api_greeting))
         .route("/get-users", put(handle_get_users))
+        .route(
+            "/legacy-password-report",
+            put(handle_get_legacy_password_report),
+        )
+        .route("/search-users", put(handle_search_users))
+        .route("/set-user-role", put(handle_set_user_role))
+        .route("/suspend-user", put(handle_suspend_user))
+        .route("/unsuspend-user", put(handle_unsuspend_user))
+        .route("/logout-user", put(handle_logout_user))
+        .route("/delete-user", put(handle_delete_user))
+        .route(
+            "/registration-exceptions",
+            put(handle_get_registration_exceptions),
+        )
+        .route(
+            "/add-registration-exception",
+            put(handle_add_registration_exception),
+        )
+        .route(
+            "/remove-registration-exception",
+            put(handle_remove_registration_exception),
+        )
 }
 
-pub async fn handle_get_users() -> impl IntoResponse {
-    let mut tx = (*DB_POOL.get().unwrap()).begin().await.unwrap();
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LegacyPasswordReportRes {
+    pub success: bool,
+    pub report: LegacyPasswordReport,
+}
+
+/// Reports how many users still have a password hash from the old Egiraffe,
+/// to decide when to force password resets for the rest
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/legacy-password-report",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = LegacyPasswordReportRes)),
+)]
+pub async fn handle_get_legacy_password_report() -> ApiResult<Json<LegacyPasswordReportRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let report = db::user::get_legacy_password_report(&mut tx).await?;
+
+    Ok(Json(LegacyPasswordReportRes {
+        success: true,
+        report,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsersRes {
+    pub success: bool,
+    pub users: Vec<RedactedUser>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/get-users",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = UsersRes)),
+)]
+pub async fn handle_get_users() -> ApiResult<Json<UsersRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
 
     // Select totp_secret as totp_enabled (check if it's null or if the string has length > 0)
     // TODO consider going back to a macro for this one
     // let users = sqlx::query_as!(
-    let users: anyhow::Result<Vec<RedactedUser>> = sqlx::query_as(
+    let users: Vec<RedactedUser> = sqlx::query_as(
         // RedactedUser,
         "
             SELECT
                 id,
                 first_names,
                 last_name,
-                true AS totp_enabled,
+                totp_secret IS NOT NULL AS totp_enabled,
                 user_role
             FROM
                 users
@@ -45,19 +124,480 @@ pub async fn handle_get_users() -> impl IntoResponse {
     )
     .fetch_all(&mut *tx)
     .await
-    .context("Failed to fetch users");
+    .context("Failed to fetch users")?;
 
-    let Ok(users) = users else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "success": false, "message": "Failed to fetch users" })),
-        );
-    };
+    tx.commit().await?; // TODO check if we really need a transaction here
+
+    Ok(Json(UsersRes {
+        success: true,
+        users,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SearchUsersReq {
+    /// Part of an email address, a name, or a nick; an empty query lists all users
+    pub query: String,
+
+    /// Also list deleted accounts
+    #[serde(default)]
+    pub include_deleted: bool,
+
+    /// How many users to return, at most 100
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchUsersRes {
+    pub success: bool,
+    pub users: Vec<AdminUser>,
+}
+
+/// Finds users by their email addresses, names, or nick, ignoring case
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/search-users",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = SearchUsersReq,
+    responses((status = 200, body = SearchUsersRes)),
+)]
+pub async fn handle_search_users(
+    Json(req): Json<SearchUsersReq>,
+) -> ApiResult<Json<SearchUsersRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let limit = req
+        .limit
+        .unwrap_or(MAX_SEARCHED_USERS)
+        .clamp(1, MAX_SEARCHED_USERS);
+
+    let users =
+        db::user::search_users(&mut tx, req.query.trim(), req.include_deleted, limit).await?;
+
+    Ok(Json(SearchUsersRes {
+        success: true,
+        users,
+    }))
+}
+
+/// Makes sure the user an admin acts on exists, hasn't been deleted, and isn't the admin themselves
+async fn check_target_user(
+    tx: &mut sqlx::PgTransaction<'_>,
+    user_id: Uuid,
+    current_user_id: Uuid,
+) -> ApiResult<()> {
+    if user_id == current_user_id {
+        return Err(ApiError::Forbidden(
+            "Admins can't do this to their own account".into(),
+        ));
+    }
+
+    if user_id.is_nil() {
+        return Err(ApiError::Forbidden("The nil user can't be changed".into()));
+    }
+
+    match db::user::is_user_deleted(tx, user_id).await? {
+        None => Err(ApiError::NotFound("No such user".into())),
+        Some(true) => Err(ApiError::Conflict("The user has been deleted".into())),
+        Some(false) => Ok(()),
+    }
+}
 
-    tx.commit().await.unwrap(); // TODO check if we really need a transaction here
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetUserRoleReq {
+    pub user_id: Uuid,
 
-    return (
-        StatusCode::OK,
-        Json(json!({ "success": true, "users": users })),
+    /// 1 for users, 2 for moderators, 3 for admins
+    pub user_role: i16,
+}
+
+/// Changes the role of another user
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/set-user-role",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = SetUserRoleReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 400, description = "No such role", body = ErrorRes),
+        (status = 403, description = "Admins can't change their own role", body = ErrorRes),
+        (status = 404, description = "No such user", body = ErrorRes),
+        (status = 409, description = "The user has been deleted", body = ErrorRes),
+    ),
+)]
+pub async fn handle_set_user_role(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<SetUserRoleReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    if !(AuthLevel::RegularUser..=AuthLevel::Admin).contains(&req.user_role) {
+        return Err(bad_request("No such role"));
+    }
+
+    check_target_user(&mut tx, req.user_id, current_user_id).await?;
+
+    db::user::set_user_role(&mut tx, req.user_id, req.user_role).await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "Admin {current_user_id} set the role of user {} to {}",
+        req.user_id,
+        req.user_role
+    );
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SuspendUserReq {
+    pub user_id: Uuid,
+
+    /// Shown to the user when they try to log in
+    pub reason: String,
+
+    /// How long the suspension lasts; leave it out to suspend the user indefinitely
+    pub duration_hours: Option<u32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SuspendUserRes {
+    pub success: bool,
+    pub suspension: Suspension,
+}
+
+/// Suspends another user and logs them out; they can't log in until the suspension ends or is lifted
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/suspend-user",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = SuspendUserReq,
+    responses(
+        (status = 200, body = SuspendUserRes),
+        (status = 400, description = "A missing reason, or an invalid duration", body = ErrorRes),
+        (status = 403, description = "Admins can't suspend themselves", body = ErrorRes),
+        (status = 404, description = "No such user", body = ErrorRes),
+        (status = 409, description = "The user is suspended already, or has been deleted", body = ErrorRes),
+    ),
+)]
+pub async fn handle_suspend_user(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<SuspendUserReq>,
+) -> ApiResult<Json<SuspendUserRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let reason = req.reason.trim();
+
+    if reason.is_empty() {
+        return Err(bad_request("Suspensions need a reason"));
+    }
+
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(bad_request("The reason is too long"));
+    }
+
+    if req
+        .duration_hours
+        .is_some_and(|hours| hours == 0 || hours > MAX_SUSPENSION_HOURS)
+    {
+        return Err(bad_request(
+            "Suspensions last between an hour and ten years, or indefinitely",
+        ));
+    }
+
+    check_target_user(&mut tx, req.user_id, current_user_id).await?;
+
+    // Changing a suspension takes lifting it first, so it's not shortened by accident
+    if db::suspension::get_active_suspension(&mut tx, req.user_id)
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::Conflict("The user is suspended already".into()));
+    }
+
+    let suspended_until = req
+        .duration_hours
+        .map(|hours| Utc::now().naive_utc() + Duration::hours(hours.into()));
+
+    let suspension = db::suspension::suspend_user(
+        &mut tx,
+        req.user_id,
+        reason,
+        suspended_until,
+        current_user_id,
+    )
+    .await?;
+
+    db::session::delete_sessions_of_user(&mut tx, req.user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "Admin {current_user_id} suspended user {} until {suspended_until:?}",
+        req.user_id
     );
+
+    Ok(Json(SuspendUserRes {
+        success: true,
+        suspension,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UserIdReq {
+    pub user_id: Uuid,
+}
+
+/// Lifts the suspension of a user before it ends
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/unsuspend-user",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = UserIdReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such user", body = ErrorRes),
+        (status = 409, description = "The user isn't suspended, or has been deleted", body = ErrorRes),
+    ),
+)]
+pub async fn handle_unsuspend_user(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<UserIdReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    check_target_user(&mut tx, req.user_id, current_user_id).await?;
+
+    if db::suspension::lift_suspensions(&mut tx, req.user_id, current_user_id).await? == 0 {
+        return Err(ApiError::Conflict("The user isn't suspended".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!(
+        "Admin {current_user_id} lifted the suspension of user {}",
+        req.user_id
+    );
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+/// Logs another user out on all their devices
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/logout-user",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = UserIdReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "No such user", body = ErrorRes),
+        (status = 409, description = "The user has been deleted", body = ErrorRes),
+    ),
+)]
+pub async fn handle_logout_user(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<UserIdReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    check_target_user(&mut tx, req.user_id, current_user_id).await?;
+
+    db::session::delete_sessions_of_user(&mut tx, req.user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!("Admin {current_user_id} logged out user {}", req.user_id);
+
+    Ok(Json(SuccessRes { success: true }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeleteUserRes {
+    pub success: bool,
+
+    /// How many uploads now belong to the nil user
+    pub reassigned_uploads: u64,
+}
+
+/// Deletes another user's account and logs them out; their uploads stay, but belong to the nil user
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/delete-user",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = UserIdReq,
+    responses(
+        (status = 200, body = DeleteUserRes),
+        (status = 403, description = "Admins can't delete themselves", body = ErrorRes),
+        (status = 404, description = "No such user", body = ErrorRes),
+        (status = 409, description = "The user has been deleted already", body = ErrorRes),
+    ),
+)]
+pub async fn handle_delete_user(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<UserIdReq>,
+) -> ApiResult<Json<DeleteUserRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    check_target_user(&mut tx, req.user_id, current_user_id).await?;
+
+    let reassigned_uploads =
+        db::user::soft_delete_user(&mut tx, req.user_id, current_user_id).await?;
+    db::session::delete_sessions_of_user(&mut tx, req.user_id).await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "Admin {current_user_id} deleted user {}, reassigning {reassigned_uploads} uploads",
+        req.user_id
+    );
+
+    Ok(Json(DeleteUserRes {
+        success: true,
+        reassigned_uploads,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RegistrationExceptionsRes {
+    pub success: bool,
+    pub exceptions: Vec<RegistrationException>,
+}
+
+/// Lists the addresses which may register although their domain doesn't belong to a university
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/registration-exceptions",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses((status = 200, body = RegistrationExceptionsRes)),
+)]
+pub async fn handle_get_registration_exceptions() -> ApiResult<Json<RegistrationExceptionsRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let exceptions = db::registration_exception::get_registration_exceptions(&mut tx).await?;
+
+    Ok(Json(RegistrationExceptionsRes {
+        success: true,
+        exceptions,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddRegistrationExceptionReq {
+    pub address: String,
+
+    /// The university to associate the address with; without one,
+    /// the address still gets the university of its domain, if there is one
+    pub university_id: Option<Uuid>,
+
+    /// Why the exception was made, for other admins
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AddRegistrationExceptionRes {
+    pub success: bool,
+    pub exception: RegistrationException,
+}
+
+/// Lets an address register although its domain doesn't belong to a university
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/add-registration-exception",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = AddRegistrationExceptionReq,
+    responses(
+        (status = 200, body = AddRegistrationExceptionRes),
+        (status = 400, description = "An invalid address", body = ErrorRes),
+        (status = 404, description = "No such university", body = ErrorRes),
+        (status = 409, description = "The address is an exception already", body = ErrorRes),
+    ),
+)]
+pub async fn handle_add_registration_exception(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<AddRegistrationExceptionReq>,
+) -> ApiResult<Json<AddRegistrationExceptionRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let address = req.address.trim();
+    if !EmailAddress::is_valid(address) {
+        return Err(ApiError::EmailInvalid(address.into()));
+    }
+
+    if let Some(university_id) = req.university_id {
+        if db::university::get_university(&mut tx, university_id)
+            .await?
+            .is_none()
+        {
+            return Err(ApiError::NotFound("No such university".into()));
+        }
+    }
+
+    let note = req.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+
+    let Some(exception) = db::registration_exception::add_registration_exception(
+        &mut tx,
+        address,
+        req.university_id,
+        note,
+        current_user_id,
+    )
+    .await?
+    else {
+        return Err(ApiError::Conflict(
+            "The address is an exception already".into(),
+        ));
+    };
+
+    tx.commit().await?;
+
+    log::info!("Admin {current_user_id} added a registration exception for {address}");
+
+    Ok(Json(AddRegistrationExceptionRes {
+        success: true,
+        exception,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RemoveRegistrationExceptionReq {
+    pub address: String,
+}
+
+/// Removes an address from the exceptions; accounts which registered with it are kept
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/users/remove-registration-exception",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body = RemoveRegistrationExceptionReq,
+    responses(
+        (status = 200, body = SuccessRes),
+        (status = 404, description = "The address isn't an exception", body = ErrorRes),
+    ),
+)]
+pub async fn handle_remove_registration_exception(
+    Extension(current_user_id): Extension<Uuid>, // Get the user ID from the session
+    Json(req): Json<RemoveRegistrationExceptionReq>,
+) -> ApiResult<Json<SuccessRes>> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    let address = req.address.trim();
+    if !db::registration_exception::remove_registration_exception(&mut tx, address).await? {
+        return Err(ApiError::NotFound("The address isn't an exception".into()));
+    }
+
+    tx.commit().await?;
+
+    log::info!("Admin {current_user_id} removed the registration exception for {address}");
+
+    Ok(Json(SuccessRes { success: true }))
 }
@@ -3,6 +3,7 @@ use std::{ops::Deref, sync::Arc};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 // #[derive(Debug, Serialize, Deserialize)]
@@ -36,7 +37,7 @@ pub struct User {
     pub user_role: i16,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct RedactedUser {
     pub id: Uuid,
     pub first_names: Option<String>,
@@ -129,7 +130,7 @@ pub struct University<'a> {
 }
 
 // HACK this should not exist twice
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct OwnedUniversity {
     pub id: Uuid,
     pub full_name: String,
@@ -154,7 +155,7 @@ pub struct DbRgbColor {
     b: i8,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RgbColor {
     pub r: u8,
     pub g: u8,
@@ -181,7 +182,7 @@ impl From<RgbColor> for DbRgbColor {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Course {
     pub id: Uuid,
     pub name: String,
@@ -190,7 +191,7 @@ pub struct Course {
     pub held_at: Uuid,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Upload {
     pub id: Uuid,
     pub name: String,
@@ -212,22 +213,36 @@ pub struct Upload {
     pub held_by: Option<Uuid>, // TODO consider adding resolved values for faster API times
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An upload together with the aggregate of its buyers' ratings
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RatedUpload {
+    #[serde(flatten)]
+    pub upload: Upload,
+
+    /// The mean rating of this upload, or `None` if nobody has rated it yet
+    pub rating_mean: Option<f64>,
+    pub rating_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Prof {
     pub id: Uuid,
     pub name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Purchase {
     pub user_id: Uuid,
     pub upload_id: Uuid,
     pub ecs_spent: i16,
     pub purchase_date: NaiveDateTime,
+    /// The buyer's rating of the upload, from 1 to 5 stars
     pub rating: Option<i16>,
+    /// The buyer's review of the upload, only possible together with a rating
+    pub review: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct File {
     pub id: Uuid,
     pub name: String,
@@ -243,7 +258,7 @@ pub struct File {
     pub approval_mod: bool,
 }
 
-#[derive(sqlx::Type, Debug, Serialize, Deserialize, Clone)]
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[sqlx(type_name = "upload_type_enum", rename_all = "snake_case")]
 pub enum UploadType {
     Exam,
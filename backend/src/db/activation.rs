@@ -0,0 +1,236 @@
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{Duration, NaiveDateTime, Utc};
+use justerror::Error;
+use sha2::{Digest, Sha256};
+use sqlx::PgTransaction;
+use uuid::Uuid;
+
+use crate::{conf::CONF, data::Token};
+
+/// How long a user has to wait before another activation mail is sent to the same address
+pub const RESEND_COOLDOWN: Duration = Duration::minutes(5);
+
+#[Error]
+pub enum ActivationError {
+    InvalidToken,
+    TokenExpired,
+    /// Another account has verified the same address in the meantime
+    AddressTaken,
+    QueryError(anyhow::Error),
+}
+
+/// An email address which still has to be verified, and who it belongs to
+#[derive(Debug, Clone)]
+pub struct UnverifiedEmail {
+    pub id: Uuid,
+    pub address: String,
+    pub first_names: String,
+    pub last_name: String,
+    /// When the last activation token for this address was created, if any
+    pub last_token_created_at: Option<NaiveDateTime>,
+}
+
+/// Only the hash of a token is stored, so a leaked database can't be used to activate accounts
+pub(super) fn hash_token(token: &str) -> String {
+    // 32 bytes of hash are 43 characters of base64, just like the tokens themselves
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a new activation token for an email, replacing any earlier ones.
+///
+/// Returns the token to send to the user; the database only knows its hash.
+pub async fn create_activation_token(
+    tx: &mut PgTransaction<'_>,
+    email_id: Uuid,
+) -> anyhow::Result<String> {
+    // 32 bytes of random data
+    let token: Token = rand::random();
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(token);
+
+    let now = Utc::now().naive_utc();
+    let expires_at = now + Duration::days(i64::from(CONF.acitvationlinkvalidityperiod));
+
+    sqlx::query!(
+        "
+        DELETE FROM
+            email_verification
+        WHERE
+            belongs_to_email = $1
+        ",
+        email_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete old activation tokens")?;
+
+    sqlx::query!(
+        "
+        INSERT INTO
+            email_verification (token, belongs_to_email, expires_at, created_at)
+        VALUES
+            ($1, $2, $3, $4)
+        ",
+        hash_token(&token),
+        email_id,
+        expires_at,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to create activation token")?;
+
+    Ok(token)
+}
+
+/// Mark the email an activation token was sent to as verified, and use up the token.
+///
+/// Returns the id of the verified email.
+///
+/// # Errors
+///
+/// - [`ActivationError::InvalidToken`] if there is no such token (or it was already used)
+/// - [`ActivationError::TokenExpired`] if the token is too old
+/// - [`ActivationError::AddressTaken`] if another account has verified the address concurrently
+/// - [`ActivationError::QueryError`] if any query fails
+pub async fn activate_email(
+    tx: &mut PgTransaction<'_>,
+    token: &str,
+) -> Result<Uuid, ActivationError> {
+    let verification = sqlx::query!(
+        "
+        DELETE FROM
+            email_verification
+        WHERE
+            token = $1
+        RETURNING
+            belongs_to_email,
+            expires_at
+        ",
+        hash_token(token),
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get activation token")
+    .map_err(ActivationError::QueryError)?
+    .ok_or(ActivationError::InvalidToken)?;
+
+    if verification.expires_at < Utc::now().naive_utc() {
+        return Err(ActivationError::TokenExpired);
+    }
+
+    // Disabled emails must stay disabled, so only unverified ones are changed
+    let verified_address = sqlx::query_scalar!(
+        r#"
+        UPDATE
+            emails
+        SET
+            status = 'verified'
+        WHERE
+            id = $1
+            AND status = 'unverified'
+        RETURNING
+            address AS "address!"
+        "#,
+        verification.belongs_to_email,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|err| match err {
+        // Verified addresses are unique, see `idx_emails_verified_address`
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            ActivationError::AddressTaken
+        }
+        err => {
+            ActivationError::QueryError(anyhow::Error::new(err).context("Failed to verify email"))
+        }
+    })?;
+
+    // Other accounts the address was added to lose it now
+    if let Some(address) = verified_address {
+        super::email::release_unverified_address(tx, &address, verification.belongs_to_email)
+            .await
+            .context("Failed to release email from other accounts")
+            .map_err(ActivationError::QueryError)?;
+    }
+
+    Ok(verification.belongs_to_email)
+}
+
+/// Mark an email as verified without sending an activation mail, e.g. for imported users
+pub async fn mark_email_verified(tx: &mut PgTransaction<'_>, email_id: Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            emails
+        SET
+            status = 'verified'
+        WHERE
+            id = $1
+        ",
+        email_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to verify email")?;
+
+    Ok(())
+}
+
+/// Checks whether the primary email of a user has been verified (and isn't disabled)
+pub async fn is_primary_email_verified(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        SELECT
+            emails.status = 'verified' AS "verified!"
+        FROM
+            users
+            INNER JOIN emails ON users.primary_email = emails.id
+        WHERE
+            users.id = $1
+        "#,
+        user_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get email status")
+    .map(|row| row.is_some_and(|row| row.verified))
+}
+
+/// Get an unverified primary email address of a user, to (re-)send an activation mail to
+pub async fn get_unverified_email(
+    tx: &mut PgTransaction<'_>,
+    address: &str,
+) -> anyhow::Result<Option<UnverifiedEmail>> {
+    sqlx::query_as!(
+        UnverifiedEmail,
+        r#"
+        SELECT
+            emails.id,
+            emails.address AS "address!",
+            COALESCE(users.first_names, '') AS "first_names!",
+            COALESCE(users.last_name, '') AS "last_name!",
+            (
+                SELECT
+                    MAX(created_at)
+                FROM
+                    email_verification
+                WHERE
+                    belongs_to_email = emails.id
+            ) AS last_token_created_at
+        FROM
+            emails
+            INNER JOIN users ON users.primary_email = emails.id
+        WHERE
+            emails.address = LOWER($1)
+            AND emails.status = 'unverified'
+        "#,
+        address,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get unverified email")
+}
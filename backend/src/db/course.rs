@@ -1,5 +1,6 @@
 use anyhow::Context;
 use sqlx::PgTransaction;
+use uuid::Uuid;
 
 use crate::data::Course;
 
@@ -46,6 +47,29 @@ pub async fn replace_course(mut tx: &mut PgTransaction<'_>, course: Course) -> a
     Ok(())
 }
 
+pub async fn get_course(
+    tx: &mut PgTransaction<'_>,
+    course_id: Uuid,
+) -> anyhow::Result<Option<Course>> {
+    sqlx::query_as!(
+        Course,
+        "
+        SELECT
+            id,
+            course_name AS name,
+            held_at
+        FROM
+            courses
+        WHERE
+            id = $1
+        ",
+        course_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get course")
+}
+
 pub async fn get_courses(mut tx: &mut PgTransaction<'_>) -> anyhow::Result<Vec<Course>> {
     sqlx::query_as!(
         Course,
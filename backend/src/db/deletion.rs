@@ -0,0 +1,560 @@
+//! Soft deletion of uploads & files
+//!
+//! Deleted rows stay in the database, so moderators can restore them; the blobs of deleted files
+//! are removed by the purge job (see [`crate::storage::spawn_purge_job`]) once nothing needs them anymore.
+
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use justerror::Error;
+use serde::Serialize;
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::data::{File, Upload, UploadType};
+
+/// A deleted upload, for moderators to decide on restoring it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeletedUpload {
+    #[serde(flatten)]
+    pub upload: Upload,
+    pub deleted_at: NaiveDateTime,
+
+    /// The uploader or moderator who deleted the upload
+    pub deleted_by: Option<Uuid>,
+}
+
+/// A deleted file, for moderators to decide on restoring it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeletedFile {
+    #[serde(flatten)]
+    pub file: File,
+    pub deleted_at: NaiveDateTime,
+
+    /// The uploader or moderator who deleted the file (or its upload)
+    pub deleted_by: Option<Uuid>,
+
+    /// Whether the file's content is gone for good, so it can't be restored anymore
+    pub blob_purged: bool,
+}
+
+#[Error]
+pub enum RestoreError {
+    NotFound,
+    NotDeleted,
+    /// A file can't be restored on its own while its upload is deleted
+    UploadDeleted,
+    /// The file's content has been purged already
+    BlobPurged,
+    QueryError(anyhow::Error),
+}
+
+/// Delete an upload together with its files; returns `false` if there is no such (undeleted) upload
+pub async fn delete_upload(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+    deleted_by: Uuid,
+) -> anyhow::Result<bool> {
+    let now = Utc::now().naive_utc();
+
+    let result = sqlx::query!(
+        "
+        UPDATE
+            uploads
+        SET
+            deleted_at = $2,
+            deleted_by = $3
+        WHERE
+            id = $1
+            AND deleted_at IS NULL
+        ",
+        upload_id,
+        now,
+        deleted_by,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete upload")?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    // The files share the upload's timestamp, which tells them apart from files deleted earlier
+    // when restoring the upload
+    sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            deleted_at = $2,
+            deleted_by = $3
+        WHERE
+            upload_id = $1
+            AND deleted_at IS NULL
+        ",
+        upload_id,
+        now,
+        deleted_by,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete files of upload")?;
+
+    Ok(true)
+}
+
+/// Delete a single file; returns `false` if there is no such (undeleted) file
+pub async fn delete_file(
+    tx: &mut PgTransaction<'_>,
+    file_id: Uuid,
+    deleted_by: Uuid,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            deleted_at = $2,
+            deleted_by = $3
+        WHERE
+            id = $1
+            AND deleted_at IS NULL
+        ",
+        file_id,
+        Utc::now().naive_utc(),
+        deleted_by,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete file")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get the uploader of a file, unless the file or its upload has been deleted
+pub async fn get_uploader_of_file(
+    tx: &mut PgTransaction<'_>,
+    file_id: Uuid,
+) -> anyhow::Result<Option<Uuid>> {
+    sqlx::query_scalar!(
+        "
+        SELECT
+            uploads.uploader
+        FROM
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
+        WHERE
+            files.id = $1
+            AND files.deleted_at IS NULL
+            AND uploads.deleted_at IS NULL
+        ",
+        file_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get uploader of file")
+}
+
+/// Restore a deleted upload, together with the files deleted along with it (unless purged already).
+///
+/// Files which were deleted on their own before stay deleted. Returns the number of restored files.
+pub async fn restore_upload(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+) -> Result<u64, RestoreError> {
+    let deleted_at = sqlx::query_scalar!(
+        "
+        SELECT
+            deleted_at
+        FROM
+            uploads
+        WHERE
+            id = $1
+        ",
+        upload_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get upload")
+    .map_err(RestoreError::QueryError)?
+    .ok_or(RestoreError::NotFound)?
+    .ok_or(RestoreError::NotDeleted)?;
+
+    let restored_files = sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            deleted_at = NULL,
+            deleted_by = NULL
+        WHERE
+            upload_id = $1
+            AND deleted_at = $2
+            AND blob_purged_at IS NULL
+        ",
+        upload_id,
+        deleted_at,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to restore files of upload")
+    .map_err(RestoreError::QueryError)?;
+
+    sqlx::query!(
+        "
+        UPDATE
+            uploads
+        SET
+            deleted_at = NULL,
+            deleted_by = NULL
+        WHERE
+            id = $1
+        ",
+        upload_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to restore upload")
+    .map_err(RestoreError::QueryError)?;
+
+    Ok(restored_files.rows_affected())
+}
+
+/// Restore a single deleted file of an upload which is still around
+pub async fn restore_file(tx: &mut PgTransaction<'_>, file_id: Uuid) -> Result<(), RestoreError> {
+    let row = sqlx::query!(
+        "
+        SELECT
+            files.deleted_at,
+            files.blob_purged_at,
+            uploads.deleted_at AS upload_deleted_at
+        FROM
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
+        WHERE
+            files.id = $1
+        ",
+        file_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get file")
+    .map_err(RestoreError::QueryError)?
+    .ok_or(RestoreError::NotFound)?;
+
+    if row.deleted_at.is_none() {
+        return Err(RestoreError::NotDeleted);
+    }
+
+    if row.upload_deleted_at.is_some() {
+        return Err(RestoreError::UploadDeleted);
+    }
+
+    if row.blob_purged_at.is_some() {
+        return Err(RestoreError::BlobPurged);
+    }
+
+    sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            deleted_at = NULL,
+            deleted_by = NULL
+        WHERE
+            id = $1
+        ",
+        file_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to restore file")
+    .map_err(RestoreError::QueryError)?;
+
+    Ok(())
+}
+
+/// Whether a file, or the upload it belongs to, has been deleted
+pub async fn is_file_deleted(tx: &mut PgTransaction<'_>, file_id: Uuid) -> anyhow::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            files.deleted_at IS NOT NULL
+            OR uploads.deleted_at IS NOT NULL AS "is_deleted!"
+        FROM
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
+        WHERE
+            files.id = $1
+        "#,
+        file_id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to check if file is deleted")
+}
+
+/// Get the IDs of the deleted files of an upload
+pub async fn get_deleted_file_ids(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+) -> anyhow::Result<Vec<Uuid>> {
+    sqlx::query_scalar!(
+        "
+        SELECT
+            id
+        FROM
+            files
+        WHERE
+            upload_id = $1
+            AND deleted_at IS NOT NULL
+        ",
+        upload_id
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get deleted files of upload")
+}
+
+/// Get all deleted uploads, the most recently deleted first
+pub async fn get_deleted_uploads(tx: &mut PgTransaction<'_>) -> anyhow::Result<Vec<DeletedUpload>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            upload_name,
+            description,
+            price,
+            uploader,
+            upload_date,
+            last_modified_date,
+            associated_date,
+            upload_type AS "upload_type: UploadType",
+            belongs_to,
+            held_by,
+            deleted_at AS "deleted_at!",
+            deleted_by
+        FROM
+            uploads
+        WHERE
+            deleted_at IS NOT NULL
+        ORDER BY
+            deleted_at DESC,
+            id
+        "#,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get deleted uploads")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DeletedUpload {
+            upload: Upload {
+                id: row.id,
+                name: row.upload_name,
+                description: row.description,
+                price: row.price,
+                uploader: row.uploader,
+                upload_date: row.upload_date,
+                last_modified_date: row.last_modified_date,
+                associated_date: row.associated_date,
+                upload_type: row.upload_type,
+                belongs_to: row.belongs_to,
+                held_by: row.held_by,
+            },
+            deleted_at: row.deleted_at,
+            deleted_by: row.deleted_by,
+        })
+        .collect())
+}
+
+/// Get all deleted files, including those of deleted uploads, the most recently deleted first
+pub async fn get_deleted_files(tx: &mut PgTransaction<'_>) -> anyhow::Result<Vec<DeletedFile>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            mime_type,
+            size,
+            sha3_256,
+            revision_at,
+            upload_id,
+            approval_uploader,
+            approval_mod,
+            deleted_at AS "deleted_at!",
+            deleted_by,
+            blob_purged_at IS NOT NULL AS "blob_purged!"
+        FROM
+            files
+        WHERE
+            deleted_at IS NOT NULL
+        ORDER BY
+            deleted_at DESC,
+            id
+        "#,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get deleted files")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DeletedFile {
+            file: File {
+                id: row.id,
+                name: row.name,
+                mime_type: row.mime_type,
+                size: row.size,
+                sha3_256: row.sha3_256,
+                revision_at: row.revision_at,
+                upload_id: row.upload_id,
+                approval_uploader: row.approval_uploader,
+                approval_mod: row.approval_mod,
+            },
+            deleted_at: row.deleted_at,
+            deleted_by: row.deleted_by,
+            blob_purged: row.blob_purged,
+        })
+        .collect())
+}
+
+/// Get the hashes of the blobs which may be purged (or check just one, if given).
+///
+/// A blob may be purged once every file referencing it has been deleted before `deleted_before`;
+/// if `keep_purchased` is set, blobs of purchased uploads are kept for their buyers.
+pub async fn get_purgeable_blobs(
+    tx: &mut PgTransaction<'_>,
+    deleted_before: NaiveDateTime,
+    keep_purchased: bool,
+    only_sha3_256: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            deleted.sha3_256
+        FROM
+            files AS deleted
+        WHERE
+            deleted.deleted_at IS NOT NULL
+            AND deleted.blob_purged_at IS NULL
+            AND (
+                $3::text IS NULL
+                OR deleted.sha3_256 = $3
+            )
+        GROUP BY
+            deleted.sha3_256
+        HAVING
+            MAX(deleted.deleted_at) < $1
+            AND NOT EXISTS (
+                SELECT
+                    1
+                FROM
+                    files
+                WHERE
+                    files.sha3_256 = deleted.sha3_256
+                    AND files.deleted_at IS NULL
+            )
+            AND NOT (
+                $2
+                AND EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        files
+                        INNER JOIN purchases ON files.upload_id = purchases.upload_id
+                    WHERE
+                        files.sha3_256 = deleted.sha3_256
+                )
+            )
+        "#,
+        deleted_before,
+        keep_purchased,
+        only_sha3_256,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get purgeable blobs")
+}
+
+/// Record that a blob has been purged, so the deleted files using it can't be restored anymore.
+///
+/// The blob itself is deleted from storage afterwards, see [`get_purged_blobs`].
+pub async fn mark_blob_purged(tx: &mut PgTransaction<'_>, sha3_256: &str) -> anyhow::Result<()> {
+    let now = Utc::now().naive_utc();
+
+    sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            blob_purged_at = $2
+        WHERE
+            sha3_256 = $1
+            AND deleted_at IS NOT NULL
+            AND blob_purged_at IS NULL
+        ",
+        sha3_256,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to mark blob as purged")?;
+
+    sqlx::query!(
+        "
+        INSERT INTO
+            purged_blobs (sha3_256, purged_at)
+        VALUES
+            ($1, $2)
+        ON CONFLICT (sha3_256) DO
+        UPDATE
+        SET
+            purged_at = EXCLUDED.purged_at
+        ",
+        sha3_256,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to queue purged blob for deletion")?;
+
+    Ok(())
+}
+
+/// Get the hashes of the purged blobs which may still have to be deleted from storage
+pub async fn get_purged_blobs(tx: &mut PgTransaction<'_>) -> anyhow::Result<Vec<String>> {
+    sqlx::query_scalar!(
+        "
+        SELECT
+            sha3_256
+        FROM
+            purged_blobs
+        ORDER BY
+            purged_at
+        "
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get purged blobs")
+}
+
+/// Record that a purged blob is gone from storage (or in use again), so it's not deleted again
+pub async fn forget_purged_blob(tx: &mut PgTransaction<'_>, sha3_256: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        DELETE FROM
+            purged_blobs
+        WHERE
+            sha3_256 = $1
+        ",
+        sha3_256,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to forget purged blob")?;
+
+    Ok(())
+}
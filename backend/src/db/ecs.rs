@@ -9,15 +9,100 @@ use uuid::Uuid;
 /// - ECS the user has earned (when a purchased upload is owned by the user)
 /// - When a user has spent ECS on a purchase
 /// - When the system has given/taken ECS from the user (see table `system_ec_transaction`)
+///
+/// The balance is read from the `ec_balances` cache, which the database keeps in sync
+/// with the ledger using triggers (see migration `0002_ec_balances.sql`).
 pub async fn calculate_available_funds(
-    mut tx: &mut PgTransaction<'_>,
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<i64> {
+    let balance = sqlx::query_scalar!(
+        "
+        SELECT
+            balance
+        FROM
+            ec_balances
+        WHERE
+            user_id = $1
+        ",
+        user_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to calculate available funds")?;
+
+    // Users who never had any transactions don't have a row yet
+    Ok(balance.unwrap_or(0))
+}
+
+/// Calculates the amount of ECS the user has available to spend by scanning the whole ledger,
+/// bypassing the `ec_balances` cache
+pub async fn recalculate_available_funds(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<i64> {
+    sqlx::query_file_scalar!("src/db/sql/get_available_ecs.sql", user_id)
+        .fetch_one(&mut **tx)
+        .await
+        .context("Failed to recalculate available funds")?
+        .context("Ledger returned no balance")
+}
+
+/// Locks the balances of the given users until the end of the transaction,
+/// returning the available funds of the first one
+///
+/// Every transaction which changes balances must lock them through this function first, so
+/// concurrent transactions can't spend the same ECs twice. The rows are locked in a consistent
+/// order to avoid deadlocks between users buying each other's uploads.
+pub async fn lock_available_funds(
+    tx: &mut PgTransaction<'_>,
     user_id: Uuid,
-) -> anyhow::Result<f64> {
-    // FIXME figure out what data type should be returned for this query
-    // sqlx::query_file!("src/db/sql/get_available_ecs.sql", user_id,)
-    //     .fetch_one(&mut **tx)
-    //     .await
-    //     .map(|row| row.ecs_available)
-    //     .context("Failed to calculate available funds")
-    Ok(42.0) // FIXME this darn thing doesn't work
+    other_user_ids: &[Uuid],
+) -> anyhow::Result<i64> {
+    let mut user_ids = vec![user_id];
+    user_ids.extend_from_slice(other_user_ids);
+    user_ids.sort_unstable();
+    user_ids.dedup();
+
+    // Users who never had any transactions don't have a row to lock yet
+    sqlx::query!(
+        "
+        INSERT INTO
+            ec_balances (user_id)
+        SELECT
+            *
+        FROM
+            UNNEST($1::uuid[])
+        ON CONFLICT (user_id) DO NOTHING
+        ",
+        &user_ids,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to create balances")?;
+
+    let balances = sqlx::query!(
+        "
+        SELECT
+            user_id,
+            balance
+        FROM
+            ec_balances
+        WHERE
+            user_id = ANY($1)
+        ORDER BY
+            user_id
+        FOR UPDATE
+        ",
+        &user_ids,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to lock balances")?;
+
+    balances
+        .into_iter()
+        .find(|row| row.user_id == user_id)
+        .map(|row| row.balance)
+        .context("Balance vanished while locking it")
 }
@@ -0,0 +1,252 @@
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[sqlx(type_name = "email_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum EmailStatus {
+    Unverified,
+    Verified,
+    Disabled,
+}
+
+/// One of a user's email addresses
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UserEmail {
+    pub id: Uuid,
+    pub address: String,
+    pub status: EmailStatus,
+
+    /// Whether the user logs in with this address, and gets mails to it
+    pub is_primary: bool,
+
+    /// The university the address belongs to, if any
+    pub of_university: Option<Uuid>,
+}
+
+/// Get all email addresses of a user, the primary one first
+pub async fn get_emails_of_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Vec<UserEmail>> {
+    sqlx::query_as!(
+        UserEmail,
+        r#"
+        SELECT
+            emails.id,
+            emails.address AS "address!",
+            emails.status AS "status: EmailStatus",
+            emails.id = users.primary_email AS "is_primary!",
+            emails.of_university
+        FROM
+            emails
+            INNER JOIN users ON emails.belongs_to_user = users.id
+        WHERE
+            users.id = $1
+        ORDER BY
+            emails.id = users.primary_email DESC,
+            emails.address
+        "#,
+        user_id
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get emails of user")
+}
+
+/// Whether any user (including deleted ones) has this address, ignoring case.
+///
+/// Only verified and primary addresses count; an address which has merely been added to an account
+/// goes to whoever verifies it (or registers with it) first, see [`release_unverified_address`].
+pub async fn is_address_taken(tx: &mut PgTransaction<'_>, address: &str) -> anyhow::Result<bool> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT
+                    1
+                FROM
+                    emails
+                WHERE
+                    address = LOWER($1)
+                    AND (
+                        status = 'verified'
+                        OR EXISTS (
+                            SELECT
+                                1
+                            FROM
+                                users
+                            WHERE
+                                users.primary_email = emails.id
+                        )
+                    )
+            ) AS "taken!"
+        "#,
+        address
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to check if address is taken")
+}
+
+/// Remove an address from the accounts it was added to but not verified on, along with its
+/// verification tokens, once another email (`claimed_by`) has claimed it
+pub async fn release_unverified_address(
+    tx: &mut PgTransaction<'_>,
+    address: &str,
+    claimed_by: Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "
+        WITH released AS (
+            SELECT
+                id
+            FROM
+                emails
+            WHERE
+                address = LOWER($1)
+                AND id <> $2
+                AND status = 'unverified'
+                AND NOT EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        users
+                    WHERE
+                        users.primary_email = emails.id
+                )
+        ),
+        released_tokens AS (
+            DELETE FROM
+                email_verification
+            WHERE
+                belongs_to_email IN (
+                    SELECT
+                        id
+                    FROM
+                        released
+                )
+        )
+        DELETE FROM
+            emails
+        WHERE
+            id IN (
+                SELECT
+                    id
+                FROM
+                    released
+            )
+        ",
+        address,
+        claimed_by,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Add an unverified address to a user; returns its ID
+pub async fn add_email(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    address: &str,
+    of_university: Option<Uuid>,
+) -> anyhow::Result<Uuid> {
+    sqlx::query_scalar!(
+        "
+        INSERT INTO
+            emails (address, belongs_to_user, of_university, STATUS)
+        VALUES
+            (LOWER($1), $2, $3, 'unverified')
+        RETURNING
+            id
+        ",
+        address,
+        user_id,
+        of_university,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to add email")
+}
+
+/// Remove an address of a user, along with its verification tokens; the primary address can't be removed.
+///
+/// Returns `false` if the user has no such (non-primary) address.
+pub async fn remove_email(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    email_id: Uuid,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        "
+        DELETE FROM
+            email_verification
+        WHERE
+            belongs_to_email = $1
+        ",
+        email_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete verification tokens")?;
+
+    let result = sqlx::query!(
+        "
+        DELETE FROM
+            emails
+        WHERE
+            id = $1
+            AND belongs_to_user = $2
+            AND id <> (
+                SELECT
+                    primary_email
+                FROM
+                    users
+                WHERE
+                    id = $2
+            )
+        ",
+        email_id,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to remove email")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Make a verified address of a user their primary one; returns `false` if they have no such verified address
+pub async fn set_primary_email(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    email_id: Uuid,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            primary_email = emails.id
+        FROM
+            emails
+        WHERE
+            users.id = $1
+            AND emails.id = $2
+            AND emails.belongs_to_user = users.id
+            AND emails.status = 'verified'
+        ",
+        user_id,
+        email_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to set primary email")?;
+
+    Ok(result.rows_affected() > 0)
+}
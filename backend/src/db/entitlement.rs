@@ -0,0 +1,174 @@
+use anyhow::Context;
+use sqlx::PgTransaction;
+use uuid::Uuid;
+
+use crate::{api::v1::AuthLevel, conf::CONF, data::File};
+
+use super::deletion::is_file_deleted;
+
+/// Whether a user may download a file, and why (not)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAccess {
+    /// The user uploaded the file
+    Owner,
+    /// Moderators & admins may access every file the uploader has approved
+    Moderator,
+    /// The file belongs to an upload which costs nothing
+    FreeUpload,
+    /// The user has purchased the upload the file belongs to
+    Purchased,
+    /// The file lacks approval from a moderator and/or the uploader
+    NotApproved,
+    /// The file could be accessed after purchasing its upload
+    NotPurchased,
+    /// The file or its upload has been deleted
+    Deleted,
+}
+
+impl FileAccess {
+    pub fn is_granted(self) -> bool {
+        matches!(
+            self,
+            Self::Owner | Self::Moderator | Self::FreeUpload | Self::Purchased
+        )
+    }
+}
+
+/// What a user is entitled to regarding one upload, independent of its files' approvals
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct UploadEntitlements {
+    is_owner: bool,
+    is_moderator: bool,
+    is_free: bool,
+    has_purchased: bool,
+    is_deleted: bool,
+}
+
+impl UploadEntitlements {
+    /// Whether the upload has been deleted
+    pub fn is_deleted(self) -> bool {
+        self.is_deleted
+    }
+
+    /// Whether the user may still see deleted files of this upload (or the deleted upload itself)
+    fn keeps_deleted(self) -> bool {
+        self.is_moderator || (CONF.purchaserskeepdeleted && self.has_purchased)
+    }
+
+    /// Whether the upload has been deleted, and the user may not see it anymore
+    pub fn is_hidden(self) -> bool {
+        self.is_deleted && !self.keeps_deleted()
+    }
+
+    /// Decides whether the user may access a file of this upload
+    pub fn access_to(self, file: &File, is_file_deleted: bool) -> FileAccess {
+        // Deleted files are gone, except for moderators and (if configured) their earlier buyers
+        if (self.is_deleted || is_file_deleted) && !self.keeps_deleted() {
+            return FileAccess::Deleted;
+        }
+
+        // Uploaders can always get their own files, even unapproved ones
+        if self.is_owner {
+            return FileAccess::Owner;
+        }
+
+        // Nobody else may access a file before the uploader has agreed to publish it
+        if !file.approval_uploader {
+            return FileAccess::NotApproved;
+        }
+
+        if self.is_moderator {
+            return FileAccess::Moderator;
+        }
+
+        if !file.approval_mod {
+            return FileAccess::NotApproved;
+        }
+
+        if self.is_free {
+            FileAccess::FreeUpload
+        } else if self.has_purchased {
+            FileAccess::Purchased
+        } else {
+            FileAccess::NotPurchased
+        }
+    }
+}
+
+/// Gets the entitlements of a user (the nil UUID for anonymous users) regarding an upload;
+/// `None` if there's no such upload
+pub async fn get_upload_entitlements(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    upload_id: Uuid,
+) -> anyhow::Result<Option<UploadEntitlements>> {
+    let Some(row) = sqlx::query!(
+        r#"
+        SELECT
+            uploads.uploader = $1 AS "is_owner!",
+            uploads.price = 0 AS "is_free!",
+            COALESCE(
+                (
+                    SELECT
+                        user_role
+                    FROM
+                        users
+                    WHERE
+                        id = $1
+                ),
+                0::smallint
+            ) >= $3 AS "is_moderator!",
+            EXISTS (
+                SELECT
+                    1
+                FROM
+                    purchases
+                WHERE
+                    user_id = $1
+                    AND upload_id = uploads.id
+            ) AS "has_purchased!",
+            uploads.deleted_at IS NOT NULL AS "is_deleted!"
+        FROM
+            uploads
+        WHERE
+            id = $2
+        "#,
+        user_id,
+        upload_id,
+        AuthLevel::Moderator,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get entitlements")?
+    else {
+        return Ok(None);
+    };
+
+    // The nil UUID stands for anonymous users, but also owns the uploads of deleted users;
+    // anonymous users can't download anything, not even free uploads
+    let is_anonymous = user_id.is_nil();
+
+    Ok(Some(UploadEntitlements {
+        is_owner: row.is_owner && !is_anonymous,
+        is_moderator: row.is_moderator && !is_anonymous,
+        is_free: row.is_free && !is_anonymous,
+        has_purchased: row.has_purchased && !is_anonymous,
+        is_deleted: row.is_deleted,
+    }))
+}
+
+/// Decides whether a user (the nil UUID for anonymous users) may download a file;
+/// `None` if its upload doesn't exist
+pub async fn can_access_file(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    file: &File,
+) -> anyhow::Result<Option<FileAccess>> {
+    let Some(entitlements) = get_upload_entitlements(tx, user_id, file.upload_id).await? else {
+        return Ok(None);
+    };
+    let file_deleted = is_file_deleted(tx, file.id).await?;
+
+    Ok(Some(entitlements.access_to(file, file_deleted)))
+}
@@ -1,3 +1,4 @@
+use anyhow::Context;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgTransaction};
@@ -207,6 +208,8 @@ pub async fn get_all_files_and_join_upload(
         FROM
             files
             INNER JOIN uploads ON files.upload_id = uploads.id
+        WHERE
+            files.deleted_at IS NULL
         "#,
     )
     .fetch_all(&mut **tx)
@@ -245,46 +248,111 @@ pub async fn get_all_files_and_join_upload(
     Ok(file_upload_joins)
 }
 
-pub async fn get_upload_of_file(
-    mut tx: &mut PgTransaction<'_>,
+/// Gets all other (undeleted) files with the same content (i.e. the same hash) as the given file,
+/// together with the uploads they belong to
+///
+/// As files are stored by their hash, all of these share a single blob on disk.
+/// The oldest upload comes first, as it's the most likely to be the original.
+pub async fn get_files_with_same_content(
+    tx: &mut PgTransaction<'_>,
     file_id: Uuid,
-) -> anyhow::Result<Upload> {
-    let upload = sqlx::query_as!(
-        Upload,
+) -> anyhow::Result<Vec<(File, Upload)>> {
+    let duplicates = sqlx::query_as!(
+        FileUpload,
         r#"
         SELECT
-            id,
-            upload_name AS name,
-            description,
-            price,
-            uploader,
-            upload_date,
-            last_modified_date,
-            associated_date,
-            upload_type AS "upload_type: _",
-            belongs_to,
-            held_by
+            files.id AS file_id,
+            files.name AS file_name,
+            files.mime_type,
+            files.size,
+            files.sha3_256,
+            files.revision_at,
+            files.approval_uploader,
+            files.approval_mod,
+            uploads.id AS upload_id,
+            uploads.upload_name,
+            uploads.description,
+            uploads.price,
+            uploads.uploader,
+            uploads.upload_date,
+            uploads.last_modified_date,
+            uploads.associated_date,
+            uploads.upload_type AS "upload_type: _",
+            uploads.belongs_to,
+            uploads.held_by
         FROM
-            uploads
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
         WHERE
-            id = (
+            files.sha3_256 = (
                 SELECT
-                    upload_id
+                    sha3_256
                 FROM
                     files
                 WHERE
                     id = $1
             )
+            AND files.id <> $1
+            AND files.deleted_at IS NULL
+        ORDER BY
+            uploads.upload_date,
+            uploads.id,
+            files.revision_at
         "#,
         file_id
     )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get files with the same content")?
+    .into_iter()
+    .map(FileUpload::into_parts)
+    .collect();
+
+    Ok(duplicates)
+}
+
+/// Serializes storing & purging a blob until the end of the transaction, so the purge job can't
+/// remove a blob a new file is about to use
+pub async fn lock_blob(tx: &mut PgTransaction<'_>, sha3_256: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        SELECT
+            pg_advisory_xact_lock(hashtextextended($1, 0))
+        ",
+        sha3_256
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to lock blob")?;
+
+    Ok(())
+}
+
+/// Checks whether any file, deleted or not, still references the blob
+pub async fn is_blob_referenced(
+    tx: &mut PgTransaction<'_>,
+    sha3_256: &str,
+) -> anyhow::Result<bool> {
+    let is_referenced = sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT
+                    1
+                FROM
+                    files
+                WHERE
+                    sha3_256 = $1
+                    AND blob_purged_at IS NULL
+            ) AS "is_referenced!"
+        "#,
+        sha3_256
+    )
     .fetch_one(&mut **tx)
     .await
-    // .unwrap();
-    // .context("Failed to get upload of file")?;
-    ?;
+    .context("Failed to check whether the blob is referenced")?;
 
-    Ok(upload)
+    Ok(is_referenced)
 }
 
 // Oida
@@ -323,3 +391,34 @@ pub struct FileUpload {
     /// The ID of the prof that held the course this upload belongs to
     pub held_by: Option<Uuid>, // TODO consider adding resolved values for faster API times
 }
+
+impl FileUpload {
+    pub fn into_parts(self) -> (File, Upload) {
+        (
+            File {
+                id: self.file_id,
+                name: self.file_name,
+                mime_type: self.mime_type,
+                size: self.size,
+                sha3_256: self.sha3_256,
+                revision_at: self.revision_at,
+                upload_id: self.upload_id,
+                approval_uploader: self.approval_uploader,
+                approval_mod: self.approval_mod,
+            },
+            Upload {
+                id: self.upload_id,
+                name: self.upload_name,
+                description: self.description,
+                price: self.price,
+                uploader: self.uploader,
+                upload_date: self.upload_date,
+                last_modified_date: self.last_modified_date,
+                associated_date: self.associated_date,
+                upload_type: self.upload_type,
+                belongs_to: self.belongs_to,
+                held_by: self.held_by,
+            },
+        )
+    }
+}
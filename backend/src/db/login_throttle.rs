@@ -0,0 +1,138 @@
+use std::net::IpAddr;
+
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgTransaction;
+
+/// Failed attempts older than this are forgotten, which also ends a lockout
+pub const ATTEMPT_WINDOW: Duration = Duration::minutes(15);
+
+/// Guessing passwords for one account is stopped quickly
+pub const MAX_FAILURES_PER_ACCOUNT: i64 = 5;
+
+/// Many students share an IP address (e.g. in the university's network), so this is more lenient
+pub const MAX_FAILURES_PER_IP: i64 = 30;
+
+/// The length of the `email` column; longer emails are cut off, as no account has one anyway
+const MAX_EMAIL_LENGTH: usize = 500;
+
+/// Emails are compared case-insensitively, so changing the case doesn't get around a lockout
+fn normalize_email(email: &str) -> String {
+    email
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_EMAIL_LENGTH)
+        .collect()
+}
+
+/// Checks whether logins for this email, or from this IP address, are locked out for now.
+///
+/// Attempts for the same email wait here until the transaction ends, so parallel requests can't all
+/// pass the check before any of their failures has been recorded.
+pub async fn is_locked_out(
+    tx: &mut PgTransaction<'_>,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> anyhow::Result<bool> {
+    let since = Utc::now().naive_utc() - ATTEMPT_WINDOW;
+
+    sqlx::query!(
+        "
+        SELECT
+            pg_advisory_xact_lock(hashtext($1))
+        ",
+        normalize_email(email),
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to lock failed logins")?;
+
+    let failures = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (
+                WHERE
+                    email = $1
+            ) AS "account_failures!",
+            COUNT(*) FILTER (
+                WHERE
+                    ip = $2::text::inet
+            ) AS "ip_failures!"
+        FROM
+            failed_logins
+        WHERE
+            attempted_at >= $3
+            AND (
+                email = $1
+                OR ip = $2::text::inet
+            )
+        "#,
+        normalize_email(email),
+        ip.map(|ip| ip.to_string()),
+        since,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to count failed logins")?;
+
+    Ok(failures.account_failures >= MAX_FAILURES_PER_ACCOUNT
+        || failures.ip_failures >= MAX_FAILURES_PER_IP)
+}
+
+/// Remember a failed login attempt, and forget the ones which don't count anymore
+pub async fn record_failed_login(
+    tx: &mut PgTransaction<'_>,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> anyhow::Result<()> {
+    let now = Utc::now().naive_utc();
+
+    sqlx::query!(
+        "
+        DELETE FROM
+            failed_logins
+        WHERE
+            attempted_at < $1
+        ",
+        now - ATTEMPT_WINDOW,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete old failed logins")?;
+
+    sqlx::query!(
+        "
+        INSERT INTO
+            failed_logins (email, ip, attempted_at)
+        VALUES
+            ($1, $2::text::inet, $3)
+        ",
+        normalize_email(email),
+        ip.map(|ip| ip.to_string()),
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to record failed login")?;
+
+    Ok(())
+}
+
+/// Forget the failed attempts for an account once its user has logged in
+pub async fn clear_failed_logins(tx: &mut PgTransaction<'_>, email: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        DELETE FROM
+            failed_logins
+        WHERE
+            email = $1
+        ",
+        normalize_email(email),
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to clear failed logins")?;
+
+    Ok(())
+}
@@ -1,10 +1,21 @@
+pub mod activation;
 pub mod course;
+pub mod deletion;
 pub mod ecs;
+pub mod email;
+pub mod entitlement;
 pub mod file;
 pub mod init;
+pub mod login_throttle;
+pub mod moderation;
+pub mod password_reset;
 pub mod prof;
 pub mod purchase;
+pub mod registration_exception;
+pub mod search;
 pub mod session;
+pub mod suspension;
+pub mod totp;
 pub mod university;
 pub mod upload;
 pub mod user;
@@ -12,11 +23,12 @@ pub mod user;
 use crate::conf::CONF;
 use anyhow::Context;
 use once_cell::sync::OnceCell;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{
     postgres::PgPoolOptions, Acquire, Executor, PgConnection, PgTransaction, Pool, Postgres,
 };
 use tokio::fs::read_to_string;
+use utoipa::ToSchema;
 
 pub static DB_POOL: OnceCell<&'static sqlx::PgPool> = OnceCell::new();
 
@@ -65,8 +77,42 @@ pub async fn debug_insert_default_entries(mut tx: &mut PgTransaction<'_>) -> any
     Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
 pub enum SortOrder {
     Ascending,
     Descending,
 }
+
+/// How many items a page has if the client doesn't ask for a specific amount
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// The most items a client can get in one page
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+/// Keyset pagination parameters of a listing request
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Pagination {
+    /// The `next_cursor` of the previous page, or `None` for the first page
+    pub cursor: Option<String>,
+
+    /// The number of items per page, see [`DEFAULT_PAGE_SIZE`] and [`MAX_PAGE_SIZE`]
+    pub limit: Option<i64>,
+}
+
+impl Pagination {
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// One page of a keyset-paginated listing
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    /// Pass this as the cursor of the next request to get the following page;
+    /// `None` if this is the last page
+    pub next_cursor: Option<String>,
+}
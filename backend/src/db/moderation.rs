@@ -0,0 +1,237 @@
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::data::File;
+
+/// A moderator's decision about a file
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[sqlx(type_name = "moderation_decision", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationDecision {
+    Approved,
+    Rejected,
+}
+
+/// A file awaiting review, with the context a moderator needs to judge it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PendingFile {
+    #[serde(flatten)]
+    pub file: File,
+    pub upload_name: String,
+    pub course_id: Uuid,
+    pub course_name: String,
+    pub uploader_id: Uuid,
+    pub uploader_name: Option<String>,
+}
+
+/// A file a decision has been made about, with what's needed to let its uploader know
+#[derive(Debug, Clone)]
+pub struct DecidedFile {
+    pub file_name: String,
+    pub upload_name: String,
+    pub uploader_id: Uuid,
+    pub uploader_first_names: Option<String>,
+    pub uploader_last_name: Option<String>,
+    pub uploader_email: Option<String>,
+}
+
+/// Get the files neither approved nor rejected (nor deleted) yet, oldest first
+pub async fn get_pending_files(
+    tx: &mut PgTransaction<'_>,
+    limit: i64,
+) -> anyhow::Result<Vec<PendingFile>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            files.id,
+            files.name,
+            files.mime_type,
+            files.size,
+            files.sha3_256,
+            files.revision_at,
+            files.upload_id,
+            files.approval_uploader,
+            files.approval_mod,
+            uploads.upload_name,
+            uploads.uploader,
+            users.nick AS uploader_name,
+            courses.id AS course_id,
+            courses.course_name
+        FROM
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
+            INNER JOIN courses ON uploads.belongs_to = courses.id
+            INNER JOIN users ON uploads.uploader = users.id
+        WHERE
+            NOT files.approval_mod
+            AND NOT files.rejected
+            AND files.deleted_at IS NULL
+        ORDER BY
+            files.revision_at,
+            files.id
+        LIMIT
+            $1
+        "#,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get pending files")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| PendingFile {
+            file: File {
+                id: row.id,
+                name: row.name,
+                mime_type: row.mime_type,
+                size: row.size,
+                sha3_256: row.sha3_256,
+                revision_at: row.revision_at,
+                upload_id: row.upload_id,
+                approval_uploader: row.approval_uploader,
+                approval_mod: row.approval_mod,
+            },
+            upload_name: row.upload_name,
+            course_id: row.course_id,
+            course_name: row.course_name,
+            uploader_id: row.uploader,
+            uploader_name: row.uploader_name,
+        })
+        .collect())
+}
+
+pub async fn count_pending_files(tx: &mut PgTransaction<'_>) -> anyhow::Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            COUNT(*) AS "count!"
+        FROM
+            files
+        WHERE
+            NOT approval_mod
+            AND NOT rejected
+            AND deleted_at IS NULL
+        "#,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to count pending files")
+}
+
+/// Approve or reject files, and record who decided so, when, and why.
+///
+/// Returns the files which exist and haven't been deleted; the caller should check that all do.
+pub async fn decide_files(
+    tx: &mut PgTransaction<'_>,
+    file_ids: &[Uuid],
+    decision: ModerationDecision,
+    reason: Option<&str>,
+    moderator_id: Uuid,
+) -> anyhow::Result<Vec<DecidedFile>> {
+    let approved = decision == ModerationDecision::Approved;
+    let now: NaiveDateTime = Utc::now().naive_utc();
+
+    sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            approval_mod = $2,
+            rejected = NOT $2
+        WHERE
+            id = ANY($1)
+            AND deleted_at IS NULL
+        ",
+        file_ids,
+        approved,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to update files")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            file_moderations (file_id, decision, reason, decided_by, decided_at)
+        SELECT
+            id,
+            $2,
+            $3,
+            $4,
+            $5
+        FROM
+            files
+        WHERE
+            id = ANY($1)
+            AND deleted_at IS NULL
+        "#,
+        file_ids,
+        decision as ModerationDecision,
+        reason,
+        moderator_id,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to record moderation decisions")?;
+
+    sqlx::query_as!(
+        DecidedFile,
+        r#"
+        SELECT
+            files.name AS file_name,
+            uploads.upload_name,
+            users.id AS uploader_id,
+            users.first_names AS uploader_first_names,
+            users.last_name AS uploader_last_name,
+            emails.address AS uploader_email
+        FROM
+            files
+            INNER JOIN uploads ON files.upload_id = uploads.id
+            INNER JOIN users ON uploads.uploader = users.id
+            INNER JOIN emails ON users.primary_email = emails.id
+        WHERE
+            files.id = ANY($1)
+            AND files.deleted_at IS NULL
+        ORDER BY
+            users.id,
+            uploads.upload_name,
+            files.name
+        "#,
+        file_ids,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get decided files")
+}
+
+/// Changing the approval directly (without a decision) puts a rejected file back in the queue
+pub async fn set_mod_approval(
+    tx: &mut PgTransaction<'_>,
+    file_id: Uuid,
+    approval_mod: bool,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            files
+        SET
+            approval_mod = $1,
+            rejected = false
+        WHERE
+            id = $2
+        ",
+        approval_mod,
+        file_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to update file")?;
+
+    Ok(())
+}
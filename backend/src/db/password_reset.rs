@@ -0,0 +1,134 @@
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{Duration, NaiveDateTime, Utc};
+use justerror::Error;
+use sqlx::PgTransaction;
+use uuid::Uuid;
+
+use super::activation::hash_token;
+use crate::data::Token;
+
+/// How long a password reset link can be used
+pub const RESET_TOKEN_VALIDITY: Duration = Duration::hours(1);
+
+/// How long a user has to wait before another reset mail is sent to them
+pub const RESEND_COOLDOWN: Duration = Duration::minutes(5);
+
+#[Error]
+pub enum PasswordResetError {
+    InvalidToken,
+    TokenExpired,
+    QueryError(anyhow::Error),
+}
+
+/// Gets when the last password reset token of a user was created, if they have one
+pub async fn get_last_reset_request(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<NaiveDateTime>> {
+    sqlx::query!(
+        "
+        SELECT
+            MAX(created_at) AS last_created_at
+        FROM
+            password_reset
+        WHERE
+            of_user = $1
+        ",
+        user_id,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to get last password reset request")
+    .map(|row| row.last_created_at)
+}
+
+/// Create a new password reset token for a user, replacing any earlier ones.
+///
+/// Returns the token to send to the user; the database only knows its hash.
+pub async fn create_reset_token(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<String> {
+    // 32 bytes of random data
+    let token: Token = rand::random();
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(token);
+
+    let now = Utc::now().naive_utc();
+
+    delete_reset_tokens_of_user(tx, user_id).await?;
+
+    sqlx::query!(
+        "
+        INSERT INTO
+            password_reset (token, of_user, expires_at, created_at)
+        VALUES
+            ($1, $2, $3, $4)
+        ",
+        hash_token(&token),
+        user_id,
+        now + RESET_TOKEN_VALIDITY,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to create password reset token")?;
+
+    Ok(token)
+}
+
+/// Use up a password reset token, returning the id of the user it was created for.
+///
+/// # Errors
+///
+/// - [`PasswordResetError::InvalidToken`] if there is no such token (or it was already used)
+/// - [`PasswordResetError::TokenExpired`] if the token is too old
+/// - [`PasswordResetError::QueryError`] if any query fails
+pub async fn redeem_reset_token(
+    tx: &mut PgTransaction<'_>,
+    token: &str,
+) -> Result<Uuid, PasswordResetError> {
+    let reset = sqlx::query!(
+        "
+        DELETE FROM
+            password_reset
+        WHERE
+            token = $1
+        RETURNING
+            of_user,
+            expires_at
+        ",
+        hash_token(token),
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get password reset token")
+    .map_err(PasswordResetError::QueryError)?
+    .ok_or(PasswordResetError::InvalidToken)?;
+
+    if reset.expires_at < Utc::now().naive_utc() {
+        return Err(PasswordResetError::TokenExpired);
+    }
+
+    Ok(reset.of_user)
+}
+
+pub async fn delete_reset_tokens_of_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        DELETE FROM
+            password_reset
+        WHERE
+            of_user = $1
+        ",
+        user_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete password reset tokens")?;
+
+    Ok(())
+}
@@ -1,8 +1,19 @@
 use anyhow::Context;
+use chrono::NaiveDateTime;
+use justerror::Error;
+use serde::{Deserialize, Serialize};
 use sqlx::PgTransaction;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::data::Purchase;
+use crate::data::{Purchase, Upload};
+
+#[Error]
+pub enum PurchaseError {
+    AlreadyPurchased,
+    InsufficientFunds { balance: i64, price: i16 },
+    QueryError(anyhow::Error),
+}
 
 pub async fn get_purchase(
     mut tx: &mut PgTransaction<'_>,
@@ -17,7 +28,8 @@ pub async fn get_purchase(
             upload_id,
             ecs_spent,
             purchase_date,
-            rating
+            rating,
+            review
         FROM
             purchases
         WHERE
@@ -44,16 +56,18 @@ pub async fn create_purchase(
                 upload_id,
                 ecs_spent,
                 purchase_date,
-                rating
+                rating,
+                review
             )
         VALUES
-            ($1, $2, $3, $4, $5)
+            ($1, $2, $3, $4, $5, $6)
         ",
         purchase.user_id,
         purchase.upload_id,
         purchase.ecs_spent,
         purchase.purchase_date,
         purchase.rating,
+        purchase.review,
     )
     .execute(&mut **tx)
     .await
@@ -61,3 +75,164 @@ pub async fn create_purchase(
 
     Ok(())
 }
+
+/// Purchase an upload for a user, debiting the buyer and crediting the uploader.
+///
+/// The buyer's and the uploader's balances stay locked until the transaction ends,
+/// so the caller must commit it for the purchase to take effect.
+///
+/// # Errors
+///
+/// - [`PurchaseError::AlreadyPurchased`] if the user already owns a purchase of this upload
+/// - [`PurchaseError::InsufficientFunds`] if the user can't afford the upload
+/// - [`PurchaseError::QueryError`] if any query fails
+pub async fn purchase_upload(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    upload: &Upload,
+) -> Result<Purchase, PurchaseError> {
+    // Prices are checked when they're set, but a negative one would let buyers mint ECs
+    if upload.price < 0 {
+        return Err(PurchaseError::QueryError(anyhow::anyhow!(
+            "Upload {} has a negative price",
+            upload.id
+        )));
+    }
+
+    // Concurrent purchases of the same user wait here until this transaction ends
+    let balance = super::ecs::lock_available_funds(tx, user_id, &[upload.uploader])
+        .await
+        .map_err(PurchaseError::QueryError)?;
+
+    // Only check this once the lock is held, so double-clicks can't buy twice
+    if get_purchase(tx, user_id, upload.id)
+        .await
+        .map_err(PurchaseError::QueryError)?
+        .is_some()
+    {
+        return Err(PurchaseError::AlreadyPurchased);
+    }
+
+    if balance < i64::from(upload.price) {
+        return Err(PurchaseError::InsufficientFunds {
+            balance,
+            price: upload.price,
+        });
+    }
+
+    let purchase = Purchase {
+        user_id,
+        upload_id: upload.id,
+        ecs_spent: upload.price,
+        purchase_date: chrono::Utc::now().naive_utc(),
+        rating: None,
+        review: None,
+    };
+
+    // The uploader gets credited by the `purchases_ec_balance` trigger
+    create_purchase(tx, &purchase)
+        .await
+        .map_err(PurchaseError::QueryError)?;
+
+    Ok(purchase)
+}
+
+/// Rate an upload the user has purchased, replacing any earlier rating and review.
+///
+/// Returns `None` if the user has no purchase of this upload, or if it's their own upload.
+/// The first rating refunds a part of the price to the buyer (see `ec_rating_refund`).
+pub async fn rate_purchase(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    upload_id: Uuid,
+    rating: i16,
+    review: Option<&str>,
+) -> anyhow::Result<Option<Purchase>> {
+    let Some(uploader) = sqlx::query_scalar!(
+        "
+        SELECT
+            uploader
+        FROM
+            uploads
+        WHERE
+            id = $1
+        ",
+        upload_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get uploader")?
+    else {
+        return Ok(None);
+    };
+
+    // The refund of the first rating changes both balances, so they're locked like for a purchase
+    super::ecs::lock_available_funds(tx, user_id, &[uploader]).await?;
+
+    sqlx::query_as!(
+        Purchase,
+        "
+        UPDATE
+            purchases AS p
+        SET
+            rating = $3,
+            review = $4
+        FROM
+            uploads AS u
+        WHERE
+            p.upload_id = u.id
+            AND p.user_id = $1
+            AND p.upload_id = $2
+            AND u.uploader <> p.user_id
+        RETURNING
+            p.user_id,
+            p.upload_id,
+            p.ecs_spent,
+            p.purchase_date,
+            p.rating,
+            p.review
+        ",
+        user_id,
+        upload_id,
+        rating,
+        review,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to rate purchase")
+}
+
+/// A rating of an upload, without revealing who rated it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UploadReview {
+    pub rating: i16,
+    pub review: Option<String>,
+    pub purchase_date: NaiveDateTime,
+}
+
+/// Get all ratings and reviews of an upload, newest purchases first
+pub async fn get_reviews_of_upload(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+) -> anyhow::Result<Vec<UploadReview>> {
+    sqlx::query_as!(
+        UploadReview,
+        r#"
+        SELECT
+            rating AS "rating!",
+            review,
+            purchase_date
+        FROM
+            purchases
+        WHERE
+            upload_id = $1
+            AND rating IS NOT NULL
+        ORDER BY
+            purchase_date DESC
+        "#,
+        upload_id,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get reviews")
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// An address which may register although its domain doesn't belong to a university
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RegistrationException {
+    pub id: Uuid,
+
+    /// In lowercase
+    pub address: String,
+
+    /// The university the address gets associated with, if any
+    pub university: Option<Uuid>,
+    pub note: Option<String>,
+    pub added_by: Uuid,
+    pub added_at: NaiveDateTime,
+}
+
+pub async fn get_registration_exceptions(
+    tx: &mut PgTransaction<'_>,
+) -> anyhow::Result<Vec<RegistrationException>> {
+    sqlx::query_as!(
+        RegistrationException,
+        "
+        SELECT
+            id,
+            address,
+            university,
+            note,
+            added_by,
+            added_at
+        FROM
+            registration_exceptions
+        ORDER BY
+            address
+        "
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get registration exceptions")
+}
+
+/// Add an address to the exceptions; returns `None` if it's on the list already
+pub async fn add_registration_exception(
+    tx: &mut PgTransaction<'_>,
+    address: &str,
+    university: Option<Uuid>,
+    note: Option<&str>,
+    admin_id: Uuid,
+) -> anyhow::Result<Option<RegistrationException>> {
+    sqlx::query_as!(
+        RegistrationException,
+        "
+        INSERT INTO
+            registration_exceptions (address, university, note, added_by, added_at)
+        VALUES
+            (LOWER($1), $2, $3, $4, $5) ON CONFLICT (address) DO NOTHING
+        RETURNING
+            id,
+            address,
+            university,
+            note,
+            added_by,
+            added_at
+        ",
+        address,
+        university,
+        note,
+        admin_id,
+        chrono::Utc::now().naive_utc(),
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to add registration exception")
+}
+
+/// Remove an address from the exceptions (accounts registered with it stay); returns `false` if it wasn't on the list
+pub async fn remove_registration_exception(
+    tx: &mut PgTransaction<'_>,
+    address: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "
+        DELETE FROM
+            registration_exceptions
+        WHERE
+            address = LOWER($1)
+        ",
+        address
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to remove registration exception")?;
+
+    Ok(result.rows_affected() > 0)
+}
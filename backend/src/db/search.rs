@@ -0,0 +1,220 @@
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::data::{Course, Prof, Upload, UploadType};
+
+/// Restricts which uploads a search may return; `None` means "don't filter by this"
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UploadFilter {
+    pub upload_type: Option<UploadType>,
+    pub course_id: Option<Uuid>,
+    pub prof_id: Option<Uuid>,
+    pub university_id: Option<Uuid>,
+
+    /// Only uploads with an associated date at or after this one
+    pub associated_date_from: Option<NaiveDateTime>,
+
+    /// Only uploads with an associated date at or before this one
+    pub associated_date_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UploadSearchResult {
+    #[serde(flatten)]
+    pub upload: Upload,
+    pub course_name: String,
+    pub prof_name: Option<String>,
+
+    /// How well the upload matches the search, higher is better
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CourseSearchResult {
+    #[serde(flatten)]
+    pub course: Course,
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProfSearchResult {
+    #[serde(flatten)]
+    pub prof: Prof,
+    pub rank: f32,
+}
+
+/// Search uploads by their name & description, and by the names of their course and prof
+///
+/// The query uses web search syntax (see `websearch_to_tsquery` in the Postgres docs),
+/// so users can use quotes, `or`, and `-` to exclude words.
+pub async fn search_uploads(
+    tx: &mut PgTransaction<'_>,
+    query: &str,
+    filter: &UploadFilter,
+    limit: i64,
+) -> anyhow::Result<Vec<UploadSearchResult>> {
+    let uploads = sqlx::query!(
+        r#"
+        SELECT
+            uploads.id,
+            upload_name AS name,
+            description,
+            price,
+            uploader,
+            upload_date,
+            last_modified_date,
+            associated_date,
+            upload_type AS "upload_type: UploadType",
+            belongs_to,
+            held_by,
+            courses.course_name,
+            profs.prof_name AS "prof_name?",
+            ts_rank_cd(uploads.search_vector, search_query) AS "rank!"
+        FROM
+            uploads
+            INNER JOIN courses ON uploads.belongs_to = courses.id
+            LEFT JOIN profs ON uploads.held_by = profs.id
+            CROSS JOIN websearch_to_tsquery('german', $1) AS search_query
+        WHERE
+            uploads.search_vector @@ search_query
+            AND uploads.deleted_at IS NULL
+            AND ($2::upload_type_enum IS NULL OR uploads.upload_type = $2)
+            AND ($3::uuid IS NULL OR uploads.belongs_to = $3)
+            AND ($4::uuid IS NULL OR uploads.held_by = $4)
+            AND ($5::uuid IS NULL OR courses.held_at = $5)
+            AND ($6::timestamp IS NULL OR uploads.associated_date >= $6)
+            AND ($7::timestamp IS NULL OR uploads.associated_date <= $7)
+        ORDER BY
+            "rank!" DESC,
+            uploads.upload_date DESC
+        LIMIT
+            $8
+        "#,
+        query,
+        filter.upload_type.clone() as Option<UploadType>,
+        filter.course_id,
+        filter.prof_id,
+        filter.university_id,
+        filter.associated_date_from,
+        filter.associated_date_until,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to search uploads")?
+    .into_iter()
+    .map(|row| UploadSearchResult {
+        upload: Upload {
+            id: row.id,
+            name: row.name,
+            description: row.description,
+            price: row.price,
+            uploader: row.uploader,
+            upload_date: row.upload_date,
+            last_modified_date: row.last_modified_date,
+            associated_date: row.associated_date,
+            upload_type: row.upload_type,
+            belongs_to: row.belongs_to,
+            held_by: row.held_by,
+        },
+        course_name: row.course_name,
+        prof_name: row.prof_name,
+        rank: row.rank,
+    })
+    .collect();
+
+    Ok(uploads)
+}
+
+/// Search courses by name, optionally only those of one university
+pub async fn search_courses(
+    tx: &mut PgTransaction<'_>,
+    query: &str,
+    university_id: Option<Uuid>,
+    limit: i64,
+) -> anyhow::Result<Vec<CourseSearchResult>> {
+    let courses = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            held_at,
+            course_name AS name,
+            ts_rank_cd(search_vector, search_query) AS "rank!"
+        FROM
+            courses
+            CROSS JOIN websearch_to_tsquery('german', $1) AS search_query
+        WHERE
+            search_vector @@ search_query
+            AND ($2::uuid IS NULL OR held_at = $2)
+        ORDER BY
+            "rank!" DESC,
+            course_name
+        LIMIT
+            $3
+        "#,
+        query,
+        university_id,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to search courses")?
+    .into_iter()
+    .map(|row| CourseSearchResult {
+        course: Course {
+            id: row.id,
+            name: row.name,
+            held_at: row.held_at,
+        },
+        rank: row.rank,
+    })
+    .collect();
+
+    Ok(courses)
+}
+
+/// Search profs by name
+pub async fn search_profs(
+    tx: &mut PgTransaction<'_>,
+    query: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<ProfSearchResult>> {
+    let profs = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            prof_name AS name,
+            ts_rank_cd(search_vector, search_query) AS "rank!"
+        FROM
+            profs
+            CROSS JOIN websearch_to_tsquery('german', $1) AS search_query
+        WHERE
+            search_vector @@ search_query
+        ORDER BY
+            "rank!" DESC,
+            prof_name
+        LIMIT
+            $2
+        "#,
+        query,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to search profs")?
+    .into_iter()
+    .map(|row| ProfSearchResult {
+        prof: Prof {
+            id: row.id,
+            name: row.name,
+        },
+        rank: row.rank,
+    })
+    .collect();
+
+    Ok(profs)
+}
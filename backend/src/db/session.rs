@@ -1,76 +1,272 @@
 use anyhow::Context;
 use base64::{engine::general_purpose, Engine as _};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
 use sqlx::{PgTransaction, Pool, Postgres};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::data::Token;
+use crate::{api::v1::client::ClientInfo, conf::CONF, data::Token};
 
 pub enum ValidationResult {
-    Valid { user_id: Uuid, auth_level: i16 },
+    Valid {
+        user_id: Uuid,
+        auth_level: i16,
+        totp_enabled: bool,
+    },
     Invalid,
 }
 
+/// Sessions seen more recently than this don't get their metadata updated on every request
+const LAST_SEEN_PRECISION: Duration = Duration::minutes(5);
+
+/// The user agent column can't hold more than this
+const MAX_USER_AGENT_LENGTH: usize = 275;
+
+/// A login of a user, shown to them so they can recognize their devices
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub initial_user_agent: Option<String>,
+    pub latest_user_agent: Option<String>,
+    pub initial_ip: Option<String>,
+    pub latest_ip: Option<String>,
+    /// Whether this is the session the request was made with
+    pub is_current: bool,
+}
+
+/// Sessions created before the first, or last seen before the second point in time are expired
+pub(super) fn expiry_cutoffs(now: NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
+    (
+        now - Duration::days(CONF.sessionmaxage.into()),
+        now - Duration::days(CONF.sessionidletimeout.into()),
+    )
+}
+
+fn truncate_user_agent(client: &ClientInfo) -> Option<String> {
+    client
+        .user_agent
+        .as_ref()
+        .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
 // pub async fn validate_session(mut tx: &mut PgTransaction, token: &Token) -> ValidationResult {
 //     let token: String = general_purpose::STANDARD_NO_PAD.encode(token);
-pub async fn validate_session(mut tx: &mut PgTransaction<'_>, token: &String) -> ValidationResult {
+/// Checks a session token, recording when & from where the session was last used
+pub async fn validate_session(
+    tx: &mut PgTransaction<'_>,
+    token: &str,
+    client: &ClientInfo,
+) -> sqlx::Result<ValidationResult> {
     log::info!("Validating session with a token");
 
     let session = sqlx::query!(
-        "
+        r#"
         SELECT
+            s.id,
             s.of_user,
-            u.user_role AS auth_level
+            s.created_at,
+            s.last_seen_at,
+            u.user_role AS auth_level,
+            u.totp_secret IS NOT NULL AS "totp_enabled!"
         FROM
             sessions AS s
             INNER JOIN users AS u ON s.of_user = u.id
         WHERE
             token = $1
-        ",
+        "#,
         token
     )
     .fetch_optional(&mut **tx)
-    .await
-    .expect("Failed to query session")
-    .map(|session| (session.of_user, session.auth_level));
-
-    if let Some((Some(user_id), auth_level)) = session {
-        log::info!("Session is valid for user {}", user_id);
-        ValidationResult::Valid {
-            user_id,
-            auth_level,
-        }
-    } else {
+    .await?;
+
+    let Some(session) = session else {
         log::info!("Session is invalid");
-        ValidationResult::Invalid
+        return Ok(ValidationResult::Invalid);
+    };
+
+    let Some(user_id) = session.of_user else {
+        log::info!("Session is invalid");
+        return Ok(ValidationResult::Invalid);
+    };
+
+    let now = Utc::now().naive_utc();
+    let (created_cutoff, last_seen_cutoff) = expiry_cutoffs(now);
+
+    if session.created_at < created_cutoff || session.last_seen_at < last_seen_cutoff {
+        log::info!("Session of user {user_id} has expired");
+        delete_session(tx, token).await?;
+        return Ok(ValidationResult::Invalid);
     }
+
+    // Skip the write (and the audit log entry) if nothing changed recently
+    sqlx::query!(
+        "
+        UPDATE
+            sessions
+        SET
+            last_seen_at = $2,
+            latest_user_agent = $3,
+            latest_ip = $4::text::inet
+        WHERE
+            id = $1
+            AND (
+                last_seen_at < $5
+                OR latest_user_agent IS DISTINCT FROM $3
+                OR latest_ip IS DISTINCT FROM $4::text::inet
+            )
+        ",
+        session.id,
+        now,
+        truncate_user_agent(client),
+        client.ip.map(|ip| ip.to_string()),
+        now - LAST_SEEN_PRECISION,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    log::info!("Session is valid for user {}", user_id);
+    Ok(ValidationResult::Valid {
+        user_id,
+        auth_level: session.auth_level,
+        totp_enabled: session.totp_enabled,
+    })
 }
 
 // pub async fn create_session(mut tx: &mut PgTransaction, user_id: Uuid) -> Token {
-pub async fn create_session(mut tx: &mut PgTransaction<'_>, user_id: Uuid) -> String {
+pub async fn create_session(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> sqlx::Result<String> {
     // 32 bytes of random data
     let token: Token = rand::random();
 
     let token: String = general_purpose::URL_SAFE_NO_PAD.encode(token);
 
+    let now = Utc::now().naive_utc();
+    let (created_cutoff, last_seen_cutoff) = expiry_cutoffs(now);
+
+    // Clean up the user's expired sessions while we're at it
+    sqlx::query!(
+        "
+        DELETE FROM
+            sessions
+        WHERE
+            of_user = $1
+            AND (
+                created_at < $2
+                OR last_seen_at < $3
+            )
+        ",
+        user_id,
+        created_cutoff,
+        last_seen_cutoff,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let user_agent = truncate_user_agent(client);
+    let ip = client.ip.map(|ip| ip.to_string());
+
     sqlx::query!(
         "
         INSERT INTO
-            sessions (id, token, of_user)
+            sessions (
+                id,
+                token,
+                of_user,
+                initial_user_agent,
+                latest_user_agent,
+                initial_ip,
+                latest_ip,
+                created_at,
+                last_seen_at
+            )
         VALUES
-            ($1, $2, $3)
+            ($1, $2, $3, $4, $4, $5::text::inet, $5::text::inet, $6, $6)
         ",
         Uuid::new_v4(),
         token,
+        user_id,
+        user_agent,
+        ip,
+        now,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(token)
+}
+
+/// Get the sessions of a user which haven't expired yet, most recently used first
+pub async fn get_sessions_of_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    current_token: Option<&str>,
+) -> anyhow::Result<Vec<SessionInfo>> {
+    let (created_cutoff, last_seen_cutoff) = expiry_cutoffs(Utc::now().naive_utc());
+
+    sqlx::query_as!(
+        SessionInfo,
+        r#"
+        SELECT
+            id,
+            created_at,
+            last_seen_at,
+            initial_user_agent,
+            latest_user_agent,
+            HOST(initial_ip) AS initial_ip,
+            HOST(latest_ip) AS latest_ip,
+            token = $2 AS "is_current!"
+        FROM
+            sessions
+        WHERE
+            of_user = $1
+            AND created_at >= $3
+            AND last_seen_at >= $4
+        ORDER BY
+            last_seen_at DESC
+        "#,
+        user_id,
+        current_token.unwrap_or_default(),
+        created_cutoff,
+        last_seen_cutoff,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get sessions")
+}
+
+/// Log a user out on one device, returning whether they had such a session
+pub async fn revoke_session(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> anyhow::Result<bool> {
+    log::info!("Revoking session {session_id} of user {user_id}");
+
+    let result = sqlx::query!(
+        "
+        DELETE FROM
+            sessions
+        WHERE
+            id = $1
+            AND of_user = $2
+        ",
+        session_id,
         user_id
     )
     .execute(&mut **tx)
     .await
-    .expect("Failed to create session");
+    .context("Failed to revoke session")?;
 
-    token
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn delete_session(mut tx: &mut PgTransaction<'_>, value: &str) -> anyhow::Result<()> {
+pub async fn delete_session(mut tx: &mut PgTransaction<'_>, value: &str) -> sqlx::Result<()> {
     log::info!("Deleting a session");
 
     sqlx::query!(
@@ -83,8 +279,30 @@ pub async fn delete_session(mut tx: &mut PgTransaction<'_>, value: &str) -> anyh
         value
     )
     .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Log a user out everywhere, e.g. after their password has been changed
+pub async fn delete_sessions_of_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<()> {
+    log::info!("Deleting all sessions of user {user_id}");
+
+    sqlx::query!(
+        "
+        DELETE FROM
+            sessions
+        WHERE
+            of_user = $1
+        ",
+        user_id
+    )
+    .execute(&mut **tx)
     .await
-    .context("Failed to delete session")?;
+    .context("Failed to delete sessions")?;
 
     Ok(())
 }
@@ -2,12 +2,12 @@ WITH ecs_earned_tbl AS (
     -- ECs earned from uploads
     SELECT
         up.uploader AS user_id,
-        COALESCE(SUM(pu.ecs_spent * 0.8), 0) AS ecs_earned
+        COALESCE(SUM(ec_uploader_share(pu.ecs_spent)), 0) AS ecs_earned
     FROM
         uploads AS up
         INNER JOIN purchases AS pu ON pu.upload_id = up.id
     WHERE
-        pu.user_id <> up.uploader
+        pu.user_id IS DISTINCT FROM up.uploader
     GROUP BY
         up.uploader
 ),
@@ -25,7 +25,7 @@ ecs_refunded_tbl AS (
     -- ECs refunded from ratings
     SELECT
         pu.user_id,
-        COALESCE(SUM(pu.ecs_spent * 0.2), 0) AS ecs_refunded
+        COALESCE(SUM(ec_rating_refund(pu.ecs_spent)), 0) AS ecs_refunded
     FROM
         purchases AS pu
     WHERE
@@ -3,13 +3,13 @@ SELECT
     (
         -- ECs earned from uploads
         SELECT
-            COALESCE(SUM(pu.ecs_spent * 0.8), 0)
+            COALESCE(SUM(ec_uploader_share(pu.ecs_spent)), 0)
         FROM
             uploads AS up
             JOIN purchases AS pu ON pu.upload_id = up.id
         WHERE
             up.uploader = u.id
-            AND pu.user_id <> up.uploader
+            AND pu.user_id IS DISTINCT FROM up.uploader
     ) + (
         -- ECs given/taken by the system
         SELECT
@@ -29,7 +29,7 @@ SELECT
     ) + (
         -- ECs refunded from ratings
         SELECT
-            COALESCE(SUM(pu.ecs_spent * 0.2), 0)
+            COALESCE(SUM(ec_rating_refund(pu.ecs_spent)), 0)
         FROM
             purchases AS pu
         WHERE
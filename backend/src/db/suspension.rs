@@ -0,0 +1,129 @@
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::PgTransaction;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A suspension which hasn't run out or been lifted yet
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Suspension {
+    pub id: Uuid,
+    pub reason: String,
+    pub suspended_by: Uuid,
+    pub suspended_at: NaiveDateTime,
+
+    /// `None` if the user is suspended indefinitely
+    pub suspended_until: Option<NaiveDateTime>,
+}
+
+/// Suspend a user until the given date, or indefinitely.
+///
+/// A suspension which is still active stays in effect, so lift it first to shorten it.
+pub async fn suspend_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    reason: &str,
+    suspended_until: Option<NaiveDateTime>,
+    admin_id: Uuid,
+) -> anyhow::Result<Suspension> {
+    sqlx::query_as!(
+        Suspension,
+        "
+        INSERT INTO
+            user_suspensions (
+                of_user,
+                reason,
+                suspended_by,
+                suspended_at,
+                suspended_until
+            )
+        VALUES
+            ($1, $2, $3, $4, $5)
+        RETURNING
+            id,
+            reason,
+            suspended_by,
+            suspended_at,
+            suspended_until
+        ",
+        user_id,
+        reason,
+        admin_id,
+        Utc::now().naive_utc(),
+        suspended_until,
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to suspend user")
+}
+
+/// Get the suspension of a user which is in effect right now, the longest one if there are several
+pub async fn get_active_suspension(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<Suspension>> {
+    sqlx::query_as!(
+        Suspension,
+        "
+        SELECT
+            id,
+            reason,
+            suspended_by,
+            suspended_at,
+            suspended_until
+        FROM
+            user_suspensions
+        WHERE
+            of_user = $1
+            AND lifted_at IS NULL
+            AND (
+                suspended_until IS NULL
+                OR suspended_until > $2
+            )
+        ORDER BY
+            suspended_until DESC NULLS FIRST
+        LIMIT
+            1
+        ",
+        user_id,
+        Utc::now().naive_utc(),
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get suspension")
+}
+
+/// Lift all active suspensions of a user; returns how many there were
+pub async fn lift_suspensions(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    admin_id: Uuid,
+) -> anyhow::Result<u64> {
+    let now = Utc::now().naive_utc();
+
+    let result = sqlx::query!(
+        "
+        UPDATE
+            user_suspensions
+        SET
+            lifted_by = $2,
+            lifted_at = $3
+        WHERE
+            of_user = $1
+            AND lifted_at IS NULL
+            AND (
+                suspended_until IS NULL
+                OR suspended_until > $3
+            )
+        ",
+        user_id,
+        admin_id,
+        now,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to lift suspensions")?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,208 @@
+use anyhow::Context;
+use sqlx::PgTransaction;
+use uuid::Uuid;
+
+use super::activation::hash_token;
+
+/// The TOTP secrets of a user, base32-encoded
+#[derive(Debug, Clone)]
+pub struct TotpState {
+    /// The secret of the confirmed authenticator, if TOTP is enabled
+    pub secret: Option<String>,
+    /// A secret which has been handed out for enrollment, but hasn't been confirmed yet
+    pub pending_secret: Option<String>,
+}
+
+pub async fn get_totp_state(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<TotpState>> {
+    sqlx::query_as!(
+        TotpState,
+        "
+        SELECT
+            totp_secret AS secret,
+            totp_pending_secret AS pending_secret
+        FROM
+            users
+        WHERE
+            id = $1
+        ",
+        user_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get TOTP state")
+}
+
+/// Store a new secret for enrollment, replacing any earlier unconfirmed one
+pub async fn set_pending_secret(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    secret: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            totp_pending_secret = $2
+        WHERE
+            id = $1
+        ",
+        user_id,
+        secret,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to set pending TOTP secret")?;
+
+    Ok(())
+}
+
+/// Turn the pending secret into the active one, enabling TOTP for the user
+pub async fn confirm_pending_secret(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            totp_secret = totp_pending_secret,
+            totp_pending_secret = NULL
+        WHERE
+            id = $1
+            AND totp_pending_secret IS NOT NULL
+        ",
+        user_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to confirm TOTP secret")?;
+
+    Ok(())
+}
+
+/// Disable TOTP for a user, removing their secrets and recovery codes
+pub async fn disable_totp(tx: &mut PgTransaction<'_>, user_id: Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            totp_secret = NULL,
+            totp_pending_secret = NULL,
+            totp_last_step = NULL
+        WHERE
+            id = $1
+        ",
+        user_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to disable TOTP")?;
+
+    delete_recovery_codes(tx, user_id).await
+}
+
+/// Remember that a code of the given time step has been used.
+///
+/// Returns `false` if a code of this or a later step was used already, i.e. the code is replayed.
+pub async fn use_time_step(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    step: i64,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            totp_last_step = $2
+        WHERE
+            id = $1
+            AND (
+                totp_last_step IS NULL
+                OR totp_last_step < $2
+            )
+        ",
+        user_id,
+        step,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to record TOTP time step")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Replace all recovery codes of a user with new ones (only their hashes are stored)
+pub async fn replace_recovery_codes(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    codes: &[String],
+) -> anyhow::Result<()> {
+    delete_recovery_codes(tx, user_id).await?;
+
+    let code_hashes: Vec<String> = codes.iter().map(|code| hash_token(code)).collect();
+
+    sqlx::query!(
+        "
+        INSERT INTO
+            totp_recovery_codes (of_user, code_hash)
+        SELECT
+            $1,
+            UNNEST($2::text[])
+        ",
+        user_id,
+        &code_hashes,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to store recovery codes")?;
+
+    Ok(())
+}
+
+/// Use up a recovery code, returning whether it was valid
+pub async fn use_recovery_code(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "
+        DELETE FROM
+            totp_recovery_codes
+        WHERE
+            of_user = $1
+            AND code_hash = $2
+        ",
+        user_id,
+        hash_token(code),
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to use recovery code")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn delete_recovery_codes(tx: &mut PgTransaction<'_>, user_id: Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        DELETE FROM
+            totp_recovery_codes
+        WHERE
+            of_user = $1
+        ",
+        user_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete recovery codes")?;
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use anyhow::Context;
+use justerror::Error;
 use sqlx::PgTransaction;
 use uuid::Uuid;
 
@@ -43,6 +44,74 @@ pub async fn get_universities(
     })
 }
 
+pub async fn get_university(
+    tx: &mut PgTransaction<'_>,
+    university_id: Uuid,
+) -> anyhow::Result<Option<OwnedUniversity>> {
+    let uni = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name_full,
+            name_mid,
+            name_short,
+            email_domain_names,
+            homepage_url,
+            cms_url,
+            background_color AS "background_color: DbRgbColor",
+            text_color AS "text_color: DbRgbColor"
+        FROM
+            universities
+        WHERE
+            id = $1
+        "#,
+        university_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get university")?;
+
+    Ok(uni.map(|uni| OwnedUniversity {
+        id: uni.id,
+        full_name: uni.name_full,
+        mid_name: uni.name_mid,
+        short_name: uni.name_short,
+        email_domain_names: uni.email_domain_names,
+        homepage_url: uni.homepage_url,
+        cms_url: uni.cms_url,
+        background_color: uni.background_color.into(),
+        text_color: uni.text_color.into(),
+    }))
+}
+
+/// Get the university a user's primary email address belongs to, if any
+pub async fn get_university_of_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<OwnedUniversity>> {
+    let university_id = sqlx::query_scalar!(
+        "
+        SELECT
+            emails.of_university
+        FROM
+            users
+            INNER JOIN emails ON users.primary_email = emails.id
+        WHERE
+            users.id = $1
+        ",
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to get university of user")?
+    .flatten();
+
+    match university_id {
+        Some(university_id) => get_university(tx, university_id).await,
+        None => Ok(None),
+    }
+}
+
 /// Create a university, returning its ID, ignoring the ID in the input
 pub async fn create_university(
     tx: &mut PgTransaction<'_>,
@@ -141,3 +210,140 @@ pub async fn create_university_with_id(
 
     Ok(())
 }
+
+/// Replace all fields of a university; returns `false` if there's no university with its ID
+pub async fn replace_university(
+    tx: &mut PgTransaction<'_>,
+    university: OwnedUniversity,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            universities
+        SET
+            name_full = $2,
+            name_mid = $3,
+            name_short = $4,
+            email_domain_names = $5,
+            homepage_url = $6,
+            cms_url = $7,
+            background_color = $8,
+            text_color = $9
+        WHERE
+            id = $1
+        "#,
+        university.id,
+        university.full_name,
+        university.mid_name,
+        university.short_name,
+        &university.email_domain_names,
+        university.homepage_url,
+        university.cms_url,
+        DbRgbColor::from(university.background_color) as _,
+        DbRgbColor::from(university.text_color) as _,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to replace university")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Find another university which already uses one of the names or email domains of this one;
+/// returns its full name
+pub async fn find_clashing_university(
+    tx: &mut PgTransaction<'_>,
+    university: &OwnedUniversity,
+) -> anyhow::Result<Option<String>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            name_full
+        FROM
+            universities
+        WHERE
+            id <> $1
+            AND (
+                LOWER(name_full) = LOWER($2)
+                OR LOWER(name_mid) = LOWER($3)
+                OR LOWER(name_short) = LOWER($4)
+                OR email_domain_names && $5::varchar[]
+            )
+        LIMIT
+            1
+        "#,
+        university.id,
+        university.full_name,
+        university.mid_name,
+        university.short_name,
+        &university.email_domain_names,
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to check for clashing universities")
+}
+
+#[Error]
+pub enum DeleteUniversityError {
+    NotFound,
+    /// Courses or email addresses still belong to the university
+    InUse,
+    QueryError(anyhow::Error),
+}
+
+/// Delete a university, as long as no course or email address refers to it
+pub async fn delete_university(
+    tx: &mut PgTransaction<'_>,
+    university_id: Uuid,
+) -> Result<(), DeleteUniversityError> {
+    let in_use = sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT
+                    1
+                FROM
+                    courses
+                WHERE
+                    held_at = $1
+            )
+            OR EXISTS (
+                SELECT
+                    1
+                FROM
+                    emails
+                WHERE
+                    of_university = $1
+            ) AS "in_use!"
+        "#,
+        university_id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to check if university is in use")
+    .map_err(DeleteUniversityError::QueryError)?;
+
+    if in_use {
+        return Err(DeleteUniversityError::InUse);
+    }
+
+    let result = sqlx::query!(
+        "
+        DELETE FROM
+            universities
+        WHERE
+            id = $1
+        ",
+        university_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete university")
+    .map_err(DeleteUniversityError::QueryError)?;
+
+    if result.rows_affected() == 0 {
+        return Err(DeleteUniversityError::NotFound);
+    }
+
+    Ok(())
+}
@@ -1,24 +1,29 @@
-use anyhow::Context;
+use anyhow::{ensure, Context};
+use base64::{engine::general_purpose, Engine as _};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, PgTransaction};
+use sqlx::{FromRow, PgPool, PgTransaction, Postgres, QueryBuilder};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::data::{Upload, UploadType};
+use crate::data::{RatedUpload, Upload, UploadType};
 
-use super::SortOrder;
+use super::{Page, SortOrder};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct Sorting {
     pub order: SortOrder,
     pub by: SortBy,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum SortBy {
     Name,
     UploadDate,
+    /// The date associated with the upload, falling back to the upload date if there is none
+    #[default]
     Date,
+    /// The mean rating, with unrated uploads counting as 0
     Rating,
 }
 
@@ -26,22 +31,126 @@ impl Default for Sorting {
     fn default() -> Self {
         Self {
             order: SortOrder::Descending,
-            by: SortBy::Date,
+            by: SortBy::default(),
         }
     }
 }
 
-pub async fn get_uploads_of_course(
-    mut tx: &mut PgTransaction<'_>,
-    course_id: Uuid,
+impl SortBy {
+    /// The SQL expression to sort by, matching [`SortKey::of`]
+    fn sql_expression(self) -> &'static str {
+        match self {
+            SortBy::Name => "uploads.upload_name",
+            SortBy::UploadDate => "uploads.upload_date",
+            SortBy::Date => "COALESCE(uploads.associated_date, uploads.upload_date)",
+            SortBy::Rating => "COALESCE(ratings.rating_mean, 0)",
+        }
+    }
+}
+
+/// The value of an upload's [`SortBy`] column, as stored in a cursor
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum SortKey {
+    Text(String),
+    Timestamp(NaiveDateTime),
+    Number(f64),
+}
+
+impl SortKey {
+    fn of(upload: &RatedUpload, by: SortBy) -> Self {
+        match by {
+            SortBy::Name => Self::Text(upload.upload.name.clone()),
+            SortBy::UploadDate => Self::Timestamp(upload.upload.upload_date),
+            SortBy::Date => Self::Timestamp(
+                upload
+                    .upload
+                    .associated_date
+                    .unwrap_or(upload.upload.upload_date),
+            ),
+            SortBy::Rating => Self::Number(upload.rating_mean.unwrap_or(0.0)),
+        }
+    }
+
+    fn from_json(value: serde_json::Value, by: SortBy) -> serde_json::Result<Self> {
+        Ok(match by {
+            SortBy::Name => Self::Text(serde_json::from_value(value)?),
+            SortBy::UploadDate | SortBy::Date => Self::Timestamp(serde_json::from_value(value)?),
+            SortBy::Rating => Self::Number(serde_json::from_value(value)?),
+        })
+    }
+}
+
+/// The position after the last upload of a page
+///
+/// Clients get it as an opaque string (URL-safe base64 encoded JSON).
+#[derive(Debug)]
+pub struct UploadCursor {
+    key: SortKey,
+    id: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncodedUploadCursor<K> {
+    by: SortBy,
+    key: K,
+    id: Uuid,
+}
+
+impl UploadCursor {
+    /// Decode a cursor a client sent, checking that it belongs to a listing sorted by `by`
+    pub fn decode(cursor: &str, by: SortBy) -> anyhow::Result<Self> {
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .context("Cursor is not valid base64")?;
+
+        let cursor: EncodedUploadCursor<serde_json::Value> =
+            serde_json::from_slice(&json).context("Cursor is malformed")?;
+
+        ensure!(cursor.by == by, "Cursor belongs to a different sorting");
+
+        Ok(Self {
+            key: SortKey::from_json(cursor.key, by).context("Cursor is malformed")?,
+            id: cursor.id,
+        })
+    }
+
+    fn encode(&self, by: SortBy) -> String {
+        let cursor = EncodedUploadCursor {
+            by,
+            key: &self.key,
+            id: self.id,
+        };
+
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cursor).unwrap())
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct RatedUploadRow {
+    #[sqlx(flatten)]
+    upload: Upload,
+    rating_mean: Option<f64>,
+    rating_count: i64,
+}
+
+/// Get a page of uploads, either of one course or of all courses, sorted and paginated
+async fn get_uploads_page(
+    tx: &mut PgTransaction<'_>,
+    course_id: Option<Uuid>,
     sorting: Option<Sorting>,
-) -> anyhow::Result<Vec<Upload>> {
-    // TODO: implement sorting
-    let _sorting = sorting.unwrap_or_default();
+    limit: i64,
+    cursor: Option<UploadCursor>,
+) -> anyhow::Result<Page<RatedUpload>> {
+    let sorting = sorting.unwrap_or_default();
+    let sort_expression = sorting.by.sql_expression();
+    let (direction, comparison) = match sorting.order {
+        SortOrder::Ascending => ("ASC", ">"),
+        SortOrder::Descending => ("DESC", "<"),
+    };
 
-    sqlx::query_as!(
-        Upload,
-        r#"
+    let mut query = QueryBuilder::<Postgres>::new(
+        "
         SELECT
             uploads.id,
             upload_name AS name,
@@ -51,54 +160,105 @@ pub async fn get_uploads_of_course(
             upload_date,
             last_modified_date,
             associated_date,
-            upload_type AS "upload_type: _",
+            upload_type,
             belongs_to,
-            held_by
+            held_by,
+            ratings.rating_mean,
+            ratings.rating_count
         FROM
             uploads
-            INNER JOIN courses ON uploads.belongs_to = courses.id
+            CROSS JOIN LATERAL (
+                SELECT
+                    AVG(rating)::float8 AS rating_mean,
+                    COUNT(rating) AS rating_count
+                FROM
+                    purchases
+                WHERE
+                    purchases.upload_id = uploads.id
+            ) AS ratings
         WHERE
-            courses.id = $1
-        "#,
-        course_id,
-    )
-    .fetch_all(&mut **tx)
-    .await
-    .context("Failed to get courses")
+            uploads.deleted_at IS NULL
+        ",
+    );
+
+    if let Some(course_id) = course_id {
+        query
+            .push(" AND uploads.belongs_to = ")
+            .push_bind(course_id);
+    }
+
+    // Continue right after the last upload of the previous page
+    if let Some(cursor) = cursor {
+        query.push(format!(
+            " AND ({sort_expression}, uploads.id) {comparison} ("
+        ));
+
+        match cursor.key {
+            SortKey::Text(key) => query.push_bind(key),
+            SortKey::Timestamp(key) => query.push_bind(key),
+            SortKey::Number(key) => query.push_bind(key),
+        };
+
+        query.push(", ").push_bind(cursor.id).push(")");
+    }
+
+    // The upload ID breaks ties, so every upload has a unique position
+    query
+        .push(format!(
+            " ORDER BY {sort_expression} {direction}, uploads.id {direction} LIMIT "
+        ))
+        .push_bind(limit + 1); // Get one more upload to know if there's a next page
+
+    let mut uploads: Vec<RatedUpload> = query
+        .build_query_as::<RatedUploadRow>()
+        .fetch_all(&mut **tx)
+        .await
+        .context("Failed to get uploads")?
+        .into_iter()
+        .map(|row| RatedUpload {
+            upload: row.upload,
+            rating_mean: row.rating_mean,
+            rating_count: row.rating_count,
+        })
+        .collect();
+
+    let has_next_page = uploads.len() > usize::try_from(limit)?;
+    uploads.truncate(usize::try_from(limit)?);
+
+    let next_cursor = uploads.last().filter(|_| has_next_page).map(|last| {
+        UploadCursor {
+            key: SortKey::of(last, sorting.by),
+            id: last.upload.id,
+        }
+        .encode(sorting.by)
+    });
+
+    Ok(Page {
+        items: uploads,
+        next_cursor,
+    })
 }
 
-pub async fn get_all_uploads(
-    mut tx: &mut PgTransaction<'_>,
+pub async fn get_uploads_of_course(
+    tx: &mut PgTransaction<'_>,
+    course_id: Uuid,
     sorting: Option<Sorting>,
-) -> anyhow::Result<Vec<Upload>> {
-    // TODO: implement sorting
-    let _sorting = sorting.unwrap_or_default();
+    limit: i64,
+    cursor: Option<UploadCursor>,
+) -> anyhow::Result<Page<RatedUpload>> {
+    get_uploads_page(tx, Some(course_id), sorting, limit, cursor).await
+}
 
-    sqlx::query_as!(
-        Upload,
-        r#"
-        SELECT
-            uploads.id,
-            upload_name AS name,
-            description,
-            price,
-            uploader,
-            upload_date,
-            last_modified_date,
-            associated_date,
-            upload_type AS "upload_type: _",
-            belongs_to,
-            held_by
-        FROM
-            uploads
-            INNER JOIN courses ON uploads.belongs_to = courses.id
-        "#,
-    )
-    .fetch_all(&mut **tx)
-    .await
-    .context("Failed to get courses")
+pub async fn get_all_uploads(
+    tx: &mut PgTransaction<'_>,
+    sorting: Option<Sorting>,
+    limit: i64,
+    cursor: Option<UploadCursor>,
+) -> anyhow::Result<Page<RatedUpload>> {
+    get_uploads_page(tx, None, sorting, limit, cursor).await
 }
 
+/// Get an upload, unless it has been deleted
 pub async fn get_upload_by_id(
     mut tx: &mut PgTransaction<'_>,
     upload_id: Uuid,
@@ -122,6 +282,7 @@ pub async fn get_upload_by_id(
             uploads
         WHERE
             uploads.id = $1
+            AND uploads.deleted_at IS NULL
         "#,
         upload_id,
     )
@@ -209,7 +370,6 @@ pub async fn get_upload_by_id_and_join_course(
 }
 
 pub async fn update_upload(mut tx: &mut PgTransaction<'_>, upload: &Upload) -> anyhow::Result<()> {
-    // FIXME impl upload_type
     sqlx::query!(
         "
         UPDATE
@@ -218,14 +378,26 @@ pub async fn update_upload(mut tx: &mut PgTransaction<'_>, upload: &Upload) -> a
             upload_name = $1,
             description = $2,
             price = $3,
-            last_modified_date = $4
+            uploader = $4,
+            upload_date = $5,
+            last_modified_date = $6,
+            associated_date = $7,
+            upload_type = $8,
+            belongs_to = $9,
+            held_by = $10
         WHERE
-            id = $5
+            id = $11
         ",
         upload.name,
         upload.description,
         upload.price,
+        upload.uploader,
+        upload.upload_date,
         upload.last_modified_date,
+        upload.associated_date,
+        upload.upload_type.clone() as UploadType,
+        upload.belongs_to,
+        upload.held_by,
         upload.id,
     )
     .execute(&mut **tx)
@@ -235,6 +407,75 @@ pub async fn update_upload(mut tx: &mut PgTransaction<'_>, upload: &Upload) -> a
     Ok(())
 }
 
+/// A moderator's edit of an upload
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UploadEdit {
+    pub id: Uuid,
+    pub upload_id: Uuid,
+
+    /// The ID of the moderator who made the edit
+    pub edited_by: Uuid,
+    pub edited_at: NaiveDateTime,
+
+    /// The changed fields, as `{ "field": { "old": ..., "new": ... } }`
+    #[schema(value_type = Object)]
+    pub changes: serde_json::Value,
+}
+
+/// Remember which moderator changed which fields of an upload
+pub async fn record_upload_edit(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+    edited_by: Uuid,
+    changes: &serde_json::Value,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        INSERT INTO
+            upload_edits (upload_id, edited_by, edited_at, changes)
+        VALUES
+            ($1, $2, $3, $4)
+        ",
+        upload_id,
+        edited_by,
+        chrono::Utc::now().naive_utc(),
+        changes,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to record upload edit")?;
+
+    Ok(())
+}
+
+/// Get the moderators' edits of an upload, oldest first
+pub async fn get_upload_edits(
+    tx: &mut PgTransaction<'_>,
+    upload_id: Uuid,
+) -> anyhow::Result<Vec<UploadEdit>> {
+    sqlx::query_as!(
+        UploadEdit,
+        "
+        SELECT
+            id,
+            upload_id,
+            edited_by,
+            edited_at,
+            changes
+        FROM
+            upload_edits
+        WHERE
+            upload_id = $1
+        ORDER BY
+            edited_at
+        ",
+        upload_id,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to get upload edits")
+}
+
 pub async fn create_upload(mut tx: &mut PgTransaction<'_>, upload: &Upload) -> anyhow::Result<()> {
     sqlx::query!(
         "
@@ -2,10 +2,13 @@ use std::{str::FromStr, sync::Arc};
 
 use anyhow::Context;
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+use chrono::NaiveDateTime;
 use email_address::EmailAddress;
 use justerror::Error;
 use rand::rngs::OsRng;
+use serde::Serialize;
 use sqlx::{self, Acquire, PgTransaction, Pool, Postgres};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
@@ -20,15 +23,20 @@ use super::SelectExists;
 pub enum UserError {
     EmailInvalid(Arc<str>),
     EmailTaken(Arc<str>), // Zero-copy string; gotta go fast
+    /// The domain doesn't belong to any university, and the address isn't an exception
+    EmailDomainNotAllowed(Arc<str>),
     QueryError(sqlx::Error),
 }
 
 /// Register a user in the database with checks for email validity and uniqueness.
 ///
+/// The user's (primary) email starts out unverified; returns its id.
+///
 /// # Errors
 ///
 /// - [`UserError::EmailInvalid`] if the email is invalid
 /// - [`UserError::EmailTaken`] if the email is already taken
+/// - [`UserError::EmailDomainNotAllowed`] if the email doesn't belong to a university
 /// - [`UserError::QueryError`] if the query fails (including the underlying database error)
 ///
 /// # Panics
@@ -37,7 +45,7 @@ pub enum UserError {
 pub async fn register(
     mut tx: &mut PgTransaction<'_>,
     user: UserWithEmails,
-) -> Result<(), UserError> {
+) -> Result<Uuid, UserError> {
     let UserWithEmails {
         id,
         first_names,
@@ -65,7 +73,11 @@ pub async fn register(
                     FROM
                         emails
                     WHERE
-                        address = $1
+                        address = LOWER($1)
+                        AND (
+                            status = 'verified'
+                            OR id IN (SELECT primary_email FROM users)
+                        )
                 )
             ",
             email
@@ -83,20 +95,13 @@ pub async fn register(
     let mail_uuid = Uuid::new_v4();
     let email_address = EmailAddress::from_str(&emails[0]).unwrap(); // We validated this earlier
 
-    // HACK the email domain verification has been disabled for now
+    let university = find_university_of_email(tx, &email_address)
+        .await?
+        .ok_or_else(|| UserError::EmailDomainNotAllowed(Arc::from(emails[0].as_str())))?;
+
     sqlx::query!(
         "
-        WITH matching_university AS (
-            SELECT
-                id
-            FROM
-                universities
-             WHERE
-                $1 = ANY (email_domain_names)
-                OR true
-            LIMIT 1
-        ),
-        new_email AS (
+        WITH new_email AS (
             INSERT INTO
                 emails (
                     id,
@@ -106,18 +111,7 @@ pub async fn register(
                     STATUS
                 )
             VALUES
-                (
-                    $2,
-                    $3,
-                    $4,
-                    (
-                        SELECT
-                            id
-                        FROM
-                            matching_university
-                    ),
-                    'unverified'
-                )
+                ($1, LOWER($2), $3, $4, 'unverified')
         )
         INSERT INTO
             users (
@@ -133,12 +127,11 @@ pub async fn register(
         VALUES
             ($5, $6, $7, $8, $9, $10, $11, $12)
         ",
-        // University
-        email_address.domain(),
         // Email
         mail_uuid,
         &*emails[0],
         id,
+        university,
         // User
         id,
         &*first_names,
@@ -153,7 +146,67 @@ pub async fn register(
     .await
     .map_err(UserError::QueryError)?;
 
-    Ok(())
+    super::email::release_unverified_address(tx, &emails[0], mail_uuid)
+        .await
+        .map_err(UserError::QueryError)?;
+
+    Ok(mail_uuid)
+}
+
+/// Find out which university an email address belongs to, if it may register at all.
+///
+/// Addresses on the admins' list of exceptions may always register, with the university given there
+/// (if any); other addresses need a domain (or subdomain) of a university, the most specific one wins.
+/// Returns `None` if the address may not register, and `Some(None)` if it may, but without a university.
+pub async fn find_university_of_email(
+    tx: &mut PgTransaction<'_>,
+    email_address: &EmailAddress,
+) -> Result<Option<Option<Uuid>>, UserError> {
+    let exception = sqlx::query_scalar!(
+        "
+        SELECT
+            university
+        FROM
+            registration_exceptions
+        WHERE
+            address = LOWER($1)
+        ",
+        email_address.as_str()
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(UserError::QueryError)?;
+
+    if let Some(Some(university)) = exception {
+        return Ok(Some(Some(university)));
+    }
+
+    let by_domain = sqlx::query_scalar!(
+        "
+        SELECT
+            universities.id
+        FROM
+            universities,
+            UNNEST(email_domain_names) AS domain
+        WHERE
+            $1 = LOWER(domain)
+            OR RIGHT($1, LENGTH(domain) + 1) = '.' || LOWER(domain)
+        ORDER BY
+            LENGTH(domain) DESC
+        LIMIT
+            1
+        ",
+        email_address.domain().to_lowercase()
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(UserError::QueryError)?;
+
+    Ok(match (exception, by_domain) {
+        (_, Some(university)) => Some(Some(university)),
+        (Some(_), None) => Some(None),
+        (None, None) => None,
+    })
 }
 
 pub async fn get_active_user_by_email(mut tx: &mut PgTransaction<'_>, email: &str) -> Option<User> {
@@ -173,7 +226,8 @@ pub async fn get_active_user_by_email(mut tx: &mut PgTransaction<'_>, email: &st
             users AS u
             INNER JOIN emails ON primary_email = emails.id
         WHERE
-            emails.address = $1
+            emails.address = LOWER($1)
+            AND u.deleted_at IS NULL
         "#,
         email
     )
@@ -204,10 +258,71 @@ pub fn make_pwd_hash(pwd: &str) -> String {
     password_hash
 }
 
+/// How many users still have a password hash from the old Egiraffe
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LegacyPasswordReport {
+    pub total_users: i64,
+    pub legacy_hash_users: i64,
+}
+
+/// Count the users whose passwords are still SHA-512 crypt (`$6$`) hashes.
+///
+/// These get upgraded to Argon2 when the user logs in; the rest will need a password reset.
+pub async fn get_legacy_password_report(
+    tx: &mut PgTransaction<'_>,
+) -> anyhow::Result<LegacyPasswordReport> {
+    sqlx::query_as!(
+        LegacyPasswordReport,
+        r#"
+        SELECT
+            COUNT(*) AS "total_users!",
+            COUNT(*) FILTER (
+                WHERE
+                    password_hash LIKE '$6$%'
+            ) AS "legacy_hash_users!"
+        FROM
+            users
+        WHERE
+            id <> '00000000-0000-0000-0000-000000000000'
+        "#
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to count legacy password hashes")
+}
+
+/// Replace the password of a user with a new one (gets hashed)
+pub async fn set_password(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    password: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            password_hash = $1
+        WHERE
+            id = $2
+        ",
+        make_pwd_hash(password),
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to set password")?;
+
+    Ok(())
+}
+
 pub async fn get_user_by_session(
     mut tx: &mut PgTransaction<'_>,
     session_cookie: &str,
 ) -> anyhow::Result<User> {
+    let (created_cutoff, last_seen_cutoff) =
+        super::session::expiry_cutoffs(chrono::Utc::now().naive_utc());
+
     let user = sqlx::query!(
         "
         SELECT
@@ -222,8 +337,12 @@ pub async fn get_user_by_session(
             INNER JOIN sessions ON u.id = sessions.of_user
         WHERE
             sessions.token = $1
+            AND sessions.created_at >= $2
+            AND sessions.last_seen_at >= $3
         ",
-        session_cookie
+        session_cookie,
+        created_cutoff,
+        last_seen_cutoff,
     )
     .fetch_one(&mut **tx)
     .await?;
@@ -275,8 +394,8 @@ pub async fn get_user_by_id(
         ",
         current_user_id
     )
-    .fetch_one(&mut **tx)
-    .await
+    .fetch_optional(&mut **tx)
+    .await?
     .map(|user| UserWithEmails {
         id: user.id,
         first_names: user.first_names.expect("User has no first name").into(),
@@ -289,7 +408,7 @@ pub async fn get_user_by_id(
         nick: user.nick,
     });
 
-    Ok(Some(user?))
+    Ok(user)
 }
 
 /// Update an existing user in the database.
@@ -337,3 +456,219 @@ pub async fn update_user(
 
     Ok(())
 }
+
+/// A user as admins see them, with their email address and whether they're suspended or deleted
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AdminUser {
+    pub id: Uuid,
+    pub first_names: Option<String>,
+    pub last_name: Option<String>,
+    pub nick: Option<String>,
+    pub primary_email: Option<String>,
+    pub user_role: i16,
+    pub totp_enabled: bool,
+
+    /// Whether a suspension is in effect right now
+    pub suspended: bool,
+
+    /// The end of the current suspension; `None` if it's indefinite (or there's none)
+    pub suspended_until: Option<NaiveDateTime>,
+    pub suspension_reason: Option<String>,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// Find users whose (any) email address, names, or nick contain the query, ignoring case
+pub async fn search_users(
+    tx: &mut PgTransaction<'_>,
+    query: &str,
+    include_deleted: bool,
+    limit: i64,
+) -> anyhow::Result<Vec<AdminUser>> {
+    // Match the query literally, not as a `LIKE` pattern
+    let pattern = format!(
+        "%{}%",
+        query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    sqlx::query_as!(
+        AdminUser,
+        r#"
+        SELECT
+            u.id,
+            u.first_names,
+            u.last_name,
+            u.nick,
+            emails.address AS primary_email,
+            u.user_role,
+            u.totp_secret IS NOT NULL AS "totp_enabled!",
+            suspension.id IS NOT NULL AS "suspended!",
+            suspension.suspended_until AS "suspended_until?",
+            suspension.reason AS "suspension_reason?",
+            u.deleted_at
+        FROM
+            users AS u
+            INNER JOIN emails ON u.primary_email = emails.id
+            LEFT JOIN LATERAL (
+                SELECT
+                    id,
+                    reason,
+                    suspended_until
+                FROM
+                    user_suspensions
+                WHERE
+                    of_user = u.id
+                    AND lifted_at IS NULL
+                    AND (
+                        suspended_until IS NULL
+                        OR suspended_until > $2
+                    )
+                ORDER BY
+                    suspended_until DESC NULLS FIRST
+                LIMIT
+                    1
+            ) AS suspension ON true
+        WHERE
+            u.id <> '00000000-0000-0000-0000-000000000000'
+            AND (
+                $3
+                OR u.deleted_at IS NULL
+            )
+            AND (
+                u.first_names ILIKE $1
+                OR u.last_name ILIKE $1
+                OR CONCAT_WS(' ', u.first_names, u.last_name) ILIKE $1
+                OR u.nick ILIKE $1
+                OR EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        emails AS any_email
+                    WHERE
+                        any_email.belongs_to_user = u.id
+                        AND any_email.address ILIKE $1
+                )
+            )
+        ORDER BY
+            u.last_name,
+            u.first_names,
+            u.id
+        LIMIT
+            $4
+        "#,
+        pattern,
+        chrono::Utc::now().naive_utc(),
+        include_deleted,
+        limit,
+    )
+    .fetch_all(&mut **tx)
+    .await
+    .context("Failed to search users")
+}
+
+/// Whether a user exists, and if so, whether their account has been deleted
+pub async fn is_user_deleted(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<bool>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            deleted_at IS NOT NULL AS "deleted!"
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to check if user is deleted")
+}
+
+pub async fn set_user_role(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    user_role: i16,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            user_role = $1
+        WHERE
+            id = $2
+        ",
+        user_role,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to set user role")?;
+
+    Ok(())
+}
+
+/// Delete a user's account, but keep the row, since purchases & transactions refer to it.
+///
+/// Their email addresses get disabled, and their uploads go to the nil user;
+/// returns how many uploads that were. The caller should log them out, too.
+pub async fn soft_delete_user(
+    tx: &mut PgTransaction<'_>,
+    user_id: Uuid,
+    admin_id: Uuid,
+) -> anyhow::Result<u64> {
+    sqlx::query!(
+        "
+        UPDATE
+            users
+        SET
+            deleted_at = $2,
+            deleted_by = $3
+        WHERE
+            id = $1
+        ",
+        user_id,
+        chrono::Utc::now().naive_utc(),
+        admin_id,
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to delete user")?;
+
+    sqlx::query!(
+        "
+        UPDATE
+            emails
+        SET
+            STATUS = 'disabled'
+        WHERE
+            belongs_to_user = $1
+        ",
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to disable emails of deleted user")?;
+
+    let reassigned = sqlx::query!(
+        "
+        UPDATE
+            uploads
+        SET
+            uploader = '00000000-0000-0000-0000-000000000000'
+        WHERE
+            uploader = $1
+        ",
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .context("Failed to reassign uploads of deleted user")?;
+
+    Ok(reassigned.rows_affected())
+}
@@ -0,0 +1,344 @@
+//! The errors an API handler can respond with
+
+use std::{borrow::Cow, sync::Arc};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDateTime;
+use justerror::Error;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    db::{
+        activation::ActivationError, deletion::RestoreError, password_reset::PasswordResetError,
+        purchase::PurchaseError, university::DeleteUniversityError, user::UserError,
+    },
+    mail::MailError,
+};
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// The body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorRes {
+    /// Always `false`
+    pub success: bool,
+
+    /// The machine-readable error code, e.g. `invalid_credentials` or `insufficient_ecs`
+    pub code: &'static str,
+    pub message: Cow<'static, str>,
+
+    /// The maximum file size in bytes, only for `file_too_large`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+
+    /// The user's current balance, only for `insufficient_ecs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ecs_balance: Option<i64>,
+
+    /// The price of the upload, only for `insufficient_ecs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<i16>,
+
+    /// Why the account is suspended, only for `account_suspended`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspension_reason: Option<String>,
+
+    /// Until when the account is suspended, only for `account_suspended`; absent if indefinitely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suspended_until: Option<NaiveDateTime>,
+}
+
+/// An error response of the API.
+///
+/// Besides a human-readable (English) `message`, the response body contains a `code`,
+/// which stays the same so clients can tell the errors apart (and translate them).
+#[Error]
+pub enum ApiError {
+    /// The request is malformed, or a value in it is invalid
+    BadRequest(Cow<'static, str>),
+    /// The caller needs to be logged in
+    Unauthorized,
+    /// The caller isn't allowed to do this
+    Forbidden(Cow<'static, str>),
+    NotFound(Cow<'static, str>),
+    /// The request clashes with the current state, e.g. something already exists
+    Conflict(Cow<'static, str>),
+    /// The caller has to wait before trying again
+    TooManyRequests(Cow<'static, str>),
+    NotImplemented,
+
+    /// Wrong email or password; doesn't tell which, so accounts can't be enumerated
+    InvalidCredentials,
+    /// The account exists but its email hasn't been activated yet
+    EmailNotVerified,
+    /// The login needs a code from the user's authenticator
+    TotpRequired,
+    InvalidTotpCode,
+    /// Moderators & admins have to enable two-factor authentication first
+    TotpMandatory,
+    /// Too many failed logins for this account or IP address
+    LoginLockedOut,
+    /// An admin has suspended the account
+    #[error(fmt = debug)]
+    AccountSuspended {
+        reason: String,
+        until: Option<NaiveDateTime>,
+    },
+    /// An emailed token (activation, password reset) doesn't exist or has been used already
+    InvalidToken,
+    TokenExpired,
+    EmailInvalid(Arc<str>),
+    EmailTaken(Arc<str>),
+    /// Only addresses of a university (or ones an admin has made an exception for) may register
+    EmailDomainNotAllowed(Arc<str>),
+    /// An uploaded file exceeds the maximum size, in bytes
+    FileTooLarge {
+        max_size: u64,
+    },
+    AlreadyPurchased,
+    InsufficientEcs {
+        balance: i64,
+        price: i16,
+    },
+
+    /// Sending an email failed
+    Mail(MailError),
+    /// A database query failed
+    Database(sqlx::Error),
+    /// Anything else that went wrong on our side
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_)
+            | Self::InvalidCredentials
+            | Self::InvalidToken
+            | Self::TokenExpired
+            | Self::InvalidTotpCode
+            | Self::EmailInvalid(_)
+            | Self::EmailDomainNotAllowed(_)
+            | Self::AlreadyPurchased => StatusCode::BAD_REQUEST,
+            Self::Unauthorized | Self::TotpRequired => StatusCode::UNAUTHORIZED,
+            Self::InsufficientEcs { .. } => StatusCode::PAYMENT_REQUIRED,
+            Self::Forbidden(_)
+            | Self::EmailNotVerified
+            | Self::TotpMandatory
+            | Self::AccountSuspended { .. } => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) | Self::EmailTaken(_) => StatusCode::CONFLICT,
+            Self::FileTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyRequests(_) | Self::LoginLockedOut => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::Mail(_) | Self::Database(_) | Self::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The machine-readable code of this error, which doesn't change between versions
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::TooManyRequests(_) => "too_many_requests",
+            Self::NotImplemented => "not_implemented",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::EmailNotVerified => "email_not_verified",
+            Self::TotpRequired => "totp_required",
+            Self::InvalidTotpCode => "invalid_totp_code",
+            Self::TotpMandatory => "totp_mandatory",
+            Self::LoginLockedOut => "login_locked_out",
+            Self::AccountSuspended { .. } => "account_suspended",
+            Self::InvalidToken => "invalid_token",
+            Self::TokenExpired => "token_expired",
+            Self::EmailInvalid(_) => "email_invalid",
+            Self::EmailTaken(_) => "email_taken",
+            Self::EmailDomainNotAllowed(_) => "email_domain_not_allowed",
+            Self::FileTooLarge { .. } => "file_too_large",
+            Self::AlreadyPurchased => "already_purchased",
+            Self::InsufficientEcs { .. } => "insufficient_ecs",
+            Self::Mail(_) => "mail_error",
+            Self::Database(_) => "database_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The human-readable message; internal errors don't reveal any details
+    pub fn message(&self) -> Cow<'static, str> {
+        match self {
+            Self::BadRequest(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::TooManyRequests(message) => message.clone(),
+            Self::Unauthorized => "You are not logged in".into(),
+            Self::NotImplemented => "Not implemented".into(),
+            Self::InvalidCredentials => "Wrong email or password".into(),
+            Self::EmailNotVerified => "Email not verified".into(),
+            Self::TotpRequired => "Two-factor code required".into(),
+            Self::InvalidTotpCode => "Invalid two-factor code".into(),
+            Self::TotpMandatory => {
+                "Two-factor authentication is required for moderators and admins".into()
+            }
+            Self::LoginLockedOut => "Too many failed login attempts".into(),
+            Self::AccountSuspended { .. } => "Your account has been suspended".into(),
+            Self::InvalidToken => "Invalid token".into(),
+            Self::TokenExpired => "Token expired".into(),
+            Self::EmailInvalid(email) => format!("Invalid email address: {email}").into(),
+            Self::EmailTaken(email) => format!("Email address already taken: {email}").into(),
+            Self::EmailDomainNotAllowed(email) => {
+                format!("Email address doesn't belong to a university: {email}").into()
+            }
+            Self::FileTooLarge { .. } => "File is too large".into(),
+            Self::AlreadyPurchased => "User has already purchased this upload".into(),
+            Self::InsufficientEcs { .. } => "Insufficient ECs".into(),
+            Self::Mail(_) => "Failed to send email".into(),
+            Self::Database(_) | Self::Internal(_) => "Internal server error".into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        match &self {
+            Self::Mail(error) => log::error!("Failed to send email: {error}"),
+            Self::Database(error) => log::error!("Database error: {error}"),
+            Self::Internal(error) => log::error!("Internal error: {error:#}"),
+            _ => log::info!("Request failed with {status}: {}", self.code()),
+        }
+
+        let mut body = ErrorRes {
+            success: false,
+            code: self.code(),
+            message: self.message(),
+            max_file_size: None,
+            ecs_balance: None,
+            price: None,
+            suspension_reason: None,
+            suspended_until: None,
+        };
+
+        // Some errors come with details the client can show
+        match self {
+            Self::FileTooLarge { max_size } => body.max_file_size = Some(max_size),
+            Self::InsufficientEcs { balance, price } => {
+                body.ecs_balance = Some(balance);
+                body.price = Some(price);
+            }
+            Self::AccountSuspended { reason, until } => {
+                body.suspension_reason = Some(reason);
+                body.suspended_until = until;
+            }
+            _ => {}
+        }
+
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        Self::Database(error)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Internal(error)
+    }
+}
+
+impl From<UserError> for ApiError {
+    fn from(error: UserError) -> Self {
+        match error {
+            UserError::EmailInvalid(email) => Self::EmailInvalid(email),
+            UserError::EmailTaken(email) => Self::EmailTaken(email),
+            UserError::EmailDomainNotAllowed(email) => Self::EmailDomainNotAllowed(email),
+            UserError::QueryError(error) => Self::Database(error),
+        }
+    }
+}
+
+impl From<MailError> for ApiError {
+    fn from(error: MailError) -> Self {
+        match error {
+            MailError::EmailInvalid(email) => Self::EmailInvalid(email),
+            error @ MailError::ServerError() => Self::Mail(error),
+        }
+    }
+}
+
+impl From<ActivationError> for ApiError {
+    fn from(error: ActivationError) -> Self {
+        match error {
+            ActivationError::InvalidToken => Self::InvalidToken,
+            ActivationError::TokenExpired => Self::TokenExpired,
+            ActivationError::AddressTaken => {
+                Self::Conflict("Another account has verified this address already".into())
+            }
+            ActivationError::QueryError(error) => Self::Internal(error),
+        }
+    }
+}
+
+impl From<PasswordResetError> for ApiError {
+    fn from(error: PasswordResetError) -> Self {
+        match error {
+            PasswordResetError::InvalidToken => Self::InvalidToken,
+            PasswordResetError::TokenExpired => Self::TokenExpired,
+            PasswordResetError::QueryError(error) => Self::Internal(error),
+        }
+    }
+}
+
+impl From<PurchaseError> for ApiError {
+    fn from(error: PurchaseError) -> Self {
+        match error {
+            PurchaseError::AlreadyPurchased => Self::AlreadyPurchased,
+            PurchaseError::InsufficientFunds { balance, price } => {
+                Self::InsufficientEcs { balance, price }
+            }
+            PurchaseError::QueryError(error) => Self::Internal(error),
+        }
+    }
+}
+
+impl From<DeleteUniversityError> for ApiError {
+    fn from(error: DeleteUniversityError) -> Self {
+        match error {
+            DeleteUniversityError::NotFound => Self::NotFound("No such university".into()),
+            DeleteUniversityError::InUse => {
+                Self::Conflict("Courses or email addresses still belong to this university".into())
+            }
+            DeleteUniversityError::QueryError(error) => Self::Internal(error),
+        }
+    }
+}
+
+impl From<RestoreError> for ApiError {
+    fn from(error: RestoreError) -> Self {
+        match error {
+            RestoreError::NotFound => Self::NotFound("No such upload or file".into()),
+            RestoreError::NotDeleted => Self::Conflict("This has not been deleted".into()),
+            RestoreError::UploadDeleted => {
+                Self::Conflict("The upload of this file is deleted; restore the upload".into())
+            }
+            RestoreError::BlobPurged => {
+                Self::Conflict("The content of this file has been purged already".into())
+            }
+            RestoreError::QueryError(error) => Self::Internal(error),
+        }
+    }
+}
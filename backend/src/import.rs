@@ -309,7 +309,10 @@ async fn import_users(
         };
 
         // HACK handle errors (this is to IGNORE duplicate emails)
-        let _ = db::user::register(&mut tx, user).await;
+        if let Ok(email_id) = db::user::register(&mut tx, user).await {
+            // Users of the old Egiraffe have been using their accounts already
+            db::activation::mark_email_verified(tx, email_id).await?;
+        }
 
         bar.inc(1);
     }
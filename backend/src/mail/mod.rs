@@ -59,20 +59,90 @@ pub async fn send_activation_mail(first_names: &str, last_name: &str, email: &st
         first_names => first_names,
         last_name => last_name,
         subject => subject,
+        baseurl => CONF.baseurl,
         activationToken => token,
         acitvationValidityPeriod => CONF.acitvationlinkvalidityperiod
     };
 
     let html = ENV.get().unwrap().get_template("activationmail.html").unwrap().render(vars.clone()).unwrap();
-    println!("{}", html);
     let txt = ENV.get().unwrap().get_template("activationmail.txt").unwrap().render(vars).unwrap();
-    println!("{}", txt);
 
+    send_mail(first_names, last_name, email, subject, html, txt).await
+}
+
+pub async fn send_password_reset_mail(first_names: &str, last_name: &str, email: &str, token: &str, validity_minutes: i64) -> Result<(), MailError> {
+    let subject = "Passwort zurücksetzen"; //TODO: i18n
+
+    let vars = context! {
+        first_names => first_names,
+        last_name => last_name,
+        subject => subject,
+        baseurl => CONF.baseurl,
+        resetToken => token,
+        resetValidityPeriod => validity_minutes
+    };
+
+    let html = ENV.get().unwrap().get_template("passwordresetmail.html").unwrap().render(vars.clone()).unwrap();
+    let txt = ENV.get().unwrap().get_template("passwordresetmail.txt").unwrap().render(vars).unwrap();
+
+    send_mail(first_names, last_name, email, subject, html, txt).await
+}
+
+/// Asks a user to confirm an address they added to their account
+pub async fn send_email_verification_mail(first_names: &str, last_name: &str, email: &str, token: &str) -> Result<(), MailError> {
+    let subject = "Bestätige deine Email-Adresse"; //TODO: i18n
+
+    let vars = context! {
+        first_names => first_names,
+        last_name => last_name,
+        subject => subject,
+        baseurl => CONF.baseurl,
+        address => email,
+        activationToken => token,
+        acitvationValidityPeriod => CONF.acitvationlinkvalidityperiod
+    };
+
+    let html = ENV.get().unwrap().get_template("emailverificationmail.html").unwrap().render(vars.clone()).unwrap();
+    let txt = ENV.get().unwrap().get_template("emailverificationmail.txt").unwrap().render(vars).unwrap();
+
+    send_mail(first_names, last_name, email, subject, html, txt).await
+}
+
+/// Lets an uploader know that a moderator approved or rejected some of their files
+pub async fn send_moderation_mail(first_names: &str, last_name: &str, email: &str, approved: bool, files: &[(&str, &str)], reason: Option<&str>) -> Result<(), MailError> {
+    let subject = if approved { "Deine Dateien wurden freigegeben" } else { "Deine Dateien wurden abgelehnt" }; //TODO: i18n
+
+    // Pairs of upload & file name
+    let files: Vec<_> = files.iter().map(|(upload_name, file_name)| context! { upload_name, file_name }).collect();
+
+    let vars = context! {
+        first_names => first_names,
+        last_name => last_name,
+        subject => subject,
+        baseurl => CONF.baseurl,
+        approved => approved,
+        files => files,
+        reason => reason
+    };
+
+    let html = ENV.get().unwrap().get_template("moderationmail.html").unwrap().render(vars.clone()).unwrap();
+    let txt = ENV.get().unwrap().get_template("moderationmail.txt").unwrap().render(vars).unwrap();
+
+    send_mail(first_names, last_name, email, subject, html, txt).await
+}
+
+/// Sends a mail with a plain text & an HTML version
+async fn send_mail(first_names: &str, last_name: &str, email: &str, subject: &str, html: String, txt: String) -> Result<(), MailError> {
+    if !CONF.mail.activated {
+        // Print the mail instead, so its links can still be followed during development
+        log::info!("Mails are deactivated, not sending \"{subject}\" to {email}:\n{txt}");
+        return Ok(());
+    }
 
     let email = Message::builder()
         .from(Mailbox::new(Some(CONF.mail.sendername.to_owned()), Address::from_str(&CONF.mail.senderemail).unwrap()))
         //TODO: Encode Malicious characters!
-        .to(Mailbox::new(format!("{first_names} {last_name}").into(), Address::from_str(email).expect("Invalid Mail Address")))
+        .to(Mailbox::new(format!("{first_names} {last_name}").into(), Address::from_str(email).map_err(|_| MailError::EmailInvalid(email.into()))?))
         .subject(subject)
 //        .header(ContentType::TEXT_MULTIPART)
         .multipart(
@@ -80,20 +150,21 @@ pub async fn send_activation_mail(first_names: &str, last_name: &str, email: &st
                 .singlepart(
                     SinglePart::builder()
                         .header(header::ContentType::TEXT_PLAIN)
-                        .body(String::from(txt)),
+                        .body(txt),
                 )
                 .singlepart(
                     SinglePart::builder()
                         .header(header::ContentType::TEXT_HTML)
-                        .body(String::from(html)),
+                        .body(html),
                 ),
         )
         .expect("failed to build email");
 
-    // Send the email
-    tokio::spawn(async move {
-        let mailer = MAILER.get().unwrap().lock().await;
-        mailer.send(email).await.expect("Error sending Mail");
-    });
+    // Send the email; every send opens its own connection, so the lock isn't held while sending
+    let mailer = MAILER.get().unwrap().lock().await.clone();
+    mailer.send(email).await.map_err(|e| {
+        log::error!("Error sending Mail: {e}");
+        MailError::ServerError()
+    })?;
     Ok(())
 }
\ No newline at end of file
@@ -9,8 +9,10 @@ mod api;
 mod conf;
 mod data;
 mod db;
+mod error;
 mod legacy;
 mod mail;
+mod storage;
 mod util;
 
 #[cfg(feature = "import")]
@@ -57,9 +59,15 @@ async fn server() -> anyhow::Result<()> {
     // Prepare Mail system
     mail::init();
 
+    // Prepare the file storage
+    storage::init().context("Storage setup failed")?;
+
     sqlx::migrate!().run(db_pool).await.unwrap();
     log::info!("Database migrations completed");
 
+    // Remove the content of deleted files once it's not needed anymore
+    storage::spawn_purge_job();
+
     #[cfg(not(feature = "prod"))]
     if CONF.database.debugdefaultentries {
         let mut tx = db_pool.begin().await?;
@@ -84,10 +92,13 @@ async fn server() -> anyhow::Result<()> {
 
     log::info!("Listening on http://{addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .context("Failed to start server")
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .context("Failed to start server")
+    .unwrap();
 
     Ok(())
 }
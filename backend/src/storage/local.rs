@@ -0,0 +1,104 @@
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use super::{Blob, BlobStore};
+
+/// Stores blobs in a directory on the local file system
+///
+/// To reduce the number of inodes in one directory, blobs are grouped into subdirectories
+/// named after the first byte of their hash, i.e. `<root>/ab/abcdef...`.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_of(&self, sha3_256: &str) -> PathBuf {
+        self.root.join(&sha3_256[..2]).join(sha3_256)
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    /// Staged files live inside the root directory, so they're on the same file system
+    /// as their final location, and can be moved there atomically
+    fn staging_file(&self) -> io::Result<NamedTempFile> {
+        std::fs::create_dir_all(&self.root)?;
+
+        NamedTempFile::with_prefix_in(".incoming-", &self.root)
+    }
+
+    async fn put(&self, sha3_256: &str, staged: NamedTempFile) -> anyhow::Result<bool> {
+        let path = self.path_of(sha3_256);
+
+        tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(path.parent().unwrap())?;
+
+            match staged.persist_noclobber(&path) {
+                Ok(_) => Ok(true),
+                // The staged file gets deleted when the error (which owns it) is dropped
+                Err(err) if err.error.kind() == ErrorKind::AlreadyExists => Ok(false),
+                Err(err) => Err(err.error),
+            }
+        })
+        .await?
+        .context("Failed to move file into place")
+    }
+
+    async fn get(
+        &self,
+        sha3_256: &str,
+        range: Option<RangeInclusive<u64>>,
+    ) -> anyhow::Result<Option<Blob>> {
+        let mut fs_file = match tokio::fs::File::open(self.path_of(sha3_256)).await {
+            Ok(fs_file) => fs_file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Failed to open file"),
+        };
+
+        let size = fs_file
+            .metadata()
+            .await
+            .context("Failed to get file size")?
+            .len();
+
+        let Some(range) = range else {
+            return Ok(Some(Blob {
+                size,
+                stream: Box::pin(ReaderStream::new(fs_file)),
+            }));
+        };
+
+        fs_file
+            .seek(SeekFrom::Start(*range.start()))
+            .await
+            .context("Failed to seek in file")?;
+        let range_size = range.end() - range.start() + 1;
+
+        Ok(Some(Blob {
+            size: range_size,
+            stream: Box::pin(ReaderStream::new(fs_file.take(range_size))),
+        }))
+    }
+
+    async fn delete(&self, sha3_256: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path_of(sha3_256)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).context("Failed to delete file")
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Where the content of uploaded files (blobs) is kept
+//!
+//! Blobs are addressed by their SHA3-256 hash, so identical files share a single blob.
+//! The database only stores the hash; which backend actually holds the bytes is configured
+//! in `conf::StorageConfig`.
+
+mod local;
+mod purge;
+mod s3;
+
+use std::{io, ops::RangeInclusive, pin::Pin};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use once_cell::sync::OnceCell;
+use tempfile::NamedTempFile;
+
+use crate::conf::{StorageBackend, CONF};
+
+pub use self::{local::LocalBlobStore, purge::spawn_purge_job, s3::S3BlobStore};
+
+static BLOB_STORE: OnceCell<Box<dyn BlobStore>> = OnceCell::new();
+
+pub type BlobStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// The content of a stored file (or a part of it), ready to be streamed to a client
+pub struct Blob {
+    /// The size in bytes of the content in `stream`
+    pub size: u64,
+    pub stream: BlobStream,
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Creates a temporary file to receive a blob in, before its hash is known
+    fn staging_file(&self) -> io::Result<NamedTempFile> {
+        NamedTempFile::with_prefix(".incoming-")
+    }
+
+    /// Stores a staged blob under its hash
+    ///
+    /// Returns `false` if a blob with this hash already existed; as blobs are addressed by their
+    /// content, the existing one is kept and the staged file is discarded.
+    async fn put(&self, sha3_256: &str, staged: NamedTempFile) -> anyhow::Result<bool>;
+
+    /// Gets a blob by its hash, or `None` if there is no such blob
+    ///
+    /// If a byte range is given, only that part of the blob is returned; the range has to be
+    /// within the blob.
+    async fn get(
+        &self,
+        sha3_256: &str,
+        range: Option<RangeInclusive<u64>>,
+    ) -> anyhow::Result<Option<Blob>>;
+
+    /// Deletes a blob by its hash; deleting a blob which doesn't exist is not an error
+    async fn delete(&self, sha3_256: &str) -> anyhow::Result<()>;
+}
+
+/// Sets up the storage backend selected in the config
+pub fn init() -> anyhow::Result<()> {
+    let store: Box<dyn BlobStore> = match CONF.storage.backend {
+        StorageBackend::Local => Box::new(LocalBlobStore::new(&CONF.upload_dir)),
+        StorageBackend::S3 => {
+            Box::new(S3BlobStore::new(&CONF.storage.s3).context("Invalid S3 configuration")?)
+        }
+    };
+
+    log::info!("Storing files using the {:?} backend", CONF.storage.backend);
+
+    BLOB_STORE
+        .set(store)
+        .map_err(|_| anyhow::anyhow!("The blob store was already set up"))
+}
+
+/// The storage backend selected in the config; only available after [`init`]
+pub fn blob_store() -> &'static dyn BlobStore {
+    BLOB_STORE
+        .get()
+        .expect("The blob store has not been set up")
+        .as_ref()
+}
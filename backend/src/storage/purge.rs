@@ -0,0 +1,128 @@
+//! Removes the blobs of deleted files for good, once no file which is still around needs them
+
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::{
+    conf::CONF,
+    db::{self, DB_POOL},
+};
+
+use super::blob_store;
+
+/// How often to look for blobs to purge
+const PURGE_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Purges blobs in the background from now on, see `conf::Config::purgedeletedafter`
+pub fn spawn_purge_job() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match purge_deleted_blobs().await {
+                Ok(0) => {}
+                Ok(purged) => log::info!("Purged {purged} blobs of deleted files"),
+                Err(err) => log::error!("Failed to purge blobs of deleted files: {err:#}"),
+            }
+        }
+    });
+}
+
+/// Purges every blob which may be purged, then deletes the purged blobs from storage; returns how
+/// many were deleted
+async fn purge_deleted_blobs() -> anyhow::Result<usize> {
+    let deleted_before =
+        Utc::now().naive_utc() - chrono::Duration::days(i64::from(CONF.purgedeletedafter));
+
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+    let candidates = db::deletion::get_purgeable_blobs(
+        &mut tx,
+        deleted_before,
+        CONF.purchaserskeepdeleted,
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    // A failing blob shouldn't hold up the others; it's tried again next time
+    for sha3_256 in candidates {
+        if let Err(err) = purge_blob(&sha3_256, deleted_before).await {
+            log::error!("Failed to purge blob {sha3_256}: {err:#}");
+        }
+    }
+
+    // This includes blobs whose deletion failed on earlier runs
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+    let purged = db::deletion::get_purged_blobs(&mut tx).await?;
+    tx.commit().await?;
+
+    let mut deleted = 0;
+
+    for sha3_256 in purged {
+        match delete_purged_blob(&sha3_256).await {
+            Ok(true) => deleted += 1,
+            Ok(false) => {}
+            Err(err) => log::error!("Failed to delete purged blob {sha3_256}: {err:#}"),
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Marks the files of a blob as purged, unless a file has started using it again in the meantime
+///
+/// Once that's committed, the files can't be restored anymore, so the blob can be deleted safely.
+async fn purge_blob(sha3_256: &str, deleted_before: NaiveDateTime) -> anyhow::Result<()> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    // New files with the same content wait until we're done
+    db::file::lock_blob(&mut tx, sha3_256).await?;
+
+    let is_purgeable = !db::deletion::get_purgeable_blobs(
+        &mut tx,
+        deleted_before,
+        CONF.purchaserskeepdeleted,
+        Some(sha3_256),
+    )
+    .await?
+    .is_empty();
+
+    if !is_purgeable {
+        return Ok(());
+    }
+
+    db::deletion::mark_blob_purged(&mut tx, sha3_256).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Deletes a purged blob from storage, unless a new file with the same content uses it by now;
+/// returns whether it was deleted
+///
+/// If deleting fails, the blob stays marked for deletion, and is tried again on the next run.
+async fn delete_purged_blob(sha3_256: &str) -> anyhow::Result<bool> {
+    let mut tx = (*DB_POOL.get().unwrap()).begin().await?;
+
+    // New files with the same content wait until we're done, and then store the blob anew
+    db::file::lock_blob(&mut tx, sha3_256).await?;
+
+    let is_in_use = db::file::is_blob_referenced(&mut tx, sha3_256).await?;
+    if !is_in_use {
+        blob_store().delete(sha3_256).await?;
+    }
+
+    db::deletion::forget_purged_blob(&mut tx, sha3_256).await?;
+
+    tx.commit().await?;
+
+    if !is_in_use {
+        log::info!("Purged blob {sha3_256}");
+    }
+
+    Ok(!is_in_use)
+}
@@ -1,13 +1,22 @@
-use axum::{http::StatusCode, Json};
-use serde_json::json;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::error::ApiError;
 
 #[inline]
-pub fn bad_request(message: &str) -> (StatusCode, Json<serde_json::Value>) {
-    (
-        StatusCode::BAD_REQUEST,
-        Json(json!({
-            "success": false,
-            "message": message,
-        })),
-    )
+pub fn bad_request(message: impl Into<Cow<'static, str>>) -> ApiError {
+    ApiError::BadRequest(message.into())
+}
+
+/// Tells a missing field (`None`) apart from one that's explicitly `null` (`Some(None)`).
+///
+/// Use together with `#[serde(default)]`, so missing fields become `None`.
+#[allow(clippy::option_option)]
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
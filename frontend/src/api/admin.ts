@@ -1,5 +1,5 @@
-import { ErrorResponse, put } from ".";
-import { RedactedUser } from "./users";
+import { ApiError, ErrorResponse, put } from ".";
+import { AuthLevel, RedactedUser } from "./users";
 
 export type GetAllUsersResponse =
   | ErrorResponse
@@ -19,15 +19,44 @@ export type CreateSystemTransactionResponse =
   | ErrorResponse
   | { success: true; };
 
+/** A user as admins see them, found by `searchUsers` */
+export interface AdminUser {
+  id: string;
+  first_names?: string;
+  last_name?: string;
+  nick?: string;
+  primary_email?: string;
+  user_role: AuthLevel;
+  totp_enabled: boolean;
+  /** Whether a suspension is in effect right now */
+  suspended: boolean;
+  /** The end of the current suspension; missing if it's indefinite */
+  suspended_until?: string;
+  suspension_reason?: string;
+  deleted_at?: string;
+}
+
+export type SearchUsersResponse =
+  | ErrorResponse
+  | { success: true; users: AdminUser[] };
+
+export type SuccessResponse =
+  | ErrorResponse
+  | { success: true; };
+
+export type DeleteUserResponse =
+  | ErrorResponse
+  | { success: true; reassigned_uploads: number };
+
 export async function getAllUsers(): Promise<RedactedUser[]> {
   const response = await put<GetAllUsersResponse>("/api/v1/admin/users/get-users");
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.users;
 }
 
 export async function getUserBalance(user_id: string): Promise<number> {
   const response = await put<UserBalanceResponse>("/api/v1/admin/ecs/get-user-balance", { user_id });
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.balance;
 }
 
@@ -35,5 +64,41 @@ export async function createSystemTransaction(
   request: CreateSystemTransactionRequest
 ): Promise<void> {
   const response = await put<GetAllUsersResponse>("/api/v1/admin/ecs/create-system-transaction", request);
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
+}
+
+/** Finds users by (part of) their email addresses, names, or nick */
+export async function searchUsers(query: string, include_deleted = false): Promise<AdminUser[]> {
+  const response = await put<SearchUsersResponse>("/api/v1/admin/users/search-users", { query, include_deleted });
+  if (!response.success) throw new ApiError(response);
+  return response.users;
+}
+
+export async function setUserRole(user_id: string, user_role: AuthLevel): Promise<void> {
+  const response = await put<SuccessResponse>("/api/v1/admin/users/set-user-role", { user_id, user_role });
+  if (!response.success) throw new ApiError(response);
+}
+
+/** Suspends a user for the given number of hours, or indefinitely without one */
+export async function suspendUser(user_id: string, reason: string, duration_hours?: number): Promise<void> {
+  const response = await put<SuccessResponse>("/api/v1/admin/users/suspend-user", { user_id, reason, duration_hours });
+  if (!response.success) throw new ApiError(response);
+}
+
+export async function unsuspendUser(user_id: string): Promise<void> {
+  const response = await put<SuccessResponse>("/api/v1/admin/users/unsuspend-user", { user_id });
+  if (!response.success) throw new ApiError(response);
+}
+
+/** Logs a user out on all their devices */
+export async function logoutUser(user_id: string): Promise<void> {
+  const response = await put<SuccessResponse>("/api/v1/admin/users/logout-user", { user_id });
+  if (!response.success) throw new ApiError(response);
+}
+
+/** Deletes a user's account; returns how many of their uploads now belong to the nil user */
+export async function deleteUser(user_id: string): Promise<number> {
+  const response = await put<DeleteUserResponse>("/api/v1/admin/users/delete-user", { user_id });
+  if (!response.success) throw new ApiError(response);
+  return response.reassigned_uploads;
 }
@@ -1,4 +1,4 @@
-import { put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
 
 export interface LoginRequest {
   email: string;
@@ -6,10 +6,7 @@ export interface LoginRequest {
   totp?: string;
 }
 
-export interface LoginResponse {
-  success: boolean;
-  email: string;
-}
+export type LoginResponse = ErrorResponse | { success: true; email: string };
 
 export interface RegisterRequest {
   first_names: string;
@@ -18,22 +15,57 @@ export interface RegisterRequest {
   password: string;
 }
 
-export interface RegisterResponse {
-  success: boolean;
-  // email: string;
+export type RegisterResponse = ErrorResponse | { success: true };
+
+/** Thrown when the account has TOTP enabled and the login needs a code from the authenticator */
+export class TotpRequiredError extends Error {
+  constructor() {
+    super("Bitte gib den Code aus deiner Authenticator-App ein");
+  }
 }
 
 export async function login(req: LoginRequest): Promise<string> {
   const response = await put<LoginResponse>("/api/v1/auth/login", req);
-  if (!response.success) throw new Error("Email oder Passwort falsch");
+  if (!response.success) {
+    if (response.code === "totp_required") throw new TotpRequiredError();
+    throw new ApiError(response);
+  }
   return response.email;
 }
 
 export async function register(req: RegisterRequest): Promise<void> {
   const response = await put<RegisterResponse>("/api/v1/auth/register", req);
-  if (!response.success) throw new Error("Registrierung fehlgeschlagen");
+  if (!response.success) throw new ApiError(response);
 }
 
 export async function logout(): Promise<void> {
   await put("/api/v1/auth/logout");
 }
+
+export type ActivationResponse = ErrorResponse | { success: true };
+
+export async function activate(token: string): Promise<void> {
+  const response = await put<ActivationResponse>("/api/v1/auth/activate", { token });
+  if (!response.success) {
+    if (response.code === "token_expired") throw new Error("Der Aktivierungslink ist abgelaufen");
+    if (response.code === "invalid_token") throw new Error("Der Aktivierungslink ist ungültig");
+    throw new ApiError(response);
+  }
+}
+
+export async function resendActivation(email: string): Promise<void> {
+  const response = await put<ActivationResponse>("/api/v1/auth/resend-activation", { email });
+  if (!response.success) throw new ApiError(response);
+}
+
+export type PasswordResetResponse = ErrorResponse | { success: true };
+
+export async function requestPasswordReset(email: string): Promise<void> {
+  const response = await put<PasswordResetResponse>("/api/v1/auth/request-password-reset", { email });
+  if (!response.success) throw new ApiError(response);
+}
+
+export async function resetPassword(token: string, password: string): Promise<void> {
+  const response = await put<PasswordResetResponse>("/api/v1/auth/reset-password", { token, password });
+  if (!response.success) throw new ApiError(response);
+}
@@ -1,4 +1,4 @@
-import { ErrorResponse, put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
 
 export type GetCoursesResponse =
   | ErrorResponse
@@ -26,7 +26,7 @@ export async function getCourses(query = ""): Promise<Course[]> {
       ? "/api/v1/get/courses?query=" + encodeURIComponent(query)
       : "/api/v1/get/courses",
   );
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
 
   // TODO: filter in backend
   return response.courses.filter((course) =>
@@ -36,6 +36,6 @@ export async function getCourses(query = ""): Promise<Course[]> {
 
 export async function createCourse(req: CreateCourseRequest): Promise<Course> {
   const response = await put<CreateCourseResponse>("/api/v1/mod/courses/create", req);
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.course;
 }
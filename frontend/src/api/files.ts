@@ -1,4 +1,4 @@
-import { ErrorResponse, put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
 import { Upload } from "./uploads";
 
 export interface File {
@@ -10,6 +10,8 @@ export interface File {
   upload_id: string;
   approval_uploader: boolean;
   approval_mod: boolean;
+  /** Only set when listing the files of an upload; deleted files are only listed for moderators and earlier buyers */
+  is_deleted?: boolean;
 }
 
 export interface UploadAndFiles {
@@ -17,6 +19,8 @@ export interface UploadAndFiles {
   files: File[];
   total_files_count: number;
   uploader_name?: string;
+  /** Deleted uploads are only shown to moderators and earlier buyers */
+  is_deleted: boolean;
 }
 
 export type FilesResponse =
@@ -26,11 +30,12 @@ export type FilesResponse =
     files: File[];
     upload: Upload;
     total_files_count: number;
+    is_deleted: boolean;
   };
 
 export async function getFiles(uploadId: string): Promise<UploadAndFiles> {
   const response = await put<FilesResponse>("/api/v1/get/files-of-upload", { upload_id: uploadId });
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
 
   response.upload.upload_date = new Date(response.upload.upload_date);
   response.upload.last_modified_date = new Date(response.upload.last_modified_date);
@@ -42,5 +47,12 @@ export async function getFiles(uploadId: string): Promise<UploadAndFiles> {
     upload: response.upload,
     files: response.files,
     total_files_count: response.total_files_count,
+    is_deleted: response.is_deleted,
   }
 }
+
+/** Retracts a single file of one of the current user's uploads */
+export async function deleteFile(file_id: string): Promise<void> {
+  const response = await put<ErrorResponse | { success: true }>("/api/v1/do/delete-file", { file_id });
+  if (!response.success) throw new ApiError(response);
+}
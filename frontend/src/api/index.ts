@@ -9,5 +9,41 @@ export async function put<Response>(path: string, body?: unknown): Promise<Respo
 
 export interface ErrorResponse {
   success: false;
+  /** Stays the same across versions, unlike the (English) message */
+  code: string;
   message: string;
 }
+
+const errorMessages: Record<string, string> = {
+  unauthorized: "Du bist nicht angemeldet",
+  too_many_requests: "Bitte warte ein paar Minuten, bevor du es erneut versuchst",
+  not_implemented: "Das ist noch nicht implementiert",
+  invalid_credentials: "Email oder Passwort falsch",
+  email_not_verified: "Bitte bestätige zuerst deine Email-Adresse über den Link in der Aktivierungsmail",
+  totp_required: "Bitte gib den Code aus deiner Authenticator-App ein",
+  invalid_totp_code: "Der Code ist ungültig",
+  totp_mandatory: "Moderatoren und Admins müssen zuerst die Zwei-Faktor-Authentifizierung aktivieren",
+  login_locked_out: "Zu viele fehlgeschlagene Anmeldeversuche, bitte versuche es in 15 Minuten erneut",
+  account_suspended: "Dein Account wurde gesperrt",
+  invalid_token: "Der Link ist ungültig oder wurde bereits verwendet",
+  token_expired: "Der Link ist abgelaufen",
+  email_invalid: "Die Email-Adresse ist ungültig",
+  email_taken: "Diese Email-Adresse wird bereits verwendet",
+  email_domain_not_allowed: "Bitte registriere dich mit der Email-Adresse deiner Hochschule",
+  file_too_large: "Die Datei ist zu groß",
+  already_purchased: "Du hast diesen Upload bereits gekauft",
+  insufficient_ecs: "Du hast nicht genug ECs",
+  mail_error: "Die Email konnte nicht gesendet werden",
+  database_error: "Ein interner Fehler ist aufgetreten",
+  internal_error: "Ein interner Fehler ist aufgetreten",
+};
+
+/** An error response of the API, with a German message where there is one for its code */
+export class ApiError extends Error {
+  readonly code: string;
+
+  constructor(response: ErrorResponse) {
+    super(errorMessages[response.code] ?? response.message);
+    this.code = response.code;
+  }
+}
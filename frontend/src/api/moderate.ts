@@ -1,5 +1,5 @@
-import { put } from ".";
-import { GetUploadsResponse, Upload } from "./uploads";
+import { ApiError, ErrorResponse, put } from ".";
+import { getAllPagesOfUploads, Upload } from "./uploads";
 import { File } from "./files";
 
 export interface ModifyFileRequest {
@@ -13,26 +13,109 @@ export interface ModifyFileRequest {
     approval_mod?: boolean;
 }
 
+export interface PendingFile extends File {
+    upload_name: string;
+    course_id: string;
+    course_name: string;
+    uploader_id: string;
+    uploader_name?: string;
+}
+
+export type ModerationDecision = "approved" | "rejected";
+
+export type ModerationQueueResponse =
+    | ErrorResponse
+    | {
+        success: true;
+        /** The oldest files awaiting review */
+        files: PendingFile[];
+        /** The number of all files awaiting review */
+        pending_count: number;
+    };
+
+export type ModerateFilesResponse =
+    | ErrorResponse
+    | { success: true; file_ids: string[] };
+
+export async function mod_getModerationQueue(limit?: number): Promise<{ files: PendingFile[]; pending_count: number }> {
+    const response = await put<ModerationQueueResponse>("/api/v1/mod/content/moderation-queue", { limit });
+    if (!response.success) throw new ApiError(response);
+    return { files: response.files, pending_count: response.pending_count };
+}
+
+/** Approves or rejects files at once; the uploaders get an email, which includes the reason (required for rejections) */
+export async function mod_moderateFiles(file_ids: string[], decision: ModerationDecision, reason?: string): Promise<string[]> {
+    const response = await put<ModerateFilesResponse>("/api/v1/mod/content/moderate-files", { file_ids, decision, reason });
+    if (!response.success) throw new ApiError(response);
+    return response.file_ids;
+}
+
 export async function mod_getAllUploads(): Promise<Upload[]> {
-    const response = await put<Upload[]>("/api/v1/mod/content/get-all-uploads");
-    if (!response.success) throw new Error(response);
-    return response.uploads;
+    return getAllPagesOfUploads("/api/v1/mod/content/get-all-uploads");
 }
 
 export async function mod_modifyFile(options: ModifyFileRequest): Promise<Upload> {
     const response = await put<ModifyFileRequest>("/api/v1/mod/content/modify-file", options);
-    if (!response.success) throw new Error(response.message);
+    if (!response.success) throw new ApiError(response);
     return response.upload;
 }
 
 export async function mod_getAllFiles(): Promise<File[][]> {
     const response = await put<Upload[]>("/api/v1/mod/content/get-all-files");
-    if (!response.success) throw new Error(response);
+    if (!response.success) throw new ApiError(response);
     return response.files;
 }
 
 export async function mod_downloadFileAsMod(id: string): Promise<File> {
     const response = await put<File>("/api/v1/mod/content/download-file-as-mod", { id });
-    if (!response.success) throw new Error(response.message);
+    if (!response.success) throw new ApiError(response);
     return response.file;
 }
+
+export interface DeletedUpload extends Upload {
+    deleted_at: string;
+    /** The uploader or moderator who deleted the upload */
+    deleted_by?: string;
+}
+
+export interface DeletedFile extends File {
+    deleted_at: string;
+    /** The uploader or moderator who deleted the file (or its upload) */
+    deleted_by?: string;
+    /** Purged files are gone for good and can't be restored */
+    blob_purged: boolean;
+}
+
+export type DeletedContentResponse =
+    | ErrorResponse
+    | { success: true; uploads: DeletedUpload[]; files: DeletedFile[] };
+
+export type SuccessResponse = ErrorResponse | { success: true };
+
+export async function mod_getDeleted(): Promise<{ uploads: DeletedUpload[]; files: DeletedFile[] }> {
+    const response = await put<DeletedContentResponse>("/api/v1/mod/content/get-deleted", {});
+    if (!response.success) throw new ApiError(response);
+    return { uploads: response.uploads, files: response.files };
+}
+
+export async function mod_deleteUpload(upload_id: string): Promise<void> {
+    const response = await put<SuccessResponse>("/api/v1/mod/content/delete-upload", { upload_id });
+    if (!response.success) throw new ApiError(response);
+}
+
+export async function mod_deleteFile(file_id: string): Promise<void> {
+    const response = await put<SuccessResponse>("/api/v1/mod/content/delete-file", { file_id });
+    if (!response.success) throw new ApiError(response);
+}
+
+/** Restores a deleted upload with the files deleted along with it; returns how many files were restored */
+export async function mod_restoreUpload(upload_id: string): Promise<number> {
+    const response = await put<ErrorResponse | { success: true; restored_files_count: number }>("/api/v1/mod/content/restore-upload", { upload_id });
+    if (!response.success) throw new ApiError(response);
+    return response.restored_files_count;
+}
+
+export async function mod_restoreFile(file_id: string): Promise<void> {
+    const response = await put<SuccessResponse>("/api/v1/mod/content/restore-file", { file_id });
+    if (!response.success) throw new ApiError(response);
+}
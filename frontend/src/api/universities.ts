@@ -1,4 +1,4 @@
-import { ErrorResponse, put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
 
 export interface University {
   id: string;
@@ -6,12 +6,45 @@ export interface University {
   mid_name: string;
   short_name: string;
   email_domain_names: string[];
+  homepage_url: string;
+  cms_url: string;
+  background_color: RgbColor;
+  text_color: RgbColor;
+}
+
+export interface RgbColor {
+  r: number;
+  g: number;
+  b: number;
 }
 
 export type GetUniversitiesResponse = ErrorResponse | { success: true; universities: University[] };
 
 export async function getUniversities(): Promise<University[]> {
   const response = await put<GetUniversitiesResponse>("/api/v1/get/universities");
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.universities;
 }
+
+export type CreateUniversityResponse = ErrorResponse | { success: true; id: string };
+
+export type ModifyUniversityResponse = ErrorResponse | { success: true };
+
+/** Adds a university (admins only); returns its ID */
+export async function createUniversity(university: Omit<University, "id">): Promise<string> {
+  const response = await put<CreateUniversityResponse>("/api/v1/admin/university/create", university);
+  if (!response.success) throw new ApiError(response);
+  return response.id;
+}
+
+/** Replaces all fields of a university (admins only) */
+export async function replaceUniversity(university: University): Promise<void> {
+  const response = await put<ModifyUniversityResponse>("/api/v1/admin/university/replace", university);
+  if (!response.success) throw new ApiError(response);
+}
+
+/** Deletes a university without courses or email addresses (admins only) */
+export async function deleteUniversity(id: string): Promise<void> {
+  const response = await put<ModifyUniversityResponse>("/api/v1/admin/university/delete", { id });
+  if (!response.success) throw new ApiError(response);
+}
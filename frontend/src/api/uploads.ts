@@ -1,4 +1,4 @@
-import { ErrorResponse, put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
 
 export interface Upload {
   id: string;
@@ -13,7 +13,8 @@ export interface Upload {
 }
 
 export type GetUploadResponse = ErrorResponse | { success: true; upload: Upload };
-export type GetUploadsResponse = ErrorResponse | { success: true; uploads: Upload[] };
+/** One page of uploads; `next_cursor` is passed back as `cursor` to get the next one */
+export type GetUploadsResponse = ErrorResponse | { success: true; uploads: Upload[]; next_cursor: string | null };
 
 export interface UploadRequest {
   id?: string;
@@ -30,23 +31,34 @@ export type FileUploadResponse = ErrorResponse | { success: true; message: strin
 
 type PurchaseInfoResponse = ErrorResponse | { success: true; purchase_info_items: PurchaseInfoItem[] };
 
+/** Gets every page of a paginated upload listing, following the cursors */
+export async function getAllPagesOfUploads(path: string, request: object = {}): Promise<Upload[]> {
+  const uploads: Upload[] = [];
+  let cursor: string | undefined;
+
+  do {
+    const response = await put<GetUploadsResponse>(path, { ...request, cursor });
+    if (!response.success) throw new ApiError(response);
+    for (const ul of response.uploads) {
+      ul.upload_date = new Date(ul.upload_date);
+      ul.last_modified_date = new Date(ul.last_modified_date);
+    }
+    uploads.push(...response.uploads);
+    cursor = response.next_cursor ?? undefined;
+  } while (cursor);
+
+  return uploads;
+}
+
 export async function getUploads(courseId: string): Promise<Upload[]> {
-  const response = await put<GetUploadsResponse>("/api/v1/get/uploads", {
-    course_id: courseId,
-  });
-  if (!response.success) throw new Error(response.message);
-  for (const ul of response.uploads) {
-    ul.upload_date = new Date(ul.upload_date);
-    ul.last_modified_date = new Date(ul.last_modified_date);
-  }
-  return response.uploads;
+  return getAllPagesOfUploads("/api/v1/get/uploads", { course_id: courseId });
 }
 
 export async function getUpload(uploadId: string): Promise<Upload> {
   const response = await put<GetUploadResponse>("/api/v1/get/upload", {
     course_id: uploadId,
   });
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   response.upload.upload_date = new Date(response.upload.upload_date);
   response.upload.last_modified_date = new Date(response.upload.last_modified_date);
   return response.upload;
@@ -54,7 +66,7 @@ export async function getUpload(uploadId: string): Promise<Upload> {
 
 export async function upload(options: UploadRequest): Promise<Upload> {
   const response = await put<UploadResponse>("/api/v1/do/upload", options);
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.upload;
 }
 
@@ -69,17 +81,23 @@ export async function uploadFile(uploadId: string, file: Blob): Promise<FileUplo
       body: form,
     })
   ).json()) as FileUploadResponse;
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response;
 }
 
+/** Retracts one of the current user's uploads, together with its files; buyers may keep access */
+export async function deleteUpload(upload_id: string): Promise<void> {
+  const response = await put<ErrorResponse | { success: true }>("/api/v1/do/delete-upload", { upload_id });
+  if (!response.success) throw new ApiError(response);
+}
+
 export interface PurchaseRequest {
   upload_id: string;
 }
 
 export async function purchaseUpload(options: PurchaseRequest): Promise<FileUploadResponse> {
   const response = await put<FileUploadResponse>("/api/v1/do/purchase", options);
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response;
 }
 
@@ -99,6 +117,6 @@ export interface PurchaseInfoItem {
 
 export async function getPurchasedUploads(): Promise<PurchaseInfoItem[]> {
   const response = await put<PurchaseInfoResponse>("/api/v1/get/purchased-uploads");
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.purchase_info_items;
 }
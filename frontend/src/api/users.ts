@@ -1,4 +1,5 @@
-import { ErrorResponse, put } from ".";
+import { ApiError, ErrorResponse, put } from ".";
+import { University } from "./universities";
 
 /**
  * Describes the different levels of authentication
@@ -53,6 +54,8 @@ export type GetMeResponse =
   | {
     success: true;
     user: RedactedUser;
+    /** The university of the user's primary email address */
+    university?: University;
   };
 
 export interface UpdateMeRequest {
@@ -71,19 +74,71 @@ export type UpdateMeResponse =
 
 export async function getMe(): Promise<RedactedUser> {
   const response = await put<GetMeResponse>("/api/v1/get/me");
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.user;
 }
 
 export async function updateMe(options: UpdateMeRequest): Promise<RedactedUser> {
   const response = await put<UpdateMeResponse>("/api/v1/do/me", options);
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.user;
 }
 
 
 export async function getMyEcsBalance(): Promise<number> {
   const response = await put<MyEcsBalanceResponse>("/api/v1/get/my-ecs-balance");
-  if (!response.success) throw new Error(response.message);
+  if (!response.success) throw new ApiError(response);
   return response.ecs_balance;
 }
+
+/** One of the current user's email addresses */
+export interface UserEmail {
+  id: string;
+  address: string;
+  status: "unverified" | "verified" | "disabled";
+  /** Whether the user logs in with this address */
+  is_primary: boolean;
+  of_university?: string;
+}
+
+export type GetEmailsResponse = ErrorResponse | { success: true; emails: UserEmail[] };
+
+export type AddEmailResponse = ErrorResponse | { success: true; email_id: string };
+
+export type ModifyEmailResponse = ErrorResponse | { success: true };
+
+/** Lists the current user's email addresses, the primary one first */
+export async function getMyEmails(): Promise<UserEmail[]> {
+  const response = await put<GetEmailsResponse>("/api/v1/do/emails/list");
+  if (!response.success) throw new ApiError(response);
+  return response.emails;
+}
+
+/** Adds an address, which has to be verified through the mail sent to it; returns its ID */
+export async function addEmail(address: string): Promise<string> {
+  const response = await put<AddEmailResponse>("/api/v1/do/emails/add", { address });
+  if (!response.success) throw new ApiError(response);
+  return response.email_id;
+}
+
+export async function removeEmail(email_id: string): Promise<void> {
+  const response = await put<ModifyEmailResponse>("/api/v1/do/emails/remove", { email_id });
+  if (!response.success) throw new ApiError(response);
+}
+
+/**
+ * Makes a verified address the one the user logs in with; takes the current password,
+ * and a two-factor code if enabled
+ */
+export async function setPrimaryEmail(
+  email_id: string,
+  password: string,
+  totp?: string,
+): Promise<void> {
+  const response = await put<ModifyEmailResponse>("/api/v1/do/emails/set-primary", {
+    email_id,
+    password,
+    totp,
+  });
+  if (!response.success) throw new ApiError(response);
+}
@@ -1,6 +1,7 @@
 import { Link } from "@solidjs/router";
 import { createSignal, onMount, Show } from "solid-js";
 import { createStore } from "solid-js/store";
+import { TotpRequiredError } from "../api/auth";
 import { useAuthContext } from "../context/AuthContext";
 
 interface Props {
@@ -9,7 +10,11 @@ interface Props {
 
 export default function LoginModal(props: Props) {
   const { login } = useAuthContext();
-  const [form, setForm] = createStore({ email: "", password: "" });
+  const [form, setForm] = createStore<{ email: string; password: string; totp?: string }>({
+    email: "",
+    password: "",
+  });
+  const [totpRequired, setTotpRequired] = createSignal(false);
   const [error, setError] = createSignal<string | undefined>();
   const [loading, setLoading] = createSignal(false);
 
@@ -31,6 +36,7 @@ export default function LoginModal(props: Props) {
       await login(form);
       close();
     } catch (error) {
+      if (error instanceof TotpRequiredError) setTotpRequired(true);
       if (error instanceof Error) setError(error.message);
     }
     setLoading(false);
@@ -82,6 +88,21 @@ export default function LoginModal(props: Props) {
               onInput={(e) => setForm("password", e.currentTarget.value)}
               class="input input-bordered w-full max-w-xs"
             />
+            <Show when={totpRequired()}>
+              <label for="login-totp" class="label">
+                <span class="label-text">Code oder Wiederherstellungscode</span>
+              </label>
+              <input
+                type="text"
+                id="login-totp"
+                autocomplete="one-time-code"
+                autofocus
+                required
+                disabled={loading()}
+                onInput={(e) => setForm("totp", e.currentTarget.value)}
+                class="input input-bordered w-full max-w-xs"
+              />
+            </Show>
             <label class="label">
               <Link href="/forgot-password" onClick={close} class="label-text-alt link">
                 Passwort vergessen?
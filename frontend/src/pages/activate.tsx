@@ -0,0 +1,71 @@
+import { useSearchParams } from "@solidjs/router";
+import { Match, Show, Switch, createResource, createSignal } from "solid-js";
+import { activate, resendActivation } from "../api/auth";
+import { useAuthContext } from "../context/AuthContext";
+
+export default function Activate() {
+  const { setLoginModal } = useAuthContext();
+  const [searchParams] = useSearchParams<{ token: string }>();
+  const [activation] = createResource(() => searchParams.token, activate);
+  const [email, setEmail] = createSignal("");
+  const [resendMessage, setResendMessage] = createSignal<string | undefined>();
+  const [loading, setLoading] = createSignal(false);
+
+  async function resend(event: SubmitEvent) {
+    event.preventDefault();
+    setLoading(true);
+    try {
+      await resendActivation(email());
+      setResendMessage("Falls ein nicht aktivierter Account existiert, haben wir dir eine neue Mail gesendet.");
+    } catch (error) {
+      if (error instanceof Error) setResendMessage(error.message);
+    }
+    setLoading(false);
+  }
+
+  return (
+    <div class="flex flex-col items-center">
+      <h3 class="font-bold text-lg text-center mb-6">Account aktivieren</h3>
+
+      <Switch>
+        <Match when={activation.loading}>
+          <span class="loading loading-spinner" />
+        </Match>
+        <Match when={activation.state === "ready"}>
+          <p class="mb-4">Deine Email-Adresse wurde bestätigt.</p>
+          <button class="btn btn-primary" onClick={() => setLoginModal(true)}>
+            Anmelden
+          </button>
+        </Match>
+        <Match when={!searchParams.token || activation.error}>
+          <Show when={activation.error instanceof Error}>
+            <span class="text-error mb-4">{(activation.error as Error).message}</span>
+          </Show>
+
+          <form onSubmit={resend} class="form-control w-full max-w-xs">
+            <label for="activate-email" class="label">
+              <span class="label-text">Neuen Aktivierungslink an diese Email senden</span>
+            </label>
+            <input
+              type="email"
+              id="activate-email"
+              autocomplete="email"
+              required
+              disabled={loading()}
+              onInput={(e) => setEmail(e.currentTarget.value)}
+              class="input input-bordered w-full max-w-xs"
+            />
+
+            <Show when={resendMessage()}>
+              <span class="mt-2">{resendMessage()}</span>
+            </Show>
+
+            <button type="submit" class="btn btn-primary mt-8" disabled={loading()}>
+              Senden
+            </button>
+          </form>
+        </Match>
+      </Switch>
+    </div>
+  );
+}
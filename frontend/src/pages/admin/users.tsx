@@ -1,19 +1,50 @@
-import { useRouteData } from "@solidjs/router";
 import { createResource, createSignal, For, Match, Show, Switch } from "solid-js";
-import { AscendingIcon, DescendingIcon } from "../../icons/Sorting";
-import { createSystemTransaction, getAllUsers } from "../../api/admin";
-import { authLevelToString, RedactedUser } from "../../api/users";
+import {
+    AdminUser,
+    createSystemTransaction,
+    deleteUser,
+    logoutUser,
+    searchUsers,
+    setUserRole,
+    suspendUser,
+    unsuspendUser,
+} from "../../api/admin";
+import { AuthLevel, authLevelToString } from "../../api/users";
 
 /**
  * @file This page displays and manages all users in the system. It is only accessible to administrators.
  */
 export default function Users() {
-    const [users] = createResource(getAllUsers);
+    const [query, setQuery] = createSignal("");
+    const [includeDeleted, setIncludeDeleted] = createSignal(false);
+    const [users, { refetch }] = createResource(
+        () => ({ query: query(), includeDeleted: includeDeleted() }),
+        ({ query, includeDeleted }) => searchUsers(query, includeDeleted),
+    );
 
     return (
         <>
             <h1 class="text-lg">Users</h1>
 
+            <div class="flex flex-row gap-4 items-center">
+                <input
+                    class="input input-bordered"
+                    type="search"
+                    placeholder="Search by email, name, or nick"
+                    value={query()}
+                    onChange={(e) => setQuery(e.currentTarget.value)}
+                />
+                <label class="label cursor-pointer gap-2">
+                    <input
+                        class="checkbox"
+                        type="checkbox"
+                        checked={includeDeleted()}
+                        onChange={(e) => setIncludeDeleted(e.currentTarget.checked)}
+                    />
+                    <span class="label-text">Include deleted accounts</span>
+                </label>
+            </div>
+
             <Show when={users.loading}>
                 <div class="skeleton"></div>
             </Show>
@@ -24,7 +55,7 @@ export default function Users() {
                 </Match>
                 <Match when={users()}>
                     <For each={users()!!}>
-                        {user => <UserCard user={user} />}
+                        {user => <UserCard user={user} onChange={refetch} />}
                     </For>
                 </Match>
             </Switch>
@@ -32,7 +63,7 @@ export default function Users() {
     );
 }
 
-function UserCard({ user }: { user: RedactedUser }) {
+function UserCard({ user, onChange }: { user: AdminUser; onChange: () => void }) {
     const [ecInput, setEcInput] = createSignal("");
     const [reasonInput, setReasonInput] = createSignal("");
 
@@ -52,37 +83,100 @@ function UserCard({ user }: { user: RedactedUser }) {
         }
     };
 
+    /** Runs an action on this user, then reloads the list */
+    const act = async (action: () => Promise<unknown>) => {
+        try {
+            await action();
+            onChange();
+        } catch (error: any) {
+            alert(`Failed: ${error.message}`);
+        }
+    };
+
+    const handleSuspend = () => {
+        const reason = prompt("Reason for the suspension (shown to the user):")?.trim();
+        if (!reason) return;
+
+        const hours = prompt("Duration in hours (leave empty to suspend indefinitely):")?.trim();
+        act(() => suspendUser(user.id, reason, hours ? parseInt(hours, 10) : undefined));
+    };
+
+    const handleDelete = () => {
+        if (!confirm(`Delete the account of ${user.primary_email}? Their uploads will be kept, but belong to nobody.`)) return;
+        act(() => deleteUser(user.id));
+    };
+
     return (
         <div class="card shadow-md">
             <div class="card-body">
                 <div class="grid grid-cols-[auto,auto]">
                     <span>User ID</span>
                     <span>{user.id}</span>
+                    <span>Email</span>
+                    <span>{user.primary_email}</span>
                     <span>First Names</span>
                     <span>{user.first_names}</span>
                     <span>Last Name</span>
                     <span>{user.last_name}</span>
+                    <span>Nick</span>
+                    <span>{user.nick}</span>
                     <span>2FA Enabled</span>
                     <span>{user.totp_enabled ? "Yes" : "No"}</span>
                     <span>User Role</span>
-                    <span>{authLevelToString(user.user_role)} (auth level {user.user_role})</span>
-
-                    <span>Add/remove ECs</span>
-                    <div class="flex flex-row gap-2 items-baseline">
-                        <div class="form-control">
-                            <label class="label" for={ecInputId}>
-                                <span class="label-text">EC Input</span>
-                            </label>
-                            <input class="input input-bordered" type="number" id={ecInputId} value={ecInput()} onInput={(e) => setEcInput(e.currentTarget.value)} />
+                    <select
+                        class="select select-bordered select-sm"
+                        disabled={!!user.deleted_at}
+                        onChange={(e) => act(() => setUserRole(user.id, parseInt(e.currentTarget.value, 10)))}
+                    >
+                        <For each={[AuthLevel.REGULAR_USER, AuthLevel.MODERATOR, AuthLevel.ADMIN]}>
+                            {level => (
+                                <option value={level} selected={level === user.user_role}>
+                                    {authLevelToString(level)} (auth level {level})
+                                </option>
+                            )}
+                        </For>
+                    </select>
+                    <span>Suspended</span>
+                    <span>
+                        {user.suspended
+                            ? `Yes, ${user.suspended_until ? `until ${new Date(user.suspended_until).toLocaleString()}` : "indefinitely"}: ${user.suspension_reason}`
+                            : "No"}
+                    </span>
+                    <Show when={user.deleted_at}>
+                        <span>Deleted</span>
+                        <span>{new Date(user.deleted_at!).toLocaleString()}</span>
+                    </Show>
+
+                    <Show when={!user.deleted_at}>
+                        <span>Add/remove ECs</span>
+                        <div class="flex flex-row gap-2 items-baseline">
+                            <div class="form-control">
+                                <label class="label" for={ecInputId}>
+                                    <span class="label-text">EC Input</span>
+                                </label>
+                                <input class="input input-bordered" type="number" id={ecInputId} value={ecInput()} onInput={(e) => setEcInput(e.currentTarget.value)} />
+                            </div>
+                            <div class="form-control">
+                                <label class="label" for={reasonInputId}>
+                                    <span class="label-text">Reason</span>
+                                </label>
+                                <input class="input input-bordered" type="text" id={reasonInputId} value={reasonInput()} onInput={(e) => setReasonInput(e.currentTarget.value)} />
+                            </div>
+                            <button class="btn" onClick={handleCreateTransaction}>Create system transaction</button>
                         </div>
-                        <div class="form-control">
-                            <label class="label" for={reasonInputId}>
-                                <span class="label-text">Reason</span>
-                            </label>
-                            <input class="input input-bordered" type="text" id={reasonInputId} value={reasonInput()} onInput={(e) => setReasonInput(e.currentTarget.value)} />
+
+                        <span>Account</span>
+                        <div class="flex flex-row gap-2">
+                            <Show
+                                when={user.suspended}
+                                fallback={<button class="btn btn-sm" onClick={handleSuspend}>Suspend</button>}
+                            >
+                                <button class="btn btn-sm" onClick={() => act(() => unsuspendUser(user.id))}>Lift suspension</button>
+                            </Show>
+                            <button class="btn btn-sm" onClick={() => act(() => logoutUser(user.id))}>Log out everywhere</button>
+                            <button class="btn btn-sm btn-error" onClick={handleDelete}>Delete account</button>
                         </div>
-                        <button class="btn" onClick={handleCreateTransaction}>Create system transaction</button>
-                    </div>
+                    </Show>
                 </div>
 
             </div>
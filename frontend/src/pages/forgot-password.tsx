@@ -0,0 +1,61 @@
+import { Show, createSignal } from "solid-js";
+import { requestPasswordReset } from "../api/auth";
+
+export default function ForgotPassword() {
+  const [email, setEmail] = createSignal("");
+  const [sent, setSent] = createSignal(false);
+  const [error, setError] = createSignal<string | undefined>();
+  const [loading, setLoading] = createSignal(false);
+
+  async function submit(event: SubmitEvent) {
+    event.preventDefault();
+    setLoading(true);
+    try {
+      await requestPasswordReset(email());
+      setSent(true);
+    } catch (error) {
+      if (error instanceof Error) setError(error.message);
+    }
+    setLoading(false);
+  }
+
+  return (
+    <form onSubmit={submit} class="flex flex-col items-center">
+      <h3 class="font-bold text-lg text-center mb-6">Passwort vergessen</h3>
+
+      <Show when={sent()}>
+        <div class="w-full max-w-xs">
+          <span>Falls ein Account mit dieser Email existiert, haben wir dir einen Link zum Zurücksetzen gesendet.</span>
+          <div class="divider" />
+        </div>
+      </Show>
+
+      <Show when={error()}>
+        <div class="w-full max-w-xs">
+          <span class="text-error">{error()}</span>
+          <div class="divider" />
+        </div>
+      </Show>
+
+      <div class="form-control w-full max-w-xs">
+        <label for="forgot-password-email" class="label">
+          <span class="label-text">Email</span>
+        </label>
+        <input
+          type="email"
+          id="forgot-password-email"
+          autocomplete="email"
+          autofocus
+          required
+          disabled={loading()}
+          onInput={(e) => setEmail(e.currentTarget.value)}
+          class="input input-bordered w-full max-w-xs"
+        />
+
+        <button type="submit" class="btn btn-primary mt-8" disabled={loading()}>
+          Link senden
+        </button>
+      </div>
+    </form>
+  );
+}
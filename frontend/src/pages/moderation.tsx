@@ -1,19 +1,30 @@
-import { Link, useRouteData } from "@solidjs/router";
-import { For, Show, Suspense, createResource } from "solid-js";
-import { Course } from "../api/courses";
-import { getUniversities } from "../api/universities";
+import { For, Show, createResource } from "solid-js";
+import { ApiError } from "../api";
 import { AuthLevel } from "../api/users";
 import { useAuthContext } from "../context/AuthContext";
-import { CoursesDataType } from "./courses.data";
-import { getUploads } from "../api/uploads";
-import { mod_getAllFiles, mod_getAllUploads } from "../api/moderate";
-import UploadCard from "../components/UploadCard";
+import { ModerationDecision, mod_getModerationQueue, mod_moderateFiles } from "../api/moderate";
 import Mod_FileCard from "../components/ModFileCard";
 
 export default function Moderation() {
   const { hasRole } = useAuthContext();
-  // const [uploads] = createResource(mod_getAllUploads);
-  const [files] = createResource(mod_getAllFiles);
+  const [queue, { refetch }] = createResource(() => mod_getModerationQueue());
+
+  const decide = async (fileIds: string[], decision: ModerationDecision) => {
+    let reason: string | undefined;
+    if (decision === "rejected") {
+      reason = prompt("Begründung für die Ablehnung (wird per E-Mail mitgeteilt):")?.trim();
+      if (!reason) return;
+    }
+
+    try {
+      await mod_moderateFiles(fileIds, decision, reason);
+    } catch (error) {
+      alert(error instanceof ApiError ? error.message : "Die Entscheidung konnte nicht gespeichert werden.");
+    }
+    refetch();
+  };
+
+  const allIds = () => queue()?.files.map((file) => file.id) ?? [];
 
   return (
     <div class="flex flex-col gap-6 items-center">
@@ -23,18 +34,36 @@ export default function Moderation() {
         </p>
       }>
         <Show
-          when={(files()?.length ?? 0) > 0}
+          when={(queue()?.files.length ?? 0) > 0}
           fallback={
             <div class="card card-compact card-side bg-base-200 shadow-md">
               <div class="card-body">
                 <div class="text-center">
                   {/* HACK this looks appalling, improve font/layout */}
-                  <h2 class="text-3xl font-bold">Keine files gefunden</h2>
+                  <h2 class="text-3xl font-bold">Keine Dateien zu prüfen</h2>
                 </div>
               </div>
             </div>
           }>
-          <For each={files()}>{(file) => <Mod_FileCard {...(file[0])} />}</For>
+          <div class="flex flex-wrap items-center gap-2">
+            <span>
+              {queue()?.pending_count} Dateien warten auf Prüfung, die ältesten {queue()?.files.length} werden angezeigt
+            </span>
+            <button class="btn btn-sm btn-primary" onClick={() => decide(allIds(), "approved")}>Alle freigeben</button>
+            <button class="btn btn-sm btn-error" onClick={() => decide(allIds(), "rejected")}>Alle ablehnen</button>
+          </div>
+          <For each={queue()?.files}>{(file) => (
+            <div class="flex flex-col gap-2">
+              <p>
+                <b>{file.upload_name}</b> in {file.course_name}, hochgeladen von {file.uploader_name ?? "Unbekannt"}
+              </p>
+              <Mod_FileCard {...file} />
+              <div class="flex gap-2">
+                <button class="btn btn-sm btn-primary" onClick={() => decide([file.id], "approved")}>Freigeben</button>
+                <button class="btn btn-sm btn-error" onClick={() => decide([file.id], "rejected")}>Ablehnen</button>
+              </div>
+            </div>
+          )}</For>
         </Show>
       </Show>
     </div>
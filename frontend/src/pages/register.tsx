@@ -12,12 +12,14 @@ export default function Register() {
   });
   const [error, setError] = createSignal<string | undefined>();
   const [loading, setLoading] = createSignal(false);
+  const [registered, setRegistered] = createSignal(false);
 
   async function submit(event: SubmitEvent) {
     event.preventDefault();
     setLoading(true);
     try {
       await register(form);
+      setRegistered(true);
     } catch (error) {
       if (error instanceof Error) setError(error.message);
     }
@@ -28,6 +30,13 @@ export default function Register() {
     <form onSubmit={submit} class="flex flex-col items-center">
       <h3 class="font-bold text-lg text-center mb-6">Bei Egiraffe Registrieren</h3>
 
+      <Show when={registered()}>
+        <div class="w-full max-w-xs">
+          <span>Wir haben dir eine Mail mit einem Link zum Aktivieren deines Accounts gesendet.</span>
+          <div class="divider" />
+        </div>
+      </Show>
+
       <Show when={error()}>
         <div class="w-full max-w-xs">
           <span class="text-error">{error()}</span>
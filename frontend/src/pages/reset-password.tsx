@@ -0,0 +1,74 @@
+import { Link, useSearchParams } from "@solidjs/router";
+import { Show, createSignal } from "solid-js";
+import { resetPassword } from "../api/auth";
+import { useAuthContext } from "../context/AuthContext";
+
+export default function ResetPassword() {
+  const { setLoginModal } = useAuthContext();
+  const [searchParams] = useSearchParams<{ token: string }>();
+  const [password, setPassword] = createSignal("");
+  const [done, setDone] = createSignal(false);
+  const [error, setError] = createSignal<string | undefined>();
+  const [loading, setLoading] = createSignal(false);
+
+  async function submit(event: SubmitEvent) {
+    event.preventDefault();
+    setLoading(true);
+    try {
+      await resetPassword(searchParams.token ?? "", password());
+      setDone(true);
+    } catch (error) {
+      if (error instanceof Error) setError(error.message);
+    }
+    setLoading(false);
+  }
+
+  return (
+    <Show
+      when={!done()}
+      fallback={
+        <div class="flex flex-col items-center">
+          <p class="mb-4">Dein Passwort wurde geändert. Alle bestehenden Anmeldungen wurden beendet.</p>
+          <button class="btn btn-primary" onClick={() => setLoginModal(true)}>
+            Anmelden
+          </button>
+        </div>
+      }
+    >
+      <form onSubmit={submit} class="flex flex-col items-center">
+        <h3 class="font-bold text-lg text-center mb-6">Neues Passwort festlegen</h3>
+
+        <Show when={error()}>
+          <div class="w-full max-w-xs">
+            <span class="text-error">{error()}</span>
+            <Link href="/forgot-password" class="link block">
+              Neuen Link anfordern
+            </Link>
+            <div class="divider" />
+          </div>
+        </Show>
+
+        <div class="form-control w-full max-w-xs">
+          <label for="reset-password-password" class="label">
+            <span class="label-text">Neues Passwort</span>
+          </label>
+          <input
+            type="password"
+            id="reset-password-password"
+            autocomplete="new-password"
+            minlength="8"
+            autofocus
+            required
+            disabled={loading()}
+            onInput={(e) => setPassword(e.currentTarget.value)}
+            class="input input-bordered w-full max-w-xs"
+          />
+
+          <button type="submit" class="btn btn-primary mt-8" disabled={loading()}>
+            Passwort ändern
+          </button>
+        </div>
+      </form>
+    </Show>
+  );
+}
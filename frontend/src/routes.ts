@@ -15,6 +15,18 @@ export const routes: RouteDefinition[] = [
     path: "/register",
     component: lazy(() => import("./pages/register")),
   },
+  {
+    path: "/activate",
+    component: lazy(() => import("./pages/activate")),
+  },
+  {
+    path: "/forgot-password",
+    component: lazy(() => import("./pages/forgot-password")),
+  },
+  {
+    path: "/reset-password",
+    component: lazy(() => import("./pages/reset-password")),
+  },
   {
     path: "/courses",
     component: lazy(() => import("./pages/courses")),